@@ -0,0 +1,51 @@
+#!/bin/bash
+set -e
+
+# Generate the certificates the TLS tests use
+# Usage: ./scripts/generate-test-certs.sh
+#
+# Writes a CA, a server certificate for localhost and 127.0.0.1, a client
+# certificate signed by the CA and a self-signed client certificate the CA
+# doesn't know, all valid for 100 years, to tests/fixtures/web.
+
+DIR="$(cd "$(dirname "$0")/.." && pwd)/tests/fixtures/web"
+DAYS=36500
+mkdir -p "$DIR"
+cd "$DIR"
+
+key() {
+    openssl ecparam -name prime256v1 -genkey -noout | openssl pkcs8 -topk8 -nocrypt -out "$1"
+}
+
+key ca.key
+openssl req -x509 -new -key ca.key -days "$DAYS" -subj "/CN=weather-exporter test CA" \
+    -addext "basicConstraints=critical,CA:TRUE" \
+    -addext "keyUsage=critical,keyCertSign,cRLSign" \
+    -out ca.crt
+
+# sign NAME SUBJECT EXTENSIONS
+sign() {
+    key "$1.key"
+    openssl req -new -key "$1.key" -subj "$2" -out "$1.csr"
+    openssl x509 -req -in "$1.csr" -CA ca.crt -CAkey ca.key -CAcreateserial -days "$DAYS" \
+        -extfile <(printf '%s\n' "$3") -out "$1.crt"
+    rm "$1.csr"
+}
+
+sign server "/CN=localhost" "basicConstraints=CA:FALSE
+keyUsage=critical,digitalSignature
+extendedKeyUsage=serverAuth
+subjectAltName=DNS:localhost,IP:127.0.0.1"
+
+sign client "/CN=prometheus" "basicConstraints=CA:FALSE
+keyUsage=critical,digitalSignature
+extendedKeyUsage=clientAuth"
+
+key untrusted.key
+openssl req -x509 -new -key untrusted.key -days "$DAYS" -subj "/CN=untrusted" \
+    -addext "basicConstraints=CA:FALSE" \
+    -addext "extendedKeyUsage=clientAuth" \
+    -out untrusted.crt
+
+rm -f ca.srl ca.key
+echo "Wrote test certificates to $DIR"
@@ -0,0 +1,302 @@
+use crate::metrics::{FORECAST_ERROR, FORECAST_MAE, location_labels};
+use crate::provider::WeatherResponse;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+// Station readings this close to a forecast hour are used instead of the analysis
+const OBSERVATION_TOLERANCE_MINUTES: i64 = 30;
+
+// Forecasts that still can't be scored this long after their hour are dropped
+const SCORING_GRACE_HOURS: i64 = 3;
+
+/// Which lead times to track and how far back the rolling MAE reaches
+#[derive(Debug, Clone)]
+pub struct AccuracySettings {
+    pub lead_hours: Vec<u32>,
+    pub window: Duration,
+}
+
+/// Remembers what was forecast for each upcoming hour and scores it once
+/// that hour has passed.
+///
+/// The lead time is measured from when the exporter saw the forecast, not
+/// from the model run. Of the forecasts that round to a tracked lead time,
+/// the one seen closest to it is kept.
+#[derive(Clone)]
+pub struct ForecastTracker {
+    settings: Arc<AccuracySettings>,
+    /// Forecast temperature by target hour and lead time, with how many hours
+    /// the lead time it was seen at was off
+    pending: BTreeMap<DateTime<Utc>, HashMap<u32, (f64, f64)>>,
+    /// Absolute errors per lead time, oldest first
+    errors: HashMap<u32, VecDeque<(DateTime<Utc>, f64)>>,
+    /// Latest station reading, if any
+    observation: Option<(DateTime<Utc>, f64)>,
+}
+
+impl ForecastTracker {
+    pub fn new(settings: Arc<AccuracySettings>) -> Self {
+        Self {
+            settings,
+            pending: BTreeMap::new(),
+            errors: HashMap::new(),
+            observation: None,
+        }
+    }
+
+    /// Record a temperature reported by a local weather station
+    pub fn observe(&mut self, time: DateTime<Utc>, temperature: f64) {
+        self.observation = Some((time, temperature));
+    }
+
+    /// Record new forecasts from `weather` and score any that have come due
+    pub fn update(&mut self, location_name: &str, weather: &WeatherResponse, now: DateTime<Utc>) {
+        if self.settings.lead_hours.is_empty() {
+            return;
+        }
+
+        self.evaluate(location_name, weather, now);
+
+        for entry in &weather.properties.timeseries {
+            let Some(temp) = entry.data.instant.details.air_temperature else {
+                continue;
+            };
+            if entry.time <= now {
+                continue;
+            }
+
+            let hours = (entry.time - now).num_minutes() as f64 / 60.0;
+            let lead = hours.round() as u32;
+            if !self.settings.lead_hours.contains(&lead) {
+                continue;
+            }
+            // Ties go to the newer forecast
+            let offset = (hours - lead as f64).abs();
+            let forecasts = self.pending.entry(entry.time).or_default();
+            if forecasts.get(&lead).is_none_or(|(_, seen)| offset <= *seen) {
+                forecasts.insert(lead, (temp, offset));
+            }
+        }
+    }
+
+    fn evaluate(&mut self, location_name: &str, weather: &WeatherResponse, now: DateTime<Utc>) {
+        let due: Vec<DateTime<Utc>> = self.pending.range(..=now).map(|(t, _)| *t).collect();
+
+        for target in due {
+            let Some(actual) = self.actual_temperature(weather, target) else {
+                // A station reading may still come in
+                if target >= now - Duration::hours(SCORING_GRACE_HOURS) {
+                    debug!(
+                        "No observation for {} at {} yet, scoring it later",
+                        location_name, target
+                    );
+                    continue;
+                }
+                let forecasts = self.pending.remove(&target).unwrap_or_default();
+                warn!(
+                    "No observation or analysis for {} at {}, dropping {} forecasts. \
+                     Forecasts are only scored if the location is updated at least hourly.",
+                    location_name,
+                    target,
+                    forecasts.len()
+                );
+                continue;
+            };
+
+            let forecasts = self.pending.remove(&target).unwrap_or_default();
+            for (lead, (predicted, _)) in forecasts {
+                let error = predicted - actual;
+                let lead_label = lead.to_string();
+                FORECAST_ERROR
+                    .with_label_values(&location_labels(location_name, &[&lead_label]))
+                    .set(error);
+
+                let errors = self.errors.entry(lead).or_default();
+                errors.push_back((target, error.abs()));
+                while errors
+                    .front()
+                    .is_some_and(|(time, _)| *time < now - self.settings.window)
+                {
+                    errors.pop_front();
+                }
+
+                let mae = errors.iter().map(|(_, e)| e).sum::<f64>() / errors.len() as f64;
+                FORECAST_MAE
+                    .with_label_values(&location_labels(location_name, &[&lead_label]))
+                    .set(mae);
+            }
+        }
+    }
+
+    /// A station reading near `target` if there is one, otherwise the
+    /// analysis value for `target` from the latest forecast.
+    fn actual_temperature(&self, weather: &WeatherResponse, target: DateTime<Utc>) -> Option<f64> {
+        if let Some((time, temp)) = self.observation
+            && (time - target).num_minutes().abs() <= OBSERVATION_TOLERANCE_MINUTES
+        {
+            return Some(temp);
+        }
+
+        weather
+            .properties
+            .timeseries
+            .iter()
+            .find(|entry| entry.time == target)
+            .and_then(|entry| entry.data.instant.details.air_temperature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    fn tracker(lead_hours: &[u32], window_hours: i64) -> ForecastTracker {
+        ForecastTracker::new(Arc::new(AccuracySettings {
+            lead_hours: lead_hours.to_vec(),
+            window: Duration::hours(window_hours),
+        }))
+    }
+
+    fn forecast(entries: &[(&str, f64)]) -> WeatherResponse {
+        let timeseries: Vec<_> = entries
+            .iter()
+            .map(|(time, temp)| {
+                json!({
+                    "time": utc(time),
+                    "data": { "instant": { "details": { "air_temperature": temp } } }
+                })
+            })
+            .collect();
+        serde_json::from_value(json!({ "properties": { "timeseries": timeseries } })).unwrap()
+    }
+
+    fn pending(tracker: &ForecastTracker, target: &str, lead: u32) -> Option<f64> {
+        tracker
+            .pending
+            .get(&utc(target))
+            .and_then(|forecasts| forecasts.get(&lead))
+            .map(|(temp, _)| *temp)
+    }
+
+    fn gauge(vec: &prometheus::GaugeVec, location_name: &str, lead: &str) -> f64 {
+        vec.with_label_values(&location_labels(location_name, &[lead]))
+            .get()
+    }
+
+    #[test]
+    fn keeps_the_forecast_seen_closest_to_the_lead_time() {
+        let mut tracker = tracker(&[24], 24);
+        let target = "2026-01-16T00:00:00Z";
+
+        // 24.4, 23.9, 23.6 and 23.2 hours ahead
+        tracker.update(
+            "AccuracyLead",
+            &forecast(&[(target, 10.0)]),
+            utc("2026-01-14T23:36:00Z"),
+        );
+        assert_eq!(pending(&tracker, target, 24), Some(10.0));
+        tracker.update(
+            "AccuracyLead",
+            &forecast(&[(target, 11.0)]),
+            utc("2026-01-15T00:06:00Z"),
+        );
+        assert_eq!(pending(&tracker, target, 24), Some(11.0));
+        tracker.update(
+            "AccuracyLead",
+            &forecast(&[(target, 12.0)]),
+            utc("2026-01-15T00:24:00Z"),
+        );
+        assert_eq!(pending(&tracker, target, 24), Some(11.0));
+        tracker.update(
+            "AccuracyLead",
+            &forecast(&[(target, 13.0)]),
+            utc("2026-01-15T00:48:00Z"),
+        );
+        assert_eq!(pending(&tracker, target, 24), Some(11.0));
+        assert_eq!(pending(&tracker, target, 23), None);
+
+        // Equally far off, the newer one wins
+        let target = "2026-01-16T06:00:00Z";
+        tracker.update(
+            "AccuracyLead",
+            &forecast(&[(target, 1.0)]),
+            utc("2026-01-15T05:36:00Z"),
+        );
+        tracker.update(
+            "AccuracyLead",
+            &forecast(&[(target, 2.0)]),
+            utc("2026-01-15T06:24:00Z"),
+        );
+        assert_eq!(pending(&tracker, target, 24), Some(2.0));
+    }
+
+    #[test]
+    fn mean_absolute_error_covers_the_window() {
+        let mut tracker = tracker(&[1], 2);
+        let mut update = |now: &str, entries: &[(&str, f64)]| {
+            tracker.update("AccuracyWindow", &forecast(entries), utc(now));
+            (
+                gauge(&FORECAST_ERROR, "AccuracyWindow", "1"),
+                gauge(&FORECAST_MAE, "AccuracyWindow", "1"),
+            )
+        };
+
+        update("2026-01-15T09:00:00Z", &[("2026-01-15T10:00:00Z", 5.0)]);
+        // Analysis values compared to what was forecast an hour earlier
+        let (error, mae) = update(
+            "2026-01-15T10:00:00Z",
+            &[("2026-01-15T10:00:00Z", 3.0), ("2026-01-15T11:00:00Z", 7.0)],
+        );
+        assert_eq!((error, mae), (2.0, 2.0));
+        let (error, mae) = update(
+            "2026-01-15T11:00:00Z",
+            &[("2026-01-15T11:00:00Z", 7.0), ("2026-01-15T12:00:00Z", 2.0)],
+        );
+        assert_eq!((error, mae), (0.0, 1.0));
+        let (error, mae) = update(
+            "2026-01-15T12:00:00Z",
+            &[("2026-01-15T12:00:00Z", 6.0), ("2026-01-15T13:00:00Z", 9.0)],
+        );
+        assert_eq!((error, mae), (-4.0, 2.0));
+        // 10:00 is now more than two hours ago
+        let (error, mae) = update("2026-01-15T13:00:00Z", &[("2026-01-15T13:00:00Z", 8.0)]);
+        assert_eq!((error, mae), (1.0, 5.0 / 3.0));
+    }
+
+    #[test]
+    fn waits_for_a_station_reading_when_the_analysis_is_gone() {
+        let mut tracker = tracker(&[1], 24);
+        tracker.update(
+            "AccuracyMissing",
+            &forecast(&[("2026-01-15T10:00:00Z", 5.0), ("2026-01-15T11:00:00Z", 6.0)]),
+            utc("2026-01-15T09:00:00Z"),
+        );
+
+        // The next forecast starts after 10:00, so nothing can be scored yet
+        let later = forecast(&[("2026-01-15T11:00:00Z", 6.0)]);
+        tracker.update("AccuracyMissing", &later, utc("2026-01-15T10:30:00Z"));
+        assert_eq!(pending(&tracker, "2026-01-15T10:00:00Z", 1), Some(5.0));
+
+        tracker.observe(utc("2026-01-15T10:10:00Z"), 4.0);
+        tracker.update("AccuracyMissing", &later, utc("2026-01-15T10:40:00Z"));
+        assert_eq!(pending(&tracker, "2026-01-15T10:00:00Z", 1), None);
+        assert_eq!(gauge(&FORECAST_ERROR, "AccuracyMissing", "1"), 1.0);
+        assert_eq!(gauge(&FORECAST_MAE, "AccuracyMissing", "1"), 1.0);
+
+        // 11:00 never gets an observation and is dropped after a while,
+        // without touching the scores
+        let gone = forecast(&[("2026-01-15T15:00:00Z", 6.0)]);
+        tracker.update("AccuracyMissing", &gone, utc("2026-01-15T13:30:00Z"));
+        assert_eq!(pending(&tracker, "2026-01-15T11:00:00Z", 1), Some(6.0));
+        tracker.update("AccuracyMissing", &gone, utc("2026-01-15T14:30:00Z"));
+        assert_eq!(pending(&tracker, "2026-01-15T11:00:00Z", 1), None);
+        assert_eq!(gauge(&FORECAST_MAE, "AccuracyMissing", "1"), 1.0);
+    }
+}
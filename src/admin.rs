@@ -0,0 +1,460 @@
+//! Authenticated endpoints for adding, removing and re-geocoding locations
+//! while the exporter runs.
+
+use crate::auth::constant_time_eq;
+use crate::config::{ConfigFile, LocationConfig, LocationEntry};
+use crate::metrics;
+use crate::provider::ProviderKind;
+use crate::{AppState, LocationSpec};
+use anyhow::{Context, Result};
+use axum::{
+    Json, Router,
+    extract::{Path, Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, post},
+};
+use serde::Serialize;
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+pub struct AdminSettings {
+    /// Token every request has to carry in `X-Admin-Token`
+    pub token: String,
+    /// Config file that changes are written back to
+    pub config_file: Option<PathBuf>,
+    /// Provider for added locations that don't name one
+    pub default_provider: ProviderKind,
+}
+
+struct Admin {
+    state: AppState,
+    settings: AdminSettings,
+    /// Serializes changes, so the config file is edited in request order
+    changes: Mutex<()>,
+    /// Whether the config file as it was before the first change has been
+    /// kept, saving its comments and formatting
+    backed_up: AtomicBool,
+}
+
+/// Outcome of looking up and fetching a location
+#[derive(Serialize)]
+struct LocationResult {
+    name: String,
+    /// The place the name resolved to, `null` if the lookup failed
+    place: Option<String>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    /// Why the last update failed, `null` if it succeeded
+    error: Option<String>,
+}
+
+pub fn router(state: AppState, settings: AdminSettings) -> Router {
+    let admin = Arc::new(Admin {
+        state,
+        settings,
+        changes: Mutex::new(()),
+        backed_up: AtomicBool::new(false),
+    });
+
+    Router::new()
+        .route("/admin/locations", post(add_handler))
+        .route("/admin/locations/{name}", delete(remove_handler))
+        .route("/admin/locations/{name}/refresh", post(refresh_handler))
+        .route_layer(middleware::from_fn_with_state(admin.clone(), authorize))
+        .with_state(admin)
+}
+
+/// Separate from `Authorization`, which basic auth from `--web.config.file`
+/// uses on every route
+const TOKEN_HEADER: &str = "x-admin-token";
+
+async fn authorize(State(admin): State<Arc<Admin>>, request: Request, next: Next) -> Response {
+    let authorized = request
+        .headers()
+        .get(TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|token| constant_time_eq(token.as_bytes(), admin.settings.token.as_bytes()));
+
+    if !authorized {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({ "error": "Missing or wrong admin token" })),
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// `POST /admin/locations` with a location as in the config file
+async fn add_handler(
+    State(admin): State<Arc<Admin>>,
+    Json(mut config): Json<LocationConfig>,
+) -> Response {
+    if let Err(e) = config.validate() {
+        return error(StatusCode::BAD_REQUEST, &format!("{:#}", e));
+    }
+    // Label names are part of every series and fixed at startup
+    if let Some(label) = config
+        .labels
+        .keys()
+        .find(|label| !metrics::custom_label_names().contains(label))
+    {
+        return error(
+            StatusCode::BAD_REQUEST,
+            &format!(
+                "Label {} is not used by any other location, new label names need a restart",
+                label
+            ),
+        );
+    }
+    let spec = match LocationSpec::from_config(config.clone(), admin.settings.default_provider) {
+        Ok(spec) => spec,
+        Err(e) => return error(StatusCode::BAD_REQUEST, &format!("{:#}", e)),
+    };
+    let location_name = spec.name.clone();
+    config.name = location_name.clone();
+
+    {
+        let _changes = admin.changes.lock().await;
+        if admin
+            .state
+            .locations
+            .read()
+            .await
+            .contains_key(&location_name)
+        {
+            return error(
+                StatusCode::CONFLICT,
+                &format!("Location {} is already monitored", location_name),
+            );
+        }
+
+        let entry = if config.provider.is_none()
+            && config.search.is_none()
+            && config.country.is_none()
+            && config.yr_id.is_none()
+            && config.labels.is_empty()
+        {
+            LocationEntry::Name(config.name)
+        } else {
+            LocationEntry::Detailed(config)
+        };
+        if let Err(e) = admin
+            .edit_config(move |file| file.locations.push(entry))
+            .await
+        {
+            return error(StatusCode::INTERNAL_SERVER_ERROR, &format!("{:#}", e));
+        }
+
+        admin.state.add_location(spec).await;
+    }
+    info!("Added location {} through the admin API", location_name);
+
+    let result = admin.update(&location_name).await;
+    (StatusCode::CREATED, Json(result)).into_response()
+}
+
+/// `DELETE /admin/locations/{name}`
+async fn remove_handler(
+    State(admin): State<Arc<Admin>>,
+    Path(location_name): Path<String>,
+) -> Response {
+    let _changes = admin.changes.lock().await;
+    if !admin
+        .state
+        .locations
+        .read()
+        .await
+        .contains_key(&location_name)
+    {
+        return error(
+            StatusCode::NOT_FOUND,
+            &format!("Unknown location: {}", location_name),
+        );
+    }
+    if let Some((station, _)) = admin
+        .state
+        .stations
+        .iter()
+        .find(|(_, station)| station.location == location_name)
+    {
+        return error(
+            StatusCode::CONFLICT,
+            &format!(
+                "Station {} reports to {}, remove it from --stations first",
+                station, location_name
+            ),
+        );
+    }
+
+    let removed = location_name.clone();
+    if let Err(e) = admin
+        .edit_config(move |file| {
+            file.locations
+                .retain(|entry| entry.name().trim() != removed)
+        })
+        .await
+    {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, &format!("{:#}", e));
+    }
+
+    admin.state.remove_location(&location_name).await;
+    info!("Removed location {} through the admin API", location_name);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// `POST /admin/locations/{name}/refresh`, looking the location up again and
+/// fetching a new forecast
+async fn refresh_handler(
+    State(admin): State<Arc<Admin>>,
+    Path(location_name): Path<String>,
+) -> Response {
+    if !admin.state.reset_location(&location_name).await {
+        return error(
+            StatusCode::NOT_FOUND,
+            &format!("Unknown location: {}", location_name),
+        );
+    }
+    info!(
+        "Refreshing location {} through the admin API",
+        location_name
+    );
+
+    let result = admin.update(&location_name).await;
+    let status = if result.error.is_some() {
+        StatusCode::BAD_GATEWAY
+    } else {
+        StatusCode::OK
+    };
+    (status, Json(result)).into_response()
+}
+
+impl Admin {
+    async fn update(&self, location_name: &str) -> LocationResult {
+        let error = match self.state.update_metrics_for_location(location_name).await {
+            Ok(()) => None,
+            Err(e) => {
+                warn!("Failed to update metrics for {}: {}", location_name, e);
+                Some(format!("{:#}", e))
+            }
+        };
+
+        let locations = self.state.locations.read().await;
+        let location = locations
+            .get(location_name)
+            .and_then(|data| data.location.as_ref());
+        LocationResult {
+            name: location_name.to_string(),
+            place: location.map(|location| location.describe()),
+            latitude: location.map(|location| location.position.lat),
+            longitude: location.map(|location| location.position.lon),
+            error,
+        }
+    }
+
+    /// Apply a change to the config file, if changes are persisted. The
+    /// first change keeps the original as `<file>.bak`.
+    async fn edit_config(
+        &self,
+        change: impl FnOnce(&mut ConfigFile) + Send + 'static,
+    ) -> Result<()> {
+        let Some(path) = self.settings.config_file.clone() else {
+            return Ok(());
+        };
+        let back_up = !self.backed_up.load(Ordering::Relaxed);
+
+        tokio::task::spawn_blocking(move || {
+            let mut file = ConfigFile::load(&path)?;
+            change(&mut file);
+            if back_up {
+                let mut backup = path.as_os_str().to_owned();
+                backup.push(".bak");
+                std::fs::copy(&path, &backup).with_context(|| {
+                    format!("Failed to back up {} before changing it", path.display())
+                })?;
+                info!(
+                    "Kept the original config file as {}",
+                    PathBuf::from(&backup).display()
+                );
+            }
+            file.save(&path)
+        })
+        .await
+        .context("Config file change panicked")??;
+
+        self.backed_up.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::station::Station;
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    const TOKEN: &str = "s3cret";
+
+    async fn serve(state: AppState, config_file: Option<PathBuf>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router(
+            state,
+            AdminSettings {
+                token: TOKEN.to_string(),
+                config_file,
+                default_provider: ProviderKind::Yr,
+            },
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    fn client() -> reqwest::Client {
+        reqwest::Client::builder().no_proxy().build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn requires_the_admin_token() {
+        let base = serve(AppState::for_tests("locations: [AdminToken]"), None).await;
+        let url = format!("{}/admin/locations/Nowhere", base);
+
+        let missing = client().delete(&url).send().await.unwrap();
+        assert_eq!(missing.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+        for token in ["wrong", "s3cre", "s3crets", ""] {
+            let wrong = client()
+                .delete(&url)
+                .header(TOKEN_HEADER, token)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(
+                wrong.status(),
+                reqwest::StatusCode::UNAUTHORIZED,
+                "{}",
+                token
+            );
+        }
+
+        let right = client()
+            .delete(&url)
+            .header(TOKEN_HEADER, TOKEN)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(right.status(), reqwest::StatusCode::NOT_FOUND);
+        assert_eq!(
+            right.text().await.unwrap(),
+            r#"{"error":"Unknown location: Nowhere"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn conflicting_changes_are_rejected() {
+        let mut state = AppState::for_tests("locations: [AdminTaken, AdminStation]");
+        state.stations = Arc::new(HashMap::from([(
+            "KOSLO2".to_string(),
+            Station {
+                location: "AdminStation".to_string(),
+                password: None,
+            },
+        )]));
+        let base = serve(state.clone(), None).await;
+
+        let added = client()
+            .post(format!("{}/admin/locations", base))
+            .header(TOKEN_HEADER, TOKEN)
+            .json(&json!({ "name": " AdminTaken " }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(added.status(), reqwest::StatusCode::CONFLICT);
+        assert!(added.text().await.unwrap().contains("already monitored"));
+
+        let removed = client()
+            .delete(format!("{}/admin/locations/AdminStation", base))
+            .header(TOKEN_HEADER, TOKEN)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(removed.status(), reqwest::StatusCode::CONFLICT);
+        assert!(removed.text().await.unwrap().contains("KOSLO2"));
+
+        assert_eq!(state.location_names().await, ["AdminTaken", "AdminStation"]);
+    }
+
+    #[tokio::test]
+    async fn changes_are_saved_to_the_config_file() {
+        let dir = std::env::temp_dir().join(format!("weather-admin-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yaml");
+        let original = "# Places we care about\nlocations:\n  - AdminKeep\n  - AdminGone\n";
+        std::fs::write(&path, original).unwrap();
+
+        let state = AppState::for_tests(original);
+        let base = serve(state.clone(), Some(path.clone())).await;
+        let removed = client()
+            .delete(format!("{}/admin/locations/AdminGone", base))
+            .header(TOKEN_HEADER, TOKEN)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(removed.status(), reqwest::StatusCode::NO_CONTENT);
+        assert_eq!(state.location_names().await, ["AdminKeep"]);
+
+        let saved = ConfigFile::load(&path).unwrap();
+        let names: Vec<&str> = saved.locations.iter().map(|entry| entry.name()).collect();
+        assert_eq!(names, ["AdminKeep"]);
+        let backup = path.with_extension("yaml.bak");
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), original);
+
+        // Later changes keep the first backup, and round-trip what they add
+        let admin = Admin {
+            state,
+            settings: AdminSettings {
+                token: TOKEN.to_string(),
+                config_file: Some(path.clone()),
+                default_provider: ProviderKind::Yr,
+            },
+            changes: Mutex::new(()),
+            backed_up: AtomicBool::new(true),
+        };
+        let added = LocationConfig {
+            name: "AdminAdded".to_string(),
+            provider: Some(ProviderKind::OpenMeteo),
+            search: Some("Lund".to_string()),
+            country: Some("SE".to_string()),
+            yr_id: None,
+            timezone: Some("Europe/Stockholm".to_string()),
+            labels: [("team".to_string(), "south".to_string())].into(),
+        };
+        let entry = LocationEntry::Detailed(added.clone());
+        admin
+            .edit_config(move |file| file.locations.push(entry))
+            .await
+            .unwrap();
+
+        let saved = ConfigFile::load(&path).unwrap();
+        assert_eq!(saved.locations.len(), 2);
+        let reloaded = saved.locations[1].clone().into_config();
+        assert_eq!(
+            serde_yaml::to_string(&reloaded).unwrap(),
+            serde_yaml::to_string(&added).unwrap()
+        );
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), original);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,613 @@
+//! JSON API serving the cached forecasts, for clients that can't parse the
+//! Prometheus text format.
+
+use crate::AppState;
+use crate::LocationData;
+use crate::comfort;
+use crate::metrics::custom_labels;
+use crate::provider::{Location, TimeSeries, WeatherResponse};
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use chrono::{DateTime, SubsecRound, Utc};
+use ring::digest;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+
+/// A configured location
+#[derive(Serialize)]
+struct LocationSummary {
+    /// Name from the configuration, used in the API paths
+    name: String,
+    /// The place the name resolved to, `null` until it has been looked up
+    place: Option<Place>,
+    labels: BTreeMap<&'static str, String>,
+    /// When the cached forecast expires, `null` if there is none
+    expires: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct Place {
+    name: String,
+    region: Option<String>,
+    country: Option<String>,
+    latitude: f64,
+    longitude: f64,
+    elevation: Option<f64>,
+    timezone: String,
+}
+
+/// Values of one forecast entry, `null` where the provider has none
+#[derive(Serialize)]
+struct Conditions {
+    time: DateTime<Utc>,
+    /// °C
+    temperature: Option<f64>,
+    /// °C
+    feels_like: Option<f64>,
+    /// °C
+    dew_point: Option<f64>,
+    /// %
+    humidity: Option<f64>,
+    /// m/s
+    wind_speed: Option<f64>,
+    /// m/s
+    wind_gust: Option<f64>,
+    /// Degrees the wind comes from
+    wind_direction: Option<f64>,
+    /// hPa at sea level
+    pressure: Option<f64>,
+    /// mm over the following hour
+    precipitation: Option<f64>,
+    /// %
+    cloud_coverage: Option<f64>,
+    uv_index: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct CurrentResponse {
+    location: String,
+    place: Place,
+    current: Conditions,
+}
+
+#[derive(Serialize)]
+struct ForecastResponse {
+    location: String,
+    place: Place,
+    forecast: Vec<Conditions>,
+}
+
+impl From<&Location> for Place {
+    fn from(location: &Location) -> Self {
+        Self {
+            name: location.name.clone(),
+            region: location.region.as_ref().map(|r| r.name.clone()),
+            country: location.country.as_ref().map(|c| c.name.clone()),
+            latitude: location.position.lat,
+            longitude: location.position.lon,
+            elevation: location.elevation,
+            timezone: location.timezone().name().to_string(),
+        }
+    }
+}
+
+impl From<&TimeSeries> for Conditions {
+    fn from(entry: &TimeSeries) -> Self {
+        let details = &entry.data.instant.details;
+        Self {
+            time: entry.time,
+            temperature: details.air_temperature,
+            feels_like: details.air_temperature.map(|temp| {
+                comfort::feels_like(temp, details.relative_humidity, details.wind_speed)
+            }),
+            dew_point: details
+                .air_temperature
+                .zip(details.relative_humidity)
+                .and_then(|(temp, humidity)| comfort::dew_point(temp, humidity)),
+            humidity: details.relative_humidity,
+            wind_speed: details.wind_speed,
+            wind_gust: details.wind_speed_of_gust,
+            wind_direction: details.wind_from_direction,
+            pressure: details.air_pressure_at_sea_level,
+            precipitation: entry
+                .data
+                .next_1_hours
+                .as_ref()
+                .and_then(|next| next.details.precipitation_amount),
+            cloud_coverage: details.cloud_area_fraction,
+            uv_index: details.ultraviolet_index_clear_sky,
+        }
+    }
+}
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/api/v1/locations", get(locations_handler))
+        .route("/api/v1/locations/{name}/current", get(current_handler))
+        .route("/api/v1/locations/{name}/forecast", get(forecast_handler))
+        .with_state(state)
+}
+
+/// `GET /api/v1/locations`
+async fn locations_handler(State(state): State<AppState>) -> Response {
+    let location_names = state.location_names().await;
+    let locations = state.locations.read().await;
+
+    let summaries: Vec<LocationSummary> = location_names
+        .iter()
+        .map(|location_name| {
+            let data = locations.get(location_name);
+            LocationSummary {
+                name: location_name.clone(),
+                place: data.and_then(|d| d.location.as_ref()).map(Place::from),
+                labels: custom_labels(location_name).into_iter().collect(),
+                expires: data.and_then(|d| d.cache.data.as_ref().and(d.cache.expires)),
+            }
+        })
+        .collect();
+
+    Json(summaries).into_response()
+}
+
+/// `GET /api/v1/locations/{name}/current`, the forecast entry closest to now
+async fn current_handler(
+    State(state): State<AppState>,
+    Path(location_name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let locations = state.locations.read().await;
+    let (data, location, weather) = match cached(&locations, &location_name) {
+        Ok(found) => found,
+        Err((status, message)) => return error(status, &message),
+    };
+    let Some(current) = weather.closest(Utc::now()) else {
+        return error(StatusCode::SERVICE_UNAVAILABLE, "No forecast entries");
+    };
+
+    // The current entry moves on without a new forecast, so it is part of
+    // the ETag
+    let validators = Validators::new(&location_name, data, Some(current.time));
+    if validators.matches(&headers) {
+        return validators.not_modified();
+    }
+
+    let body = CurrentResponse {
+        location: location_name.clone(),
+        place: Place::from(location),
+        current: Conditions::from(current),
+    };
+    validators.respond(Json(body))
+}
+
+/// `GET /api/v1/locations/{name}/forecast`, every cached forecast entry
+async fn forecast_handler(
+    State(state): State<AppState>,
+    Path(location_name): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let locations = state.locations.read().await;
+    let (data, location, weather) = match cached(&locations, &location_name) {
+        Ok(found) => found,
+        Err((status, message)) => return error(status, &message),
+    };
+
+    let validators = Validators::new(&location_name, data, None);
+    if validators.matches(&headers) {
+        return validators.not_modified();
+    }
+
+    let body = ForecastResponse {
+        location: location_name.clone(),
+        place: Place::from(location),
+        forecast: weather
+            .properties
+            .timeseries
+            .iter()
+            .map(Conditions::from)
+            .collect(),
+    };
+    validators.respond(Json(body))
+}
+
+/// The location's data if it is configured and has been fetched
+fn cached<'a>(
+    locations: &'a HashMap<String, LocationData>,
+    location_name: &str,
+) -> Result<(&'a LocationData, &'a Location, &'a WeatherResponse), (StatusCode, String)> {
+    let Some(data) = locations.get(location_name) else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("Unknown location: {}", location_name),
+        ));
+    };
+    match (&data.location, &data.cache.data) {
+        (Some(location), Some(weather)) => Ok((data, location, weather)),
+        _ => Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("No forecast for {} yet", location_name),
+        )),
+    }
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+/// Cache validators of a response built from a location's cached forecast
+struct Validators {
+    etag: String,
+    /// The upstream `Last-Modified` of the forecast, or when the current
+    /// entry took over if that is later
+    last_modified: Option<(String, DateTime<Utc>)>,
+}
+
+impl Validators {
+    fn new(location_name: &str, data: &LocationData, entry: Option<DateTime<Utc>>) -> Self {
+        let mut last_modified = data.cache.last_modified.as_ref().and_then(|value| {
+            DateTime::parse_from_rfc2822(value)
+                .ok()
+                .map(|time| (value.clone(), time.with_timezone(&Utc)))
+        });
+        // The current entry moves on without the forecast changing. The
+        // closest entry can lie ahead, and Last-Modified can't.
+        if let Some(entry) = entry
+            && let Some((_, forecast)) = &last_modified
+        {
+            let current_since = entry.min(Utc::now().trunc_subsecs(0));
+            if current_since > *forecast {
+                last_modified = Some((
+                    current_since
+                        .format("%a, %d %b %Y %H:%M:%S GMT")
+                        .to_string(),
+                    current_since,
+                ));
+            }
+        }
+
+        // Providers without Last-Modified get a new expiry with every fetch.
+        // SHA-256 rather than the std hasher, whose output may change with
+        // the Rust version, which would invalidate every client's cache.
+        let mut context = digest::Context::new(&digest::SHA256);
+        for field in [
+            Some(location_name.to_string()),
+            data.cache.last_modified.clone(),
+            data.cache.expires.map(|time| time.to_rfc3339()),
+            entry.map(|time| time.to_rfc3339()),
+        ] {
+            let field = field.unwrap_or_default();
+            context.update(&(field.len() as u64).to_be_bytes());
+            context.update(field.as_bytes());
+        }
+        let hash: String = context.finish().as_ref()[..8]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        Self {
+            etag: format!("\"{}\"", hash),
+            last_modified,
+        }
+    }
+
+    /// Whether the request's conditional headers match, `If-None-Match`
+    /// taking precedence over `If-Modified-Since`
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+            return if_none_match.to_str().is_ok_and(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == self.etag)
+            });
+        }
+
+        let Some((_, last_modified)) = self.last_modified else {
+            return false;
+        };
+        headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+            .is_some_and(|since| last_modified <= since)
+    }
+
+    fn not_modified(&self) -> Response {
+        self.respond(StatusCode::NOT_MODIFIED)
+    }
+
+    fn respond(&self, body: impl IntoResponse) -> Response {
+        let mut response = body.into_response();
+        let headers = response.headers_mut();
+        if let Ok(etag) = HeaderValue::from_str(&self.etag) {
+            headers.insert(header::ETAG, etag);
+        }
+        if let Some(last_modified) = self
+            .last_modified
+            .as_ref()
+            .and_then(|(value, _)| HeaderValue::from_str(value).ok())
+        {
+            headers.insert(header::LAST_MODIFIED, last_modified);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{Area, Position, WeatherCache};
+    use chrono::{Duration, DurationRound, TimeZone};
+    use reqwest::header::HeaderMap as RequestHeaders;
+    use tokio::net::TcpListener;
+
+    const LAST_MODIFIED: &str = "Thu, 15 Jan 2026 10:00:00 GMT";
+
+    fn oslo() -> Location {
+        Location {
+            id: Some("1-72837".to_string()),
+            name: "Oslo".to_string(),
+            position: Position {
+                lat: 59.9127,
+                lon: 10.7461,
+            },
+            elevation: Some(23.0),
+            time_zone: Some("Europe/Oslo".to_string()),
+            country: Some(Area {
+                id: Some("NO".to_string()),
+                name: "Norway".to_string(),
+            }),
+            region: None,
+        }
+    }
+
+    /// Hourly entries from an hour before the current one, 10°C and up
+    fn forecast(now: DateTime<Utc>) -> WeatherResponse {
+        let hour = now.duration_trunc(Duration::hours(1)).unwrap();
+        let timeseries: Vec<_> = (-1..6)
+            .map(|i| {
+                json!({
+                    "time": hour + Duration::hours(i),
+                    "data": {
+                        "instant": { "details": { "air_temperature": 10.0 + i as f64 } },
+                        "next_1_hours": null
+                    }
+                })
+            })
+            .collect();
+        serde_json::from_value(json!({ "properties": { "timeseries": timeseries } })).unwrap()
+    }
+
+    fn cache(now: DateTime<Utc>) -> WeatherCache {
+        WeatherCache {
+            data: Some(forecast(now)),
+            expires: Some(Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap()),
+            last_modified: Some(LAST_MODIFIED.to_string()),
+        }
+    }
+
+    /// ApiOslo is fetched, ApiPending only looked up and ApiNew untouched
+    async fn state() -> AppState {
+        let state = AppState::for_tests("locations: [ApiOslo, ApiPending, ApiNew]");
+        {
+            let mut locations = state.locations.write().await;
+            let oslo_data = locations.get_mut("ApiOslo").unwrap();
+            oslo_data.location = Some(oslo());
+            oslo_data.cache = cache(Utc::now());
+            locations.get_mut("ApiPending").unwrap().location = Some(oslo());
+        }
+        state
+    }
+
+    async fn serve(state: AppState) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(state)).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    async fn get(
+        url: &str,
+        headers: &[(header::HeaderName, &str)],
+    ) -> (StatusCode, RequestHeaders, String) {
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let mut request = client.get(url);
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        let response = request.send().await.unwrap();
+        let status = StatusCode::from_u16(response.status().as_u16()).unwrap();
+        let headers = response.headers().clone();
+        (status, headers, response.text().await.unwrap())
+    }
+
+    fn header_value<'a>(headers: &'a RequestHeaders, name: &str) -> &'a str {
+        headers
+            .get(name)
+            .map(|value| value.to_str().unwrap())
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn lists_locations_in_configuration_order() {
+        let base = serve(state().await).await;
+        let (status, _, body) = get(&format!("{}/api/v1/locations", base), &[]).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let locations: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let names: Vec<&str> = locations
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|location| location["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["ApiOslo", "ApiPending", "ApiNew"]);
+        assert_eq!(locations[0]["place"]["country"], "Norway");
+        assert_eq!(locations[0]["expires"], "2030-01-01T00:00:00Z");
+        assert_eq!(locations[1]["expires"], serde_json::Value::Null);
+        assert_eq!(locations[2]["place"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn unknown_and_unfetched_locations() {
+        let base = serve(state().await).await;
+
+        for path in ["current", "forecast"] {
+            let (status, _, body) =
+                get(&format!("{}/api/v1/locations/Nowhere/{}", base, path), &[]).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(body, r#"{"error":"Unknown location: Nowhere"}"#);
+
+            for location_name in ["ApiPending", "ApiNew"] {
+                let url = format!("{}/api/v1/locations/{}/{}", base, location_name, path);
+                let (status, headers, body) = get(&url, &[]).await;
+                assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+                assert!(body.contains("No forecast"), "{}", body);
+                assert!(headers.get("etag").is_none());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn conditional_forecast_requests() {
+        let base = serve(state().await).await;
+        let url = format!("{}/api/v1/locations/ApiOslo/forecast", base);
+
+        let (status, headers, body) = get(&url, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        let forecast: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(forecast["forecast"].as_array().unwrap().len(), 7);
+        assert_eq!(forecast["place"]["timezone"], "Europe/Oslo");
+        let etag = header_value(&headers, "etag").to_string();
+        assert_eq!(etag.len(), 18, "{}", etag);
+        assert_eq!(header_value(&headers, "last-modified"), LAST_MODIFIED);
+
+        let weak = format!("W/{}", etag);
+        let listed = format!("\"other\", {}", etag);
+        for (headers, expected) in [
+            (
+                vec![(header::IF_NONE_MATCH, etag.as_str())],
+                StatusCode::NOT_MODIFIED,
+            ),
+            (
+                vec![(header::IF_NONE_MATCH, weak.as_str())],
+                StatusCode::NOT_MODIFIED,
+            ),
+            (
+                vec![(header::IF_NONE_MATCH, listed.as_str())],
+                StatusCode::NOT_MODIFIED,
+            ),
+            (vec![(header::IF_NONE_MATCH, "*")], StatusCode::NOT_MODIFIED),
+            (vec![(header::IF_NONE_MATCH, "\"other\"")], StatusCode::OK),
+            (
+                vec![(header::IF_MODIFIED_SINCE, LAST_MODIFIED)],
+                StatusCode::NOT_MODIFIED,
+            ),
+            (
+                vec![(header::IF_MODIFIED_SINCE, "Thu, 15 Jan 2026 11:00:00 GMT")],
+                StatusCode::NOT_MODIFIED,
+            ),
+            (
+                vec![(header::IF_MODIFIED_SINCE, "Thu, 15 Jan 2026 09:59:59 GMT")],
+                StatusCode::OK,
+            ),
+            (
+                vec![(header::IF_MODIFIED_SINCE, "yesterday")],
+                StatusCode::OK,
+            ),
+            // If-None-Match wins over a matching If-Modified-Since
+            (
+                vec![
+                    (header::IF_NONE_MATCH, "\"other\""),
+                    (header::IF_MODIFIED_SINCE, LAST_MODIFIED),
+                ],
+                StatusCode::OK,
+            ),
+            (
+                vec![
+                    (header::IF_NONE_MATCH, etag.as_str()),
+                    (header::IF_MODIFIED_SINCE, "Thu, 15 Jan 2026 09:00:00 GMT"),
+                ],
+                StatusCode::NOT_MODIFIED,
+            ),
+        ] {
+            let (status, response_headers, body) = get(&url, &headers).await;
+            assert_eq!(status, expected, "{:?}", headers);
+            // 304s carry the validators too, and nothing else
+            assert_eq!(header_value(&response_headers, "etag"), etag);
+            if status == StatusCode::NOT_MODIFIED {
+                assert!(body.is_empty());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn current_conditions_move_on_with_the_hour() {
+        let state = state().await;
+        let base = serve(state.clone()).await;
+        let url = format!("{}/api/v1/locations/ApiOslo/current", base);
+
+        let requested = Utc::now().trunc_subsecs(0);
+        let (status, headers, body) = get(&url, &[]).await;
+        assert_eq!(status, StatusCode::OK);
+        let current: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let time: DateTime<Utc> = current["current"]["time"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        // The closest entry, which may be the next hour
+        assert!((time - Utc::now()).num_minutes().abs() <= 30);
+        // Last-Modified is when that entry took over, unless that's ahead
+        let last_modified = DateTime::parse_from_rfc2822(header_value(&headers, "last-modified"))
+            .unwrap()
+            .with_timezone(&Utc);
+        if time <= requested {
+            assert_eq!(last_modified, time);
+        } else {
+            assert!(last_modified >= requested && last_modified <= Utc::now());
+        }
+
+        let forecast_url = format!("{}/api/v1/locations/ApiOslo/forecast", base);
+        let (_, forecast_headers, _) = get(&forecast_url, &[]).await;
+        assert_ne!(
+            header_value(&headers, "etag"),
+            header_value(&forecast_headers, "etag")
+        );
+    }
+
+    #[tokio::test]
+    async fn etags_are_stable() {
+        let state = AppState::for_tests("locations: [ApiStable]");
+        let mut locations = state.locations.write().await;
+        let data = locations.get_mut("ApiStable").unwrap();
+        data.cache = WeatherCache {
+            data: None,
+            expires: Some(Utc.with_ymd_and_hms(2026, 1, 15, 11, 0, 0).unwrap()),
+            last_modified: Some(LAST_MODIFIED.to_string()),
+        };
+        let entry = Utc.with_ymd_and_hms(2026, 1, 15, 10, 0, 0).unwrap();
+
+        // Fixed values, so a change of hash shows up here rather than as
+        // every client refetching after an upgrade
+        assert_eq!(
+            Validators::new("ApiStable", data, None).etag,
+            "\"9d402a5bc968cb61\""
+        );
+        assert_eq!(
+            Validators::new("ApiStable", data, Some(entry)).etag,
+            "\"e2ef5c1bef31271e\""
+        );
+
+        data.cache.expires = Some(entry);
+        assert_ne!(
+            Validators::new("ApiStable", data, None).etag,
+            "\"9d402a5bc968cb61\""
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! Derived comfort indices. All temperatures are in Celsius, humidity is
+//! relative humidity in percent and wind speed is in meters per second.
+
+// Magnus formula coefficients (Sonntag 1990)
+const MAGNUS_A: f64 = 17.62;
+const MAGNUS_B: f64 = 243.12;
+
+/// Dew point using the Magnus formula
+pub fn dew_point(temp: f64, humidity: f64) -> Option<f64> {
+    if humidity <= 0.0 {
+        return None;
+    }
+    let gamma = (humidity / 100.0).ln() + MAGNUS_A * temp / (MAGNUS_B + temp);
+    Some(MAGNUS_B * gamma / (MAGNUS_A - gamma))
+}
+
+/// Wind chill (Environment Canada / NWS 2001). Only defined at or below
+/// 10 °C with wind above 4.8 km/h.
+pub fn wind_chill(temp: f64, wind_speed: f64) -> Option<f64> {
+    let wind_kmh = wind_speed * 3.6;
+    if temp > 10.0 || wind_kmh <= 4.8 {
+        return None;
+    }
+    let v = wind_kmh.powf(0.16);
+    Some(13.12 + 0.6215 * temp - 11.37 * v + 0.3965 * temp * v)
+}
+
+/// Heat index (NWS Rothfusz regression). Only defined from 27 °C and 40 %
+/// relative humidity.
+pub fn heat_index(temp: f64, humidity: f64) -> Option<f64> {
+    if temp < 27.0 || humidity < 40.0 {
+        return None;
+    }
+    let t = temp * 9.0 / 5.0 + 32.0;
+    let rh = humidity;
+    let hi = -42.379 + 2.049_015_23 * t + 10.143_331_27 * rh
+        - 0.224_755_41 * t * rh
+        - 0.006_837_83 * t * t
+        - 0.054_817_17 * rh * rh
+        + 0.001_228_74 * t * t * rh
+        + 0.000_852_82 * t * rh * rh
+        - 0.000_001_99 * t * t * rh * rh;
+    Some((hi - 32.0) * 5.0 / 9.0)
+}
+
+/// Canadian humidex
+pub fn humidex(temp: f64, humidity: f64) -> Option<f64> {
+    let dew_point_kelvin = dew_point(temp, humidity)? + 273.15;
+    let vapour_pressure = 6.11 * (5417.7530 * (1.0 / 273.16 - 1.0 / dew_point_kelvin)).exp();
+    Some(temp + 0.5555 * (vapour_pressure - 10.0))
+}
+
+/// Absolute humidity in grams of water vapour per cubic meter
+pub fn absolute_humidity(temp: f64, humidity: f64) -> f64 {
+    let saturation = 6.112 * (17.67 * temp / (temp + 243.5)).exp();
+    saturation * humidity * 2.1674 / (273.15 + temp)
+}
+
+/// Apparent temperature: wind chill when cold and windy, heat index when hot
+/// and humid, otherwise the air temperature itself.
+pub fn feels_like(temp: f64, humidity: Option<f64>, wind_speed: Option<f64>) -> f64 {
+    wind_speed
+        .and_then(|wind| wind_chill(temp, wind))
+        .or_else(|| humidity.and_then(|rh| heat_index(temp, rh)))
+        .unwrap_or(temp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn dew_point_matches_tables() {
+        assert_near(dew_point(20.0, 50.0).unwrap(), 9.3, 0.05);
+        assert_near(dew_point(-10.0, 80.0).unwrap(), -12.8, 0.1);
+        // Saturated air is at its dew point
+        assert_near(dew_point(15.0, 100.0).unwrap(), 15.0, 1e-9);
+        assert_eq!(dew_point(20.0, 0.0), None);
+    }
+
+    #[test]
+    fn wind_chill_matches_environment_canada() {
+        // -10 °C with 20 km/h of wind feels like -18 °C
+        assert_near(wind_chill(-10.0, 20.0 / 3.6).unwrap(), -17.9, 0.05);
+        assert_near(wind_chill(0.0, 50.0 / 3.6).unwrap(), -8.1, 0.1);
+        assert_eq!(wind_chill(11.0, 10.0), None);
+        // 4.8 km/h or less is calm
+        assert_eq!(wind_chill(-10.0, 1.3), None);
+    }
+
+    #[test]
+    fn heat_index_matches_nws() {
+        // 90 °F at 60 % is about 100 °F
+        assert_near(heat_index(32.22, 60.0).unwrap(), 37.8, 0.3);
+        // 100 °F at 40 % is about 109 °F
+        assert_near(heat_index(37.78, 40.0).unwrap(), 42.8, 0.3);
+        assert_eq!(heat_index(26.9, 80.0), None);
+        assert_eq!(heat_index(35.0, 39.0), None);
+    }
+
+    #[test]
+    fn humidex_matches_environment_canada() {
+        // 30 °C with a dew point of 15 °C has a humidex of 34
+        let humidity = 100.0 * (MAGNUS_A * 15.0 / (MAGNUS_B + 15.0)).exp()
+            / (MAGNUS_A * 30.0 / (MAGNUS_B + 30.0)).exp();
+        assert_near(humidex(30.0, humidity).unwrap(), 34.0, 0.1);
+        assert_eq!(humidex(30.0, 0.0), None);
+    }
+
+    #[test]
+    fn absolute_humidity_of_saturated_air() {
+        assert_near(absolute_humidity(20.0, 100.0), 17.3, 0.05);
+        assert_near(absolute_humidity(0.0, 100.0), 4.85, 0.05);
+        assert_near(absolute_humidity(20.0, 50.0), 17.3 / 2.0, 0.05);
+    }
+
+    #[test]
+    fn feels_like_picks_the_applicable_index() {
+        let cold = feels_like(-10.0, Some(80.0), Some(20.0 / 3.6));
+        assert_near(cold, wind_chill(-10.0, 20.0 / 3.6).unwrap(), 1e-9);
+        let hot = feels_like(32.22, Some(60.0), Some(2.0));
+        assert_near(hot, heat_index(32.22, 60.0).unwrap(), 1e-9);
+        assert_eq!(feels_like(18.0, Some(60.0), Some(5.0)), 18.0);
+        assert_eq!(feels_like(-10.0, None, None), -10.0);
+    }
+}
@@ -0,0 +1,222 @@
+//! One-shot subcommands that print to stdout instead of serving metrics
+
+use crate::AppState;
+use crate::comfort;
+use crate::metrics;
+use crate::provider::{Area, TimeSeries, WeatherProvider};
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use tracing::error;
+
+/// Print every candidate for `query` as a table
+pub async fn search_locations(provider: &dyn WeatherProvider, query: &str) -> Result<()> {
+    let candidates = provider.search_locations(query).await?;
+    if candidates.is_empty() {
+        return Err(anyhow::anyhow!("Location not found: {}", query));
+    }
+
+    let rows: Vec<Vec<String>> = candidates
+        .iter()
+        .map(|location| {
+            let area =
+                |area: &Option<Area>| area.as_ref().map(|a| a.name.clone()).unwrap_or_default();
+            let country = match &location.country {
+                Some(country) => match &country.id {
+                    Some(id) => format!("{} ({})", country.name, id),
+                    None => country.name.clone(),
+                },
+                None => String::new(),
+            };
+            vec![
+                location.name.clone(),
+                area(&location.region),
+                country,
+                format!("{:.4}", location.position.lat),
+                format!("{:.4}", location.position.lon),
+                location.id.clone().unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+
+    print_table(
+        &[
+            "NAME",
+            "REGION",
+            "COUNTRY",
+            "LATITUDE",
+            "LONGITUDE",
+            "YR ID",
+        ],
+        &rows,
+    );
+
+    Ok(())
+}
+
+/// How `now` prints what it fetched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// One table per location
+    Table,
+    /// The selected forecast entries as JSON
+    Json,
+    /// Prometheus text exposition, as served on /metrics
+    Prometheus,
+}
+
+#[derive(Serialize)]
+struct LocationReport<'a> {
+    location: &'a str,
+    name: &'a str,
+    latitude: f64,
+    longitude: f64,
+    timezone: &'a str,
+    timeseries: Vec<&'a TimeSeries>,
+}
+
+/// Fetch every location once, as the server would, and print the result
+pub async fn now(state: &AppState, format: OutputFormat, hours: i64) -> Result<()> {
+    let location_names = state.location_names().await;
+    let mut failed = 0;
+    for location_name in &location_names {
+        if let Err(e) = state.update_metrics_for_location(location_name).await {
+            error!("Failed to update metrics for {}: {}", location_name, e);
+            failed += 1;
+        }
+    }
+    if failed == location_names.len() {
+        return Err(anyhow::anyhow!("No location could be fetched"));
+    }
+
+    if format == OutputFormat::Prometheus {
+        print!("{}", metrics::gather_text());
+        return Ok(());
+    }
+
+    let now = Utc::now();
+    let locations = state.locations.read().await;
+    let mut reports = Vec::new();
+    for location_name in &location_names {
+        let Some(data) = locations.get(location_name) else {
+            continue;
+        };
+        let (Some(location), Some(weather)) = (&data.location, &data.cache.data) else {
+            continue;
+        };
+        let Some(current) = weather.closest(now) else {
+            continue;
+        };
+        let until = current.time + Duration::hours(hours);
+
+        reports.push((
+            location.timezone(),
+            LocationReport {
+                location: location_name,
+                name: &location.name,
+                latitude: location.position.lat,
+                longitude: location.position.lon,
+                timezone: location.timezone().name(),
+                timeseries: weather
+                    .properties
+                    .timeseries
+                    .iter()
+                    .filter(|ts| ts.time >= current.time && ts.time <= until)
+                    .collect(),
+            },
+        ));
+    }
+
+    match format {
+        OutputFormat::Json => {
+            let reports: Vec<&LocationReport> = reports.iter().map(|(_, r)| r).collect();
+            println!("{}", serde_json::to_string_pretty(&reports)?);
+        }
+        _ => {
+            for (i, (tz, report)) in reports.iter().enumerate() {
+                if i > 0 {
+                    println!();
+                }
+                print_forecast_table(report, *tz);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_forecast_table(report: &LocationReport, tz: Tz) {
+    println!(
+        "{} ({} at {:.4}, {:.4})",
+        report.location, report.name, report.latitude, report.longitude
+    );
+
+    let value = |value: Option<f64>| value.map(|v| format!("{:.1}", v)).unwrap_or_default();
+    let rows: Vec<Vec<String>> = report
+        .timeseries
+        .iter()
+        .map(|ts| {
+            let details = &ts.data.instant.details;
+            let feels_like = details.air_temperature.map(|temp| {
+                comfort::feels_like(temp, details.relative_humidity, details.wind_speed)
+            });
+            let precipitation = ts
+                .data
+                .next_1_hours
+                .as_ref()
+                .and_then(|next| next.details.precipitation_amount);
+            vec![
+                ts.time.with_timezone(&tz).format("%a %H:%M").to_string(),
+                value(details.air_temperature),
+                value(feels_like),
+                value(details.relative_humidity),
+                value(details.wind_speed),
+                value(details.wind_speed_of_gust),
+                value(details.wind_from_direction),
+                value(details.air_pressure_at_sea_level),
+                value(precipitation),
+                value(details.cloud_area_fraction),
+                value(details.ultraviolet_index_clear_sky),
+            ]
+        })
+        .collect();
+
+    let header = [
+        "TIME",
+        "TEMP °C",
+        "FEELS °C",
+        "HUM %",
+        "WIND m/s",
+        "GUST m/s",
+        "DIR °",
+        "PRES hPa",
+        "PRECIP mm",
+        "CLOUD %",
+        "UV",
+    ];
+    print_table(&header, &rows);
+}
+
+/// Print left-aligned columns sized to their widest cell
+fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let print_row = |cells: &[String]| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+    print_row(&header.iter().map(|h| h.to_string()).collect::<Vec<_>>());
+    for row in rows {
+        print_row(row);
+    }
+}
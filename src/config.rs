@@ -1,64 +1,296 @@
-use config::{Config, ConfigError, File};
-use serde::Deserialize;
-use std::path::Path;
-
-#[derive(Debug, Deserialize)]
-pub struct Settings {
-    pub user_agent: String,
-    pub locations: Vec<String>,
-    pub port: u16,
-    pub log_level: String,
-    pub cache_settings: CacheSettings,
+use crate::provider::ProviderKind;
+use anyhow::{Context, Result};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    str::FromStr,
+};
+use tracing::level_filters::LevelFilter;
+
+// Labels the exporter sets itself, which user-defined labels may not shadow
+const RESERVED_LABELS: &[&str] = &[
+    "location",
+    "latitude",
+    "longitude",
+    "source",
+    "station",
+    "lead_hours",
+    "period",
+    "day",
+    "elevation",
+    "country",
+    "region",
+    "yr_id",
+    "timezone",
+    "job",
+    "instance",
+];
+
+/// Settings read from the YAML file given with `--config-file`
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(default)]
+    pub locations: Vec<LocationEntry>,
+    /// Overrides `--log-level`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+    /// How often the background task checks for expired forecasts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update_interval_seconds: Option<u64>,
+    /// Overrides `--push-interval-seconds`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub push_interval_seconds: Option<u64>,
+    /// Overrides `--influx-interval-seconds`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub influx_interval_seconds: Option<u64>,
+    /// Overrides `--otlp-interval-seconds`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub otlp_interval_seconds: Option<u64>,
+}
+
+/// A location is either just a name or a mapping with more settings
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum LocationEntry {
+    Name(String),
+    Detailed(LocationConfig),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LocationConfig {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<ProviderKind>,
+    /// Place name to search for, when it differs from `name`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub search: Option<String>,
+    /// Country code or name the geocoding match has to be in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// yr.no location ID, skipping the search
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub yr_id: Option<String>,
+    /// IANA timezone, instead of the one from geocoding or the offline lookup
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// Static labels attached to every series of this location
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct CacheSettings {
-    pub enable_cache: bool,
-    pub cache_duration_minutes: u64,
+impl LocationEntry {
+    pub fn into_config(self) -> LocationConfig {
+        match self {
+            LocationEntry::Name(name) => LocationConfig {
+                name,
+                provider: None,
+                search: None,
+                country: None,
+                yr_id: None,
+                timezone: None,
+                labels: BTreeMap::new(),
+            },
+            LocationEntry::Detailed(config) => config,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            LocationEntry::Name(name) => name,
+            LocationEntry::Detailed(config) => &config.name,
+        }
+    }
 }
 
-impl Settings {
-    pub fn new() -> Result<Self, ConfigError> {
-        let config = Config::builder()
-            // Start with default values
-            .set_default("port", 9090)?
-            .set_default("log_level", "info")?
-            .set_default("cache_settings.enable_cache", true)?
-            .set_default("cache_settings.cache_duration_minutes", 5)?
-            
-            // Look for config file in multiple locations
-            .add_source(File::with_name("/etc/weather-exporter/config").required(false))
-            .add_source(File::with_name("config/default").required(false))
-            .add_source(File::with_name("config/local").required(false))
-            
-            // Override with environment variables (prefixed with WEATHER_)
-            .add_source(config::Environment::with_prefix("WEATHER"))
-            
-            .build()?;
-
-        let settings: Settings = config.try_deserialize()?;
-        
-        // Validate required fields
-        if settings.user_agent.trim().is_empty() {
-            return Err(ConfigError::Message(
-                "user_agent is required and cannot be empty".to_string()
-            ));
+impl LocationConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            return Err(anyhow::anyhow!("Location names cannot be empty"));
+        }
+        if let Some(timezone) = &self.timezone {
+            Tz::from_str(timezone).map_err(|_| {
+                anyhow::anyhow!("Unknown timezone '{}' for location {}", timezone, self.name)
+            })?;
         }
-        
-        if settings.locations.is_empty() {
-            return Err(ConfigError::Message(
-                "At least one location must be specified".to_string()
-            ));
+        for label in self.labels.keys() {
+            validate_label_name(label)
+                .with_context(|| format!("Invalid label for location {}", self.name))?;
         }
-        
-        Ok(settings)
+        Ok(())
+    }
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        // Untagged location entries take the last of duplicate keys, a
+        // generic value rejects them
+        serde_yaml::from_str::<serde_yaml::Value>(contents)?;
+        let config: ConfigFile = serde_yaml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(level) = &self.log_level {
+            LevelFilter::from_str(level)
+                .map_err(|_| anyhow::anyhow!("Invalid log level '{}'", level))?;
+        }
+        for (name, seconds) in [
+            ("update_interval_seconds", self.update_interval_seconds),
+            ("push_interval_seconds", self.push_interval_seconds),
+            ("influx_interval_seconds", self.influx_interval_seconds),
+            ("otlp_interval_seconds", self.otlp_interval_seconds),
+        ] {
+            if seconds == Some(0) {
+                return Err(anyhow::anyhow!("{} must be positive", name));
+            }
+        }
+        let mut names = BTreeSet::new();
+        for location in self
+            .locations
+            .iter()
+            .cloned()
+            .map(LocationEntry::into_config)
+        {
+            location.validate()?;
+            // Names are trimmed when the locations are set up
+            let name = location.name.trim();
+            if !names.insert(name.to_string()) {
+                return Err(anyhow::anyhow!("Location {} is listed twice", name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the file back, e.g. after locations were changed at runtime.
+    /// Comments and formatting of the original are not kept.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        self.validate()?;
+        let contents = serde_yaml::to_string(self)?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+
+        std::fs::write(&tmp, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to replace config file {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Check a label name against the Prometheus naming rules
+fn validate_label_name(name: &str) -> Result<()> {
+    let mut chars = name.chars();
+    let valid_start = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    if !valid_start || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(anyhow::anyhow!(
+            "'{}' is not a valid label name, use [a-zA-Z_][a-zA-Z0-9_]*",
+            name
+        ));
+    }
+    if name.starts_with("__") {
+        return Err(anyhow::anyhow!(
+            "'{}' is reserved, label names starting with __ are internal to Prometheus",
+            name
+        ));
     }
+    if RESERVED_LABELS.contains(&name) {
+        return Err(anyhow::anyhow!(
+            "'{}' is already used by the exporter",
+            name
+        ));
+    }
+    Ok(())
 }
 
-// Usage in main:
-#[tokio::main]
-async fn main() -> Result<()> {
-    let settings = Settings::new()?;
-    
-    // Use settings.user_agent, settings.locations, etc.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(yaml: &str) -> String {
+        format!("{:#}", ConfigFile::parse(yaml).unwrap_err())
+    }
+
+    fn labelled(label: &str) -> String {
+        format!(
+            "locations:\n  - name: Oslo\n    labels:\n      {}: x\n",
+            label
+        )
+    }
+
+    #[test]
+    fn label_names_follow_prometheus_rules() {
+        for valid in ["site", "_site", "Site_2", "s"] {
+            assert!(ConfigFile::parse(&labelled(valid)).is_ok(), "{}", valid);
+        }
+        for invalid in ["2site", "site-name", "site.name", "sité", "\"\""] {
+            assert!(
+                error(&labelled(invalid)).contains("not a valid label name"),
+                "{}",
+                invalid
+            );
+        }
+        assert!(error(&labelled("__site")).contains("internal to Prometheus"));
+    }
+
+    #[test]
+    fn label_names_set_by_the_exporter_are_reserved() {
+        for reserved in RESERVED_LABELS {
+            assert!(
+                error(&labelled(reserved)).contains("already used by the exporter"),
+                "{}",
+                reserved
+            );
+        }
+        assert!(ConfigFile::parse(&labelled("Location")).is_ok());
+    }
+
+    #[test]
+    fn duplicates_are_rejected() {
+        assert!(error("locations: [Oslo, Lund, Oslo]").contains("Oslo is listed twice"));
+        // Names are trimmed before use, so these would collide
+        assert!(error("locations: [Oslo, 'Oslo ']").contains("Oslo is listed twice"));
+        assert!(
+            error("locations:\n  - Oslo\n  - name: ' Oslo'\n    provider: open-meteo\n")
+                .contains("listed twice")
+        );
+        assert!(
+            error("locations:\n  - name: Oslo\n    labels:\n      site: a\n      site: b\n")
+                .contains("duplicate")
+        );
+    }
+
+    #[test]
+    fn settings_are_validated() {
+        assert!(ConfigFile::parse("").unwrap().locations.is_empty());
+        assert!(error("locations: ['  ']").contains("cannot be empty"));
+        assert!(error("log_level: loud").contains("Invalid log level"));
+        assert!(
+            error("locations:\n  - name: Oslo\n    timezone: Europe/Olso\n")
+                .contains("Unknown timezone 'Europe/Olso'")
+        );
+        assert!(error("update_interval_seconds: 0").contains("must be positive"));
+        assert!(error("otlp_interval_seconds: 0").contains("otlp_interval_seconds"));
+        assert!(error("locations: [Oslo]\nunknown: 1").contains("unknown field"));
+
+        let file = ConfigFile::parse(
+            "log_level: debug\npush_interval_seconds: 30\nlocations:\n  - Oslo\n  - name: Lund\n    country: SE\n",
+        )
+        .unwrap();
+        assert_eq!(file.push_interval_seconds, Some(30));
+        let names: Vec<&str> = file.locations.iter().map(LocationEntry::name).collect();
+        assert_eq!(names, ["Oslo", "Lund"]);
+    }
 }
@@ -0,0 +1,328 @@
+use crate::metrics::{
+    DAILY_PRECIPITATION, DAILY_TEMPERATURE_MAX, DAILY_TEMPERATURE_MEAN, DAILY_TEMPERATURE_MIN,
+    DAILY_UV_INDEX_MAX, DAILY_WIND_GUST_MAX, location_labels,
+};
+use crate::provider::{TimeSeries, WeatherResponse};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use prometheus::GaugeVec;
+use serde::{Deserialize, Serialize};
+
+/// Aggregates over the forecast entries that fall on one day
+#[derive(Debug, Default)]
+struct DailySummary {
+    temperature_min: Option<f64>,
+    temperature_max: Option<f64>,
+    temperature_mean: Option<f64>,
+    precipitation: Option<f64>,
+    wind_gust_max: Option<f64>,
+    uv_index_max: Option<f64>,
+}
+
+fn max(acc: Option<f64>, value: Option<f64>) -> Option<f64> {
+    match (acc, value) {
+        (Some(a), Some(v)) => Some(a.max(v)),
+        (a, v) => a.or(v),
+    }
+}
+
+fn min(acc: Option<f64>, value: Option<f64>) -> Option<f64> {
+    match (acc, value) {
+        (Some(a), Some(v)) => Some(a.min(v)),
+        (a, v) => a.or(v),
+    }
+}
+
+impl DailySummary {
+    fn from_entries<'a>(entries: impl Iterator<Item = &'a TimeSeries>) -> Self {
+        let mut summary = Self::default();
+        let mut temperature_sum = 0.0;
+        let mut temperature_count = 0;
+
+        for entry in entries {
+            let details = &entry.data.instant.details;
+
+            if let Some(temp) = details.air_temperature {
+                temperature_sum += temp;
+                temperature_count += 1;
+            }
+            summary.temperature_min = min(summary.temperature_min, details.air_temperature);
+            summary.temperature_max = max(summary.temperature_max, details.air_temperature);
+            summary.wind_gust_max = max(summary.wind_gust_max, details.wind_speed_of_gust);
+            summary.uv_index_max = max(summary.uv_index_max, details.ultraviolet_index_clear_sky);
+
+            if let Some(precip) = entry
+                .data
+                .next_1_hours
+                .as_ref()
+                .and_then(|next| next.details.precipitation_amount)
+            {
+                summary.precipitation = Some(summary.precipitation.unwrap_or(0.0) + precip);
+            }
+        }
+
+        if temperature_count > 0 {
+            summary.temperature_mean = Some(temperature_sum / temperature_count as f64);
+        }
+
+        summary
+    }
+
+    fn update_metrics(&self, location_name: &str, day: &str) {
+        let labels = location_labels(location_name, &[day]);
+        let set = |gauge: &GaugeVec, value: Option<f64>| match value {
+            Some(value) => gauge.with_label_values(&labels).set(value),
+            None => {
+                let _ = gauge.remove_label_values(&labels);
+            }
+        };
+
+        set(&DAILY_TEMPERATURE_MIN, self.temperature_min);
+        set(&DAILY_TEMPERATURE_MAX, self.temperature_max);
+        set(&DAILY_TEMPERATURE_MEAN, self.temperature_mean);
+        set(&DAILY_PRECIPITATION, self.precipitation);
+        set(&DAILY_WIND_GUST_MAX, self.wind_gust_max);
+        set(&DAILY_UV_INDEX_MAX, self.uv_index_max);
+    }
+}
+
+/// Forecast entries from earlier today that newer forecasts no longer include,
+/// so today's aggregates still cover the whole day. Persisted, so they
+/// survive a restart.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EarlierToday {
+    entries: Vec<TimeSeries>,
+}
+
+impl EarlierToday {
+    /// Keep the entries of `previous` that are older than anything in
+    /// `current` but still on the same day as `now`
+    pub fn retain(
+        &mut self,
+        previous: Option<&WeatherResponse>,
+        current: &WeatherResponse,
+        now: DateTime<Utc>,
+        tz: Tz,
+    ) {
+        let today = day_of(now, tz);
+        let first_current = current.properties.timeseries.first().map(|ts| ts.time);
+
+        if let Some(previous) = previous {
+            for entry in &previous.properties.timeseries {
+                let superseded = first_current.is_some_and(|first| entry.time < first);
+                let known = self.entries.iter().any(|e| e.time == entry.time);
+                if superseded && !known {
+                    self.entries.push(entry.clone());
+                }
+            }
+        }
+
+        self.entries.retain(|entry| {
+            day_of(entry.time, tz) == today && first_current.is_none_or(|first| entry.time < first)
+        });
+        self.entries.sort_by_key(|entry| entry.time);
+    }
+}
+
+/// Export today's and tomorrow's aggregates for a location, with days
+/// starting at midnight in `tz`
+pub fn update_daily_metrics(
+    location_name: &str,
+    earlier_today: &EarlierToday,
+    weather: &WeatherResponse,
+    now: DateTime<Utc>,
+    tz: Tz,
+) {
+    let today = day_of(now, tz);
+    let tomorrow = today + Duration::days(1);
+
+    let entries = || {
+        earlier_today
+            .entries
+            .iter()
+            .chain(weather.properties.timeseries.iter())
+    };
+
+    for (day, label) in [(today, "today"), (tomorrow, "tomorrow")] {
+        DailySummary::from_entries(entries().filter(|entry| day_of(entry.time, tz) == day))
+            .update_metrics(location_name, label);
+    }
+}
+
+fn day_of(time: DateTime<Utc>, tz: Tz) -> NaiveDate {
+    time.with_timezone(&tz).date_naive()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::Europe::Oslo;
+    use serde_json::json;
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    /// Hourly entries from `start`, with the given temperatures and 1 mm of
+    /// precipitation each
+    fn forecast(start: &str, temperatures: &[f64]) -> WeatherResponse {
+        let start = utc(start);
+        let timeseries: Vec<_> = temperatures
+            .iter()
+            .enumerate()
+            .map(|(hour, temp)| {
+                json!({
+                    "time": start + Duration::hours(hour as i64),
+                    "data": {
+                        "instant": { "details": { "air_temperature": temp } },
+                        "next_1_hours": { "details": { "precipitation_amount": 1.0 } }
+                    }
+                })
+            })
+            .collect();
+        serde_json::from_value(json!({ "properties": { "timeseries": timeseries } })).unwrap()
+    }
+
+    fn times(earlier_today: &EarlierToday) -> Vec<DateTime<Utc>> {
+        earlier_today
+            .entries
+            .iter()
+            .map(|entry| entry.time)
+            .collect()
+    }
+
+    fn daily(gauge: &GaugeVec, location_name: &str, day: &str) -> f64 {
+        gauge
+            .with_label_values(&location_labels(location_name, &[day]))
+            .get()
+    }
+
+    #[test]
+    fn keeps_superseded_hours_of_today() {
+        let mut earlier_today = EarlierToday::default();
+        let first = forecast("2026-01-15T06:00:00Z", &[1.0; 6]);
+        let second = forecast("2026-01-15T09:00:00Z", &[2.0; 6]);
+        let now = utc("2026-01-15T09:10:00Z");
+
+        earlier_today.retain(None, &first, now, Oslo);
+        assert!(times(&earlier_today).is_empty());
+        earlier_today.retain(Some(&first), &second, now, Oslo);
+        assert_eq!(
+            times(&earlier_today),
+            [
+                utc("2026-01-15T06:00:00Z"),
+                utc("2026-01-15T07:00:00Z"),
+                utc("2026-01-15T08:00:00Z")
+            ]
+        );
+        // The same forecast again adds nothing
+        earlier_today.retain(Some(&second), &second, now, Oslo);
+        assert_eq!(times(&earlier_today).len(), 3);
+    }
+
+    #[test]
+    fn rolls_over_at_local_midnight() {
+        let mut earlier_today = EarlierToday::default();
+        // 21:00 to 23:00 in Oslo
+        let evening = forecast("2026-01-15T20:00:00Z", &[1.0; 6]);
+        let night = forecast("2026-01-15T22:00:00Z", &[1.0; 6]);
+        let later = forecast("2026-01-15T23:00:00Z", &[1.0; 6]);
+
+        earlier_today.retain(Some(&evening), &night, utc("2026-01-15T22:10:00Z"), Oslo);
+        assert_eq!(times(&earlier_today).len(), 2);
+        // 00:10 in Oslo, though still the 15th in UTC
+        earlier_today.retain(Some(&night), &later, utc("2026-01-15T23:10:00Z"), Oslo);
+        assert!(times(&earlier_today).is_empty());
+
+        update_daily_metrics(
+            "DailyMidnight",
+            &earlier_today,
+            &later,
+            utc("2026-01-15T23:10:00Z"),
+            Oslo,
+        );
+        // The forecast's first hour is 00:00 on the 16th in Oslo
+        assert_eq!(daily(&DAILY_PRECIPITATION, "DailyMidnight", "today"), 6.0);
+    }
+
+    #[test]
+    fn today_still_covers_the_morning_after_a_restart() {
+        let morning = forecast("2026-01-15T06:00:00Z", &[-5.0, -4.0, -3.0, 0.0, 1.0, 2.0]);
+        let noon = forecast("2026-01-15T09:00:00Z", &[0.0, 1.0, 2.0, 3.0]);
+        let now = utc("2026-01-15T09:30:00Z");
+        let mut earlier_today = EarlierToday::default();
+        earlier_today.retain(Some(&morning), &noon, now, Oslo);
+
+        // Saved in the state file and loaded again, without a cached forecast
+        let json = serde_json::to_string(&earlier_today).unwrap();
+        let mut restored: EarlierToday = serde_json::from_str(&json).unwrap();
+        restored.retain(None, &noon, utc("2026-01-15T10:00:00Z"), Oslo);
+        assert_eq!(times(&restored), times(&earlier_today));
+
+        update_daily_metrics("DailyRestart", &restored, &noon, now, Oslo);
+        assert_eq!(daily(&DAILY_TEMPERATURE_MIN, "DailyRestart", "today"), -5.0);
+        assert_eq!(daily(&DAILY_TEMPERATURE_MAX, "DailyRestart", "today"), 3.0);
+        assert_eq!(daily(&DAILY_PRECIPITATION, "DailyRestart", "today"), 7.0);
+        assert_eq!(
+            daily(&DAILY_TEMPERATURE_MEAN, "DailyRestart", "today"),
+            (-5.0 - 4.0 - 3.0 + 0.0 + 1.0 + 2.0 + 3.0) / 7.0
+        );
+
+        // A restart on the next day drops them
+        restored.retain(None, &noon, utc("2026-01-16T09:00:00Z"), Oslo);
+        assert!(times(&restored).is_empty());
+    }
+
+    #[test]
+    fn days_follow_daylight_saving_time() {
+        // Clocks in Oslo go from 02:00 to 03:00 on 29 March 2026, so the
+        // day runs from 23:00Z on the 28th to 22:00Z on the 29th
+        let weather = forecast("2026-03-28T20:00:00Z", &[5.0; 60]);
+        update_daily_metrics(
+            "DailyDst",
+            &EarlierToday::default(),
+            &weather,
+            utc("2026-03-28T12:00:00Z"),
+            Oslo,
+        );
+        assert_eq!(daily(&DAILY_PRECIPITATION, "DailyDst", "today"), 3.0);
+        assert_eq!(daily(&DAILY_PRECIPITATION, "DailyDst", "tomorrow"), 23.0);
+
+        // And back from 03:00 to 02:00 on 25 October, a 25 hour day
+        let weather = forecast("2026-10-24T22:00:00Z", &[5.0; 30]);
+        update_daily_metrics(
+            "DailyDstEnd",
+            &EarlierToday::default(),
+            &weather,
+            utc("2026-10-25T12:00:00Z"),
+            Oslo,
+        );
+        assert_eq!(daily(&DAILY_PRECIPITATION, "DailyDstEnd", "today"), 25.0);
+    }
+
+    #[test]
+    fn missing_values_remove_the_series() {
+        let mut weather = forecast("2026-01-15T06:00:00Z", &[1.0; 3]);
+        update_daily_metrics(
+            "DailyMissing",
+            &EarlierToday::default(),
+            &weather,
+            utc("2026-01-15T06:00:00Z"),
+            Oslo,
+        );
+        assert_eq!(daily(&DAILY_PRECIPITATION, "DailyMissing", "today"), 3.0);
+
+        for entry in &mut weather.properties.timeseries {
+            entry.data.next_1_hours = None;
+        }
+        update_daily_metrics(
+            "DailyMissing",
+            &EarlierToday::default(),
+            &weather,
+            utc("2026-01-15T06:00:00Z"),
+            Oslo,
+        );
+        let labels = location_labels("DailyMissing", &["today"]);
+        assert!(DAILY_PRECIPITATION.remove_label_values(&labels).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use crate::metrics::{COOLING_DEGREE_DAYS, HEATING_DEGREE_DAYS, location_labels};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+// Don't integrate across gaps longer than this, e.g. while the exporter was down
+const MAX_SAMPLE_GAP_HOURS: i64 = 3;
+
+/// Base temperatures for heating and cooling degree days
+#[derive(Debug, Clone, Copy)]
+pub struct DegreeDaySettings {
+    pub heating_base: f64,
+    pub cooling_base: f64,
+}
+
+/// Heating and cooling degree days for the current day and month, integrated
+/// from temperature samples taken whenever the location is updated.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DegreeDays {
+    last_sample: Option<(DateTime<Utc>, f64)>,
+    day: Option<NaiveDate>,
+    heating_day: f64,
+    cooling_day: f64,
+    heating_month: f64,
+    cooling_month: f64,
+}
+
+impl DegreeDays {
+    /// Integrate from the previous sample up to `time`, splitting the interval
+    /// at local midnight in `tz` so each part counts towards the right day.
+    pub fn add_sample(
+        &mut self,
+        time: DateTime<Utc>,
+        temp: f64,
+        tz: Tz,
+        settings: &DegreeDaySettings,
+    ) {
+        // Already integrated past it, and rolling back to its day would reset
+        // the totals
+        if self
+            .last_sample
+            .is_some_and(|(prev_time, _)| time <= prev_time)
+        {
+            return;
+        }
+
+        if let Some((prev_time, prev_temp)) = self.last_sample
+            && time - prev_time <= Duration::hours(MAX_SAMPLE_GAP_HOURS)
+        {
+            let interpolate = |t: DateTime<Utc>| {
+                let fraction =
+                    (t - prev_time).num_seconds() as f64 / (time - prev_time).num_seconds() as f64;
+                prev_temp + (temp - prev_temp) * fraction
+            };
+
+            let mut start = (prev_time, prev_temp);
+            while start.0 < time {
+                let next_day = day_of(start.0, tz) + Duration::days(1);
+                let end_time = day_start(next_day, tz).min(time);
+                let end = (end_time, interpolate(end_time));
+
+                self.roll_over(day_of(start.0, tz));
+                self.accumulate(start, end, settings);
+                start = end;
+            }
+        }
+
+        self.roll_over(day_of(time, tz));
+        self.last_sample = Some((time, temp));
+    }
+
+    fn accumulate(
+        &mut self,
+        (start_time, start_temp): (DateTime<Utc>, f64),
+        (end_time, end_temp): (DateTime<Utc>, f64),
+        settings: &DegreeDaySettings,
+    ) {
+        let days = (end_time - start_time).num_seconds() as f64 / 86_400.0;
+        let mean = (start_temp + end_temp) / 2.0;
+
+        let heating = (settings.heating_base - mean).max(0.0) * days;
+        let cooling = (mean - settings.cooling_base).max(0.0) * days;
+
+        self.heating_day += heating;
+        self.heating_month += heating;
+        self.cooling_day += cooling;
+        self.cooling_month += cooling;
+    }
+
+    /// Reset the daily (and if needed monthly) totals when `date` is a new day
+    fn roll_over(&mut self, date: NaiveDate) {
+        if self.day == Some(date) {
+            return;
+        }
+
+        let same_month = self
+            .day
+            .is_some_and(|day| day.year() == date.year() && day.month() == date.month());
+        if !same_month {
+            self.heating_month = 0.0;
+            self.cooling_month = 0.0;
+        }
+
+        self.heating_day = 0.0;
+        self.cooling_day = 0.0;
+        self.day = Some(date);
+    }
+
+    pub fn update_metrics(&self, location_name: &str) {
+        HEATING_DEGREE_DAYS
+            .with_label_values(&location_labels(location_name, &["day"]))
+            .set(self.heating_day);
+        HEATING_DEGREE_DAYS
+            .with_label_values(&location_labels(location_name, &["month"]))
+            .set(self.heating_month);
+        COOLING_DEGREE_DAYS
+            .with_label_values(&location_labels(location_name, &["day"]))
+            .set(self.cooling_day);
+        COOLING_DEGREE_DAYS
+            .with_label_values(&location_labels(location_name, &["month"]))
+            .set(self.cooling_month);
+    }
+}
+
+fn day_of(time: DateTime<Utc>, tz: Tz) -> NaiveDate {
+    time.with_timezone(&tz).date_naive()
+}
+
+fn day_start(date: NaiveDate, tz: Tz) -> DateTime<Utc> {
+    let midnight = date.and_hms_opt(0, 0, 0).expect("midnight is a valid time");
+    // Some zones skip midnight when DST starts, the day then begins an hour later
+    tz.from_local_datetime(&midnight)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(midnight + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|start| start.with_timezone(&Utc))
+        .unwrap_or_else(|| midnight.and_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: DegreeDaySettings = DegreeDaySettings {
+        heating_base: 18.0,
+        cooling_base: 22.0,
+    };
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn integrates_interpolated_temperature() {
+        let mut dd = DegreeDays::default();
+        dd.add_sample(utc("2026-07-10T10:00:00Z"), 20.0, Tz::UTC, &SETTINGS);
+        assert_close(dd.heating_day, 0.0);
+        // Mean 24°C over two hours
+        dd.add_sample(utc("2026-07-10T12:00:00Z"), 28.0, Tz::UTC, &SETTINGS);
+        assert_close(dd.cooling_day, 2.0 * 2.0 / 24.0);
+        assert_close(dd.cooling_month, dd.cooling_day);
+        assert_close(dd.heating_day, 0.0);
+    }
+
+    #[test]
+    fn splits_at_local_midnight() {
+        let oslo = chrono_tz::Europe::Oslo;
+        let mut dd = DegreeDays::default();
+        // 23:00 and 01:00 in Oslo, at a constant 8°C
+        dd.add_sample(utc("2026-01-14T22:00:00Z"), 8.0, oslo, &SETTINGS);
+        dd.add_sample(utc("2026-01-15T00:00:00Z"), 8.0, oslo, &SETTINGS);
+        assert_eq!(dd.day, NaiveDate::from_ymd_opt(2026, 1, 15));
+        assert_close(dd.heating_day, 10.0 / 24.0);
+        assert_close(dd.heating_month, 20.0 / 24.0);
+    }
+
+    #[test]
+    fn resets_month_on_the_first() {
+        let oslo = chrono_tz::Europe::Oslo;
+        let mut dd = DegreeDays::default();
+        dd.add_sample(utc("2026-01-31T12:00:00Z"), 8.0, oslo, &SETTINGS);
+        dd.add_sample(utc("2026-01-31T14:00:00Z"), 8.0, oslo, &SETTINGS);
+        assert_close(dd.heating_month, 20.0 / 24.0);
+        dd.add_sample(utc("2026-01-31T21:30:00Z"), 8.0, oslo, &SETTINGS);
+        // Not integrated, the gap is too long
+        assert_close(dd.heating_month, 20.0 / 24.0);
+        dd.add_sample(utc("2026-01-31T23:30:00Z"), 8.0, oslo, &SETTINGS);
+        assert_eq!(dd.day, NaiveDate::from_ymd_opt(2026, 2, 1));
+        // 22:30 to 00:30 local, half an hour of it in February
+        assert_close(dd.heating_day, 10.0 * 0.5 / 24.0);
+        assert_close(dd.heating_month, 10.0 * 0.5 / 24.0);
+    }
+
+    #[test]
+    fn day_with_dst_start_has_23_hours() {
+        let oslo = chrono_tz::Europe::Oslo;
+        let mut dd = DegreeDays::default();
+        // Local midnight on 29 March is 23:00 UTC, clocks skip 02:00 to 03:00
+        let mut time = utc("2026-03-28T23:00:00Z");
+        while time <= utc("2026-03-29T21:00:00Z") {
+            dd.add_sample(time, 8.0, oslo, &SETTINGS);
+            time += Duration::hours(1);
+        }
+        assert_eq!(dd.day, NaiveDate::from_ymd_opt(2026, 3, 29));
+        // 00:00 to 23:00 local is 22 hours
+        assert_close(dd.heating_day, 10.0 * 22.0 / 24.0);
+        assert_eq!(
+            day_start(NaiveDate::from_ymd_opt(2026, 3, 30).unwrap(), oslo),
+            utc("2026-03-29T22:00:00Z")
+        );
+    }
+
+    #[test]
+    fn day_starts_after_skipped_midnight() {
+        // Havana moves from 00:00 to 01:00 when DST starts
+        let havana = chrono_tz::America::Havana;
+        let date = NaiveDate::from_ymd_opt(2026, 3, 8).unwrap();
+        assert!(
+            havana
+                .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+                .earliest()
+                .is_none()
+        );
+        assert_eq!(day_start(date, havana), utc("2026-03-08T05:00:00Z"));
+
+        let mut dd = DegreeDays::default();
+        dd.add_sample(utc("2026-03-08T04:00:00Z"), 8.0, havana, &SETTINGS);
+        dd.add_sample(utc("2026-03-08T06:00:00Z"), 8.0, havana, &SETTINGS);
+        assert_eq!(dd.day, Some(date));
+        assert_close(dd.heating_day, 10.0 / 24.0);
+    }
+
+    #[test]
+    fn ignores_samples_out_of_order() {
+        let mut dd = DegreeDays::default();
+        dd.add_sample(utc("2026-01-15T12:00:00Z"), 8.0, Tz::UTC, &SETTINGS);
+        dd.add_sample(utc("2026-01-15T11:00:00Z"), 8.0, Tz::UTC, &SETTINGS);
+        assert_close(dd.heating_day, 0.0);
+        // Integration carries on from the latest sample
+        dd.add_sample(utc("2026-01-15T13:00:00Z"), 8.0, Tz::UTC, &SETTINGS);
+        assert_close(dd.heating_day, 10.0 / 24.0);
+        dd.add_sample(utc("2026-01-15T13:00:00Z"), 20.0, Tz::UTC, &SETTINGS);
+        assert_close(dd.heating_day, 10.0 / 24.0);
+    }
+
+    #[test]
+    fn ignores_samples_from_before_midnight() {
+        let mut dd = DegreeDays::default();
+        dd.add_sample(utc("2026-01-31T23:00:00Z"), 8.0, Tz::UTC, &SETTINGS);
+        dd.add_sample(utc("2026-02-01T01:00:00Z"), 8.0, Tz::UTC, &SETTINGS);
+        assert_close(dd.heating_day, 10.0 / 24.0);
+        assert_close(dd.heating_month, 10.0 / 24.0);
+
+        // A late sample from the previous day and month leaves the new
+        // day's and month's totals alone
+        dd.add_sample(utc("2026-01-31T23:30:00Z"), 8.0, Tz::UTC, &SETTINGS);
+        assert_eq!(dd.day, NaiveDate::from_ymd_opt(2026, 2, 1));
+        assert_close(dd.heating_day, 10.0 / 24.0);
+        assert_close(dd.heating_month, 10.0 / 24.0);
+
+        dd.add_sample(utc("2026-02-01T02:00:00Z"), 8.0, Tz::UTC, &SETTINGS);
+        assert_close(dd.heating_day, 10.0 * 2.0 / 24.0);
+        assert_close(dd.heating_month, 10.0 * 2.0 / 24.0);
+    }
+}
@@ -0,0 +1,98 @@
+//! Liveness and readiness probes on `/health/live` and `/health/ready`.
+
+use crate::AppState;
+use anyhow::Result;
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    /// Locations with a forecast that is current, or was refreshed on the
+    /// last attempt
+    fresh: usize,
+    locations: usize,
+    /// Fresh locations needed to be ready
+    required: usize,
+    failing: Vec<FailingLocation>,
+}
+
+#[derive(Serialize)]
+struct FailingLocation {
+    name: String,
+    /// Why the last update failed, or why there is nothing to serve
+    error: String,
+    /// `null` if no update succeeded yet
+    last_success: Option<DateTime<Utc>>,
+}
+
+/// Check `--ready-fraction`
+pub fn validate_fraction(fraction: f64) -> Result<()> {
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err(anyhow::anyhow!(
+            "--ready-fraction must be between 0 and 1, got {}",
+            fraction
+        ));
+    }
+    Ok(())
+}
+
+/// `GET /health/live`, OK as long as the process serves requests
+pub async fn live_handler() -> Response {
+    Json(serde_json::json!({ "status": "ok" })).into_response()
+}
+
+/// `GET /health/ready`, OK once at least one location, and at least
+/// `fraction` of them, have fresh data
+pub async fn ready_handler(State(state): State<AppState>, fraction: f64) -> Response {
+    let location_names = state.location_names().await;
+    let locations = state.locations.read().await;
+
+    let mut fresh = 0;
+    let mut failing = Vec::new();
+    for location_name in &location_names {
+        let Some(data) = locations.get(location_name) else {
+            continue;
+        };
+        // A forecast that expired moments ago is fresh until the background
+        // task's next check, unless refreshing it failed
+        let error = match (&data.cache.data, &data.status.last_error) {
+            (Some(_), _) if !data.cache.is_expired() => None,
+            (Some(_), None) => None,
+            (_, Some(error)) => Some(error.clone()),
+            (None, None) => Some("No forecast fetched yet".to_string()),
+        };
+        match error {
+            None => fresh += 1,
+            Some(error) => failing.push(FailingLocation {
+                name: location_name.clone(),
+                error,
+                last_success: data.status.last_success,
+            }),
+        }
+    }
+
+    let total = fresh + failing.len();
+    let required = ((total as f64 * fraction).ceil() as usize).clamp(1, total.max(1));
+    let ready = fresh >= required;
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let body = Readiness {
+        ready,
+        fresh,
+        locations: total,
+        required,
+        failing,
+    };
+    (status, Json(body)).into_response()
+}
@@ -0,0 +1,292 @@
+//! InfluxDB line protocol, served on `/metrics.influx` and optionally written
+//! to an InfluxDB v2 bucket.
+
+use crate::AppState;
+use crate::comfort;
+use crate::metrics::custom_labels;
+use crate::provider::{Location, TimeSeries};
+use anyhow::Result;
+use axum::{extract::State, http::header, response::IntoResponse};
+use chrono::Utc;
+use std::time::Duration;
+use tokio::sync::{Notify, watch};
+use tracing::{debug, error, info};
+
+const MEASUREMENT: &str = "weather";
+
+#[derive(Debug, Clone)]
+pub struct InfluxSettings {
+    pub url: String,
+    pub org: String,
+    pub bucket: String,
+    pub token: Option<String>,
+    /// Minimum time between two writes, until changed with
+    /// [`InfluxWriter::set_interval`]
+    pub interval: Duration,
+}
+
+/// Writes current values to InfluxDB after updates, at most once per interval
+pub struct InfluxWriter {
+    settings: InfluxSettings,
+    interval: watch::Sender<Duration>,
+    client: reqwest::Client,
+    pending: Notify,
+}
+
+impl InfluxWriter {
+    pub fn new(settings: InfluxSettings, client: reqwest::Client) -> Self {
+        Self {
+            interval: watch::Sender::new(settings.interval),
+            settings,
+            client,
+            pending: Notify::new(),
+        }
+    }
+
+    /// Change the minimum time between writes, e.g. on a config reload
+    pub fn set_interval(&self, interval: Duration) {
+        if self.interval.send_replace(interval) != interval {
+            info!("Writing to InfluxDB at most every {}s", interval.as_secs());
+        }
+    }
+
+    /// Request a write. Requests made while waiting out the interval are
+    /// folded into the next write.
+    pub fn notify(&self) {
+        self.pending.notify_one();
+    }
+
+    /// Write on request until `stop` changes, then write once more so the
+    /// last update isn't lost
+    pub async fn run(&self, state: AppState, mut stop: watch::Receiver<bool>) {
+        loop {
+            tokio::select! {
+                _ = self.pending.notified() => {}
+                _ = stop.changed() => break,
+            }
+            self.write_logged(&state).await;
+            tokio::select! {
+                _ = tokio::time::sleep(*self.interval.borrow()) => {}
+                _ = stop.changed() => break,
+            }
+        }
+        self.write_logged(&state).await;
+    }
+
+    async fn write_logged(&self, state: &AppState) {
+        match self.write(state).await {
+            Ok(()) => debug!("Wrote current values to InfluxDB"),
+            Err(e) => error!("Failed to write to InfluxDB: {:#}", e),
+        }
+    }
+
+    async fn write(&self, state: &AppState) -> Result<()> {
+        let url = format!(
+            "{}/api/v2/write?org={}&bucket={}&precision=ns",
+            self.settings.url.trim_end_matches('/'),
+            urlencoding::encode(&self.settings.org),
+            urlencoding::encode(&self.settings.bucket)
+        );
+
+        let mut request = self
+            .client
+            .post(url)
+            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(render(state).await);
+        if let Some(token) = &self.settings.token {
+            request = request.header(header::AUTHORIZATION, format!("Token {}", token));
+        }
+
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+pub async fn handler(State(state): State<AppState>) -> impl IntoResponse {
+    // Update metrics before serving them, like /metrics
+    state.update_all_metrics().await;
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        render(&state).await,
+    )
+}
+
+/// One line per location with the forecast entry closest to now
+async fn render(state: &AppState) -> String {
+    let now = Utc::now();
+    let location_names = state.location_names().await;
+    let locations = state.locations.read().await;
+
+    let mut body = String::new();
+    for location_name in &location_names {
+        let Some(data) = locations.get(location_name) else {
+            continue;
+        };
+        let (Some(location), Some(weather)) = (&data.location, &data.cache.data) else {
+            continue;
+        };
+        if let Some(line) = weather
+            .closest(now)
+            .and_then(|current| line(location_name, location, current))
+        {
+            body.push_str(&line);
+            body.push('\n');
+        }
+    }
+    body
+}
+
+/// `weather,location=...,latitude=...,longitude=... temperature=...,... <time>`,
+/// stamped with the forecast time
+fn line(location_name: &str, location: &Location, entry: &TimeSeries) -> Option<String> {
+    let details = &entry.data.instant.details;
+    let precipitation = entry
+        .data
+        .next_1_hours
+        .as_ref()
+        .and_then(|next| next.details.precipitation_amount);
+    let feels_like = details
+        .air_temperature
+        .map(|temp| comfort::feels_like(temp, details.relative_humidity, details.wind_speed));
+    let dew_point = details
+        .air_temperature
+        .zip(details.relative_humidity)
+        .and_then(|(temp, humidity)| comfort::dew_point(temp, humidity));
+
+    let fields: Vec<String> = [
+        ("temperature", details.air_temperature),
+        ("feels_like", feels_like),
+        ("dew_point", dew_point),
+        ("humidity", details.relative_humidity),
+        ("wind_speed", details.wind_speed),
+        ("wind_gust", details.wind_speed_of_gust),
+        ("wind_direction", details.wind_from_direction),
+        ("pressure", details.air_pressure_at_sea_level),
+        ("precipitation", precipitation),
+        ("cloud_coverage", details.cloud_area_fraction),
+        ("uv_index", details.ultraviolet_index_clear_sky),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| {
+        value
+            .filter(|v| v.is_finite())
+            .map(|v| format!("{}={}", escape(key), v))
+    })
+    .collect();
+    // A point needs at least one field
+    if fields.is_empty() {
+        return None;
+    }
+
+    let (lat, lon) = location.position.rounded();
+    let mut tags = vec![
+        ("location".to_string(), location_name.to_string()),
+        ("latitude".to_string(), lat.to_string()),
+        ("longitude".to_string(), lon.to_string()),
+    ];
+    tags.extend(
+        custom_labels(location_name)
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value)),
+    );
+    // InfluxDB prefers tags sorted by key
+    tags.sort();
+
+    let tags: String = tags
+        .iter()
+        .map(|(key, value)| format!(",{}={}", escape(key), escape(value)))
+        .collect();
+    Some(format!(
+        "{}{} {} {}",
+        MEASUREMENT,
+        tags,
+        fields.join(","),
+        entry.time.timestamp_nanos_opt()?
+    ))
+}
+
+/// Escape a tag key, tag value or field key. Line breaks would end the point
+/// and can't be escaped, so they become spaces.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ',' | '=' | ' ' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' | '\r' => escaped.push_str("\\ "),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn location() -> Location {
+        serde_json::from_value(json!({
+            "id": "1-72837",
+            "name": "Oslo",
+            "position": { "lat": 59.91273, "lon": 10.74609 },
+            "elevation": 10.0,
+            "timeZone": "Europe/Oslo",
+            "country": null,
+            "region": null
+        }))
+        .unwrap()
+    }
+
+    fn entry(details: serde_json::Value) -> TimeSeries {
+        serde_json::from_value(json!({
+            "time": "2026-01-15T12:00:00Z",
+            "data": {
+                "instant": { "details": details },
+                "next_1_hours": { "details": { "precipitation_amount": 0.4 } }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn escapes_special_characters() {
+        assert_eq!(escape("New York"), "New\\ York");
+        assert_eq!(escape("a,b=c"), "a\\,b\\=c");
+        assert_eq!(escape("C:\\"), "C:\\\\");
+        assert_eq!(escape("two\nlines\r"), "two\\ lines\\ ");
+        assert_eq!(escape("Zürich"), "Zürich");
+    }
+
+    #[test]
+    fn line_has_sorted_tags_fields_and_timestamp() {
+        let line = line(
+            "Oslo, Norway",
+            &location(),
+            &entry(json!({ "air_temperature": -2.5, "wind_speed": 3.0 })),
+        )
+        .unwrap();
+        assert!(
+            line.starts_with(
+                "weather,latitude=59.9127,location=Oslo\\,\\ Norway,longitude=10.7461 temperature=-2.5,feels_like="
+            ),
+            "{}",
+            line
+        );
+        assert!(line.contains(",wind_speed=3,"), "{}", line);
+        assert!(line.contains(",precipitation=0.4 "), "{}", line);
+        assert!(!line.contains("humidity"), "{}", line);
+        assert!(line.ends_with(" 1768478400000000000"), "{}", line);
+        assert!(!line.contains('\n'));
+    }
+
+    #[test]
+    fn line_without_values_is_skipped() {
+        let mut entry = entry(json!({ "air_temperature": f64::NAN }));
+        entry.data.next_1_hours = None;
+        assert_eq!(line("Oslo", &location(), &entry), None);
+    }
+}
@@ -0,0 +1,333 @@
+//! Sockets the HTTP server listens on: TCP addresses, Unix sockets and
+//! sockets passed by systemd.
+
+use crate::web::TlsListener;
+use anyhow::{Context, Result};
+use axum::{Router, serve::Listener};
+use std::fmt;
+use std::io::ErrorKind;
+use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::net::{TcpListener, UnixListener};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+
+/// First file descriptor systemd passes, see sd_listen_fds(3)
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// A `--listen-address`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddress {
+    /// `host:port`, `[::1]:port` or `:port` for all IPv4 interfaces
+    Tcp(String),
+    /// `unix:/path.sock`
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("unix: needs a socket path".to_string());
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+
+        let Some((host, port)) = s.rsplit_once(':') else {
+            return Err(format!("{} has no port, expected host:port", s));
+        };
+        port.parse::<u16>()
+            .map_err(|_| format!("Invalid port in {}", s))?;
+        if host.contains(':') && !(host.starts_with('[') && host.ends_with(']')) {
+            return Err(format!("IPv6 addresses need brackets, e.g. [::]:{}", port));
+        }
+        // Like Go's net.Listen, which Prometheus exporters use
+        let host = if host.is_empty() { "0.0.0.0" } else { host };
+        Ok(Self::Tcp(format!("{}:{}", host, port)))
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "{}", address),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A socket ready to serve on
+pub enum Bound {
+    Tcp(TcpListener),
+    /// The path is removed again on shutdown, unless systemd created the
+    /// socket
+    Unix(UnixListener, Option<PathBuf>),
+}
+
+impl fmt::Display for Bound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => write!(f, "{}", addr),
+                Err(_) => write!(f, "TCP socket"),
+            },
+            Self::Unix(listener, _) => match listener
+                .local_addr()
+                .ok()
+                .and_then(|addr| addr.as_pathname().map(|path| path.display().to_string()))
+            {
+                Some(path) => write!(f, "unix:{}", path),
+                None => write!(f, "Unix socket"),
+            },
+        }
+    }
+}
+
+impl Bound {
+    /// Serve `app` on this socket until `stop` changes
+    pub fn spawn(
+        self,
+        tasks: &mut JoinSet<()>,
+        app: Router,
+        tls: Option<TlsAcceptor>,
+        stop: watch::Receiver<bool>,
+    ) {
+        match (self, tls) {
+            (Self::Tcp(listener), None) => {
+                tasks.spawn(serve(listener, app, stop));
+            }
+            (Self::Tcp(listener), Some(acceptor)) => {
+                tasks.spawn(serve(TlsListener::new(listener, acceptor), app, stop));
+            }
+            (Self::Unix(listener, path), tls) => {
+                tasks.spawn(async move {
+                    match tls {
+                        Some(acceptor) => {
+                            serve(TlsListener::new(listener, acceptor), app, stop).await
+                        }
+                        None => serve(listener, app, stop).await,
+                    }
+                    if let Some(path) = path {
+                        let _ = std::fs::remove_file(path);
+                    }
+                });
+            }
+        }
+    }
+}
+
+async fn serve<L>(listener: L, app: Router, mut stop: watch::Receiver<bool>)
+where
+    L: Listener,
+    L::Addr: fmt::Debug,
+{
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            let _ = stop.changed().await;
+        })
+        .await
+        .expect("Failed to start server");
+}
+
+/// Bind `addresses`, or take the sockets systemd passed instead
+pub async fn bind(addresses: &[ListenAddress]) -> Result<Vec<Bound>> {
+    if let Some(sockets) = systemd_sockets()? {
+        info!("Using {} sockets passed by systemd", sockets.len());
+        return Ok(sockets);
+    }
+
+    let mut bound = Vec::new();
+    for address in addresses {
+        bound.push(match address {
+            ListenAddress::Tcp(address) => Bound::Tcp(
+                TcpListener::bind(address)
+                    .await
+                    .with_context(|| format!("Failed to listen on {}", address))?,
+            ),
+            ListenAddress::Unix(path) => {
+                if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+                    remove_stale_socket(path)?;
+                }
+                let listener = UnixListener::bind(path)
+                    .with_context(|| format!("Failed to listen on {}", path.display()))?;
+                Bound::Unix(listener, Some(path.clone()))
+            }
+        });
+    }
+    Ok(bound)
+}
+
+/// Remove a socket left behind by a previous run that didn't shut down. One
+/// that still accepts connections belongs to a running process and is kept.
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(anyhow::anyhow!(
+            "{} is in use by another process",
+            path.display()
+        )),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+            warn!("Removing stale socket {}", path.display());
+            std::fs::remove_file(path)
+                .with_context(|| format!("Failed to remove {}", path.display()))
+        }
+        Err(e) => {
+            Err(e).with_context(|| format!("Failed to check whether {} is in use", path.display()))
+        }
+    }
+}
+
+/// Sockets from systemd socket activation, `None` if the exporter wasn't
+/// started that way
+fn systemd_sockets() -> Result<Option<Vec<Bound>>> {
+    let Ok(pid) = std::env::var("LISTEN_PID") else {
+        return Ok(None);
+    };
+    // Meant for another process, e.g. a shell that started the exporter
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+        return Ok(None);
+    }
+    let count: RawFd = std::env::var("LISTEN_FDS")
+        .context("LISTEN_PID is set but LISTEN_FDS isn't")?
+        .parse()
+        .context("Invalid LISTEN_FDS")?;
+
+    let mut sockets = Vec::new();
+    for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count {
+        // SAFETY: systemd passes ownership of these descriptors, and
+        // nothing else uses them
+        let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+        // getsockname only returns a Unix address for Unix sockets
+        let bound = if unix.local_addr().is_ok() {
+            unix.set_nonblocking(true)?;
+            Bound::Unix(UnixListener::from_std(unix)?, None)
+        } else {
+            // SAFETY: as above, ownership moves from the Unix listener
+            let tcp = unsafe { std::net::TcpListener::from_raw_fd(unix.into_raw_fd()) };
+            tcp.local_addr()
+                .with_context(|| format!("File descriptor {} is not a TCP or Unix socket", fd))?;
+            tcp.set_nonblocking(true)?;
+            Bound::Tcp(TcpListener::from_std(tcp)?)
+        };
+        sockets.push(bound);
+    }
+    if sockets.is_empty() {
+        return Err(anyhow::anyhow!("systemd passed no sockets"));
+    }
+    Ok(Some(sockets))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<ListenAddress, String> {
+        s.parse()
+    }
+
+    #[test]
+    fn parses_tcp_addresses() {
+        assert_eq!(
+            parse("127.0.0.1:9090"),
+            Ok(ListenAddress::Tcp("127.0.0.1:9090".to_string()))
+        );
+        assert_eq!(
+            parse("localhost:9090"),
+            Ok(ListenAddress::Tcp("localhost:9090".to_string()))
+        );
+        assert_eq!(
+            parse(":9090"),
+            Ok(ListenAddress::Tcp("0.0.0.0:9090".to_string()))
+        );
+        assert_eq!(
+            parse("[::]:9090"),
+            Ok(ListenAddress::Tcp("[::]:9090".to_string()))
+        );
+        assert_eq!(
+            parse("[::1]:0"),
+            Ok(ListenAddress::Tcp("[::1]:0".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_tcp_addresses() {
+        assert!(parse("127.0.0.1").unwrap_err().contains("has no port"));
+        assert!(
+            parse("127.0.0.1:http")
+                .unwrap_err()
+                .contains("Invalid port")
+        );
+        assert!(
+            parse("127.0.0.1:65536")
+                .unwrap_err()
+                .contains("Invalid port")
+        );
+        assert!(parse("::1:9090").unwrap_err().contains("need brackets"));
+        assert!(parse("[::1]").is_err());
+    }
+
+    #[test]
+    fn parses_unix_sockets() {
+        assert_eq!(
+            parse("unix:/run/weather.sock"),
+            Ok(ListenAddress::Unix(PathBuf::from("/run/weather.sock")))
+        );
+        assert_eq!(
+            parse("unix:relative.sock"),
+            Ok(ListenAddress::Unix(PathBuf::from("relative.sock")))
+        );
+        assert!(parse("unix:").is_err());
+    }
+
+    #[test]
+    fn displays_as_parsed() {
+        for address in ["127.0.0.1:9090", "[::1]:9090", "unix:/run/weather.sock"] {
+            assert_eq!(parse(address).unwrap().to_string(), address);
+        }
+    }
+
+    #[tokio::test]
+    async fn binds_tcp_and_unix_sockets() {
+        let dir = std::env::temp_dir().join(format!("weather-listen-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("test.sock");
+        // A stale socket from an earlier run is replaced
+        drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+
+        let bound = bind(&[
+            parse("127.0.0.1:0").unwrap(),
+            ListenAddress::Unix(socket.clone()),
+        ])
+        .await
+        .unwrap();
+        assert!(matches!(bound[0], Bound::Tcp(_)));
+        assert!(bound[0].to_string().starts_with("127.0.0.1:"));
+        assert_eq!(bound[1].to_string(), format!("unix:{}", socket.display()));
+
+        drop(bound);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_sockets_in_use() {
+        let dir = std::env::temp_dir().join(format!("weather-listen-used-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("used.sock");
+        let running = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+
+        let Err(e) = bind(&[ListenAddress::Unix(socket.clone())]).await else {
+            panic!("A socket in use was taken over");
+        };
+        assert!(e.to_string().contains("in use"), "{:#}", e);
+        // Still reachable for whoever owns it
+        std::os::unix::net::UnixStream::connect(&socket).unwrap();
+
+        drop(running);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
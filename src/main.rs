@@ -1,85 +1,71 @@
-use anyhow::Result;
-use axum::{Router, extract::State, http::StatusCode, response::IntoResponse, routing::get};
-use chrono::{DateTime, Utc};
-use clap::Parser;
-use lazy_static::lazy_static;
-use prometheus::{Encoder, GaugeVec, IntGaugeVec, Opts, Registry, TextEncoder};
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
-use tokio::sync::RwLock;
+use accuracy::{AccuracySettings, ForecastTracker};
+use anyhow::{Context, Result};
+use axum::{
+    Router,
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+    routing::{get, post},
+};
+use chrono::{Offset, Utc};
+use chrono_tz::Tz;
+use clap::{Parser, Subcommand, ValueEnum};
+use config::{ConfigFile, LocationConfig, LocationEntry};
+use daily::EarlierToday;
+use degree_days::{DegreeDaySettings, DegreeDays};
+use influx::{InfluxSettings, InfluxWriter};
+use metrics::*;
+use mqtt::{MqttPublisher, MqttSettings};
+use otlp::{OtlpExporter, OtlpProtocol, OtlpSettings};
+use persist::{PersistedState, StateFile};
+use provider::{Location, LocationQuery, ProviderKind, WeatherCache, WeatherProvider};
+use push::{PushMode, PushSettings, Pusher};
+use station::Station;
+use status::FetchStatus;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use textfile::TextfileWriter;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::{RwLock, watch};
+use tokio::task::JoinSet;
+use tracing::level_filters::LevelFilter;
 use tracing::{debug, error, info, warn};
-
-// Prometheus metrics
-lazy_static! {
-    static ref TEMPERATURE: GaugeVec = GaugeVec::new(
-        Opts::new("weather_temperature_celsius", "Temperature in Celsius"),
-        &["location", "latitude", "longitude"]
-    )
-    .expect("metric can be created");
-    static ref HUMIDITY: GaugeVec = GaugeVec::new(
-        Opts::new("weather_humidity_percent", "Relative humidity percentage"),
-        &["location", "latitude", "longitude"]
-    )
-    .expect("metric can be created");
-    static ref WIND_SPEED: GaugeVec = GaugeVec::new(
-        Opts::new("weather_wind_speed_mps", "Wind speed in meters per second"),
-        &["location", "latitude", "longitude"]
-    )
-    .expect("metric can be created");
-    static ref WIND_DIRECTION: GaugeVec = GaugeVec::new(
-        Opts::new(
-            "weather_wind_direction_degrees",
-            "Wind direction in degrees"
-        ),
-        &["location", "latitude", "longitude"]
-    )
-    .expect("metric can be created");
-    static ref PRESSURE: GaugeVec = GaugeVec::new(
-        Opts::new("weather_pressure_hpa", "Air pressure in hectopascals"),
-        &["location", "latitude", "longitude"]
-    )
-    .expect("metric can be created");
-    static ref PRECIPITATION: GaugeVec = GaugeVec::new(
-        Opts::new("weather_precipitation_mm", "Precipitation in millimeters"),
-        &["location", "latitude", "longitude"]
-    )
-    .expect("metric can be created");
-    static ref CLOUD_COVERAGE: GaugeVec = GaugeVec::new(
-        Opts::new(
-            "weather_cloud_coverage_percent",
-            "Cloud coverage percentage"
-        ),
-        &["location", "latitude", "longitude"]
-    )
-    .expect("metric can be created");
-    static ref UV_INDEX: GaugeVec = GaugeVec::new(
-        Opts::new("weather_uv_index", "UV index"),
-        &["location", "latitude", "longitude"]
-    )
-    .expect("metric can be created");
-    static ref WEATHER_FETCH_SUCCESS: IntGaugeVec = IntGaugeVec::new(
-        Opts::new(
-            "weather_fetch_success",
-            "Whether the last weather fetch was successful"
-        ),
-        &["location"]
-    )
-    .expect("metric can be created");
-    static ref WEATHER_CACHE_HITS: IntGaugeVec = IntGaugeVec::new(
-        Opts::new(
-            "weather_cache_hits_total",
-            "Number of times cached data was used"
-        ),
-        &["location"]
-    )
-    .expect("metric can be created");
-    static ref WEATHER_API_CALLS: IntGaugeVec = IntGaugeVec::new(
-        Opts::new("weather_api_calls_total", "Total number of API calls made"),
-        &["location"]
-    )
-    .expect("metric can be created");
-    static ref REGISTRY: Registry = Registry::new();
-}
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::prelude::*;
+
+mod accuracy;
+mod admin;
+mod api;
+mod auth;
+mod comfort;
+mod commands;
+mod config;
+mod daily;
+mod degree_days;
+mod health;
+mod influx;
+mod listen;
+mod metrics;
+mod mqtt;
+mod openmetrics;
+mod otlp;
+mod persist;
+mod provider;
+mod push;
+mod reload;
+mod station;
+mod status;
+mod textfile;
+mod timezones;
+mod web;
+
+/// How often the background task checks for expired caches, unless the
+/// config file says otherwise
+const UPDATE_INTERVAL: Duration = Duration::from_secs(60);
 
 /// Weather exporter for Prometheus
 ///
@@ -101,9 +87,18 @@ lazy_static! {
     export WEATHER_USER_AGENT='my-app/1.0 github.com/user/repo'
     weather-exporter -l London,Paris
 
+    # Use Open-Meteo for one of the locations:
+    weather-exporter -u 'my-app/1.0' -l Oslo,open-meteo:Tokyo
+
+    # Locations with custom labels from a config file:
+    weather-exporter -u 'my-app/1.0' --config-file config.yml
+
     # Custom port:
     weather-exporter -u 'my-app/1.0' -l Oslo -p 8080
 
+    # List all places called Lund, to pin one in the config file:
+    weather-exporter -u 'my-app/1.0' locations search Lund
+
 USER-AGENT FORMAT:
     The User-Agent must uniquely identify your application (required by yr.no).
     Format: <application>/<version> <contact>
@@ -123,6 +118,16 @@ struct Args {
     )]
     user_agent: String,
 
+    /// Configuration file
+    #[arg(
+        short = 'c',
+        long,
+        env = "WEATHER_CONFIG_FILE",
+        value_name = "PATH",
+        help = "YAML file with locations, their labels and intervals, reloaded when it changes or on SIGHUP. Label names that no location had at startup need a restart."
+    )]
+    config_file: Option<PathBuf>,
+
     /// Comma-separated list of locations to monitor
     #[arg(
         short = 'l',
@@ -135,6 +140,385 @@ struct Args {
     )]
     locations: Vec<String>,
 
+    /// Default weather data provider
+    #[arg(
+        long,
+        env = "WEATHER_PROVIDER",
+        default_value_t = ProviderKind::Yr,
+        value_name = "PROVIDER",
+        help = "Provider for locations without a 'provider:' prefix"
+    )]
+    provider: ProviderKind,
+
+    /// Weather stations allowed to push observations
+    #[arg(
+        long,
+        env = "WEATHER_STATIONS",
+        value_delimiter = ',',
+        value_name = "ID=LOCATION[:PASSWORD]",
+        help = "Stations accepted for upload, mapped to a location (e.g., 'KOSLO12=Oslo:secret')"
+    )]
+    stations: Vec<String>,
+
+    /// Lead times to track forecast accuracy for
+    #[arg(
+        long,
+        env = "WEATHER_ACCURACY_LEAD_HOURS",
+        default_value = "1,3,6,12,24,48",
+        value_delimiter = ',',
+        value_name = "HOURS",
+        help = "Forecast lead times (hours) to score against later observations"
+    )]
+    accuracy_lead_hours: Vec<u32>,
+
+    /// Window for the rolling mean absolute error
+    #[arg(
+        long,
+        env = "WEATHER_ACCURACY_WINDOW_HOURS",
+        default_value_t = 168,
+        value_name = "HOURS",
+        help = "How many hours of forecast errors the rolling MAE covers"
+    )]
+    accuracy_window_hours: i64,
+
+    /// Base temperature for heating degree days
+    #[arg(
+        long,
+        env = "WEATHER_HEATING_BASE_CELSIUS",
+        default_value_t = 18.0,
+        value_name = "CELSIUS",
+        help = "Base temperature for heating degree days"
+    )]
+    heating_base_celsius: f64,
+
+    /// Base temperature for cooling degree days
+    #[arg(
+        long,
+        env = "WEATHER_COOLING_BASE_CELSIUS",
+        default_value_t = 18.0,
+        value_name = "CELSIUS",
+        help = "Base temperature for cooling degree days"
+    )]
+    cooling_base_celsius: f64,
+
+    /// File for state kept across restarts
+    #[arg(
+        long,
+        env = "WEATHER_STATE_FILE",
+        value_name = "PATH",
+        help = "Persist accumulated state (e.g., degree days) to this JSON file"
+    )]
+    state_file: Option<PathBuf>,
+
+    /// Labels identifying a location on value series
+    #[arg(
+        long,
+        env = "WEATHER_LABEL_SCHEME",
+        value_enum,
+        default_value_t = LabelScheme::Info,
+        value_name = "SCHEME",
+        help = "'info' puts coordinates only on weather_location_info, 'coordinates' on every series"
+    )]
+    label_scheme: LabelScheme,
+
+    /// Directory for node_exporter's textfile collector
+    #[arg(
+        long,
+        env = "WEATHER_TEXTFILE_DIRECTORY",
+        value_name = "DIR",
+        help = "Write metrics to DIR/weather_exporter.prom instead of serving HTTP"
+    )]
+    textfile_directory: Option<PathBuf>,
+
+    /// Push metrics to this URL after updates
+    #[arg(
+        long,
+        env = "WEATHER_PUSH_URL",
+        value_name = "URL",
+        help = "Pushgateway base URL or remote_write endpoint to push metrics to"
+    )]
+    push_url: Option<String>,
+
+    /// How metrics are pushed
+    #[arg(
+        long,
+        env = "WEATHER_PUSH_MODE",
+        value_enum,
+        default_value_t = PushMode::Pushgateway,
+        value_name = "MODE",
+        help = "'pushgateway' or 'remote-write'"
+    )]
+    push_mode: PushMode,
+
+    /// Minimum time between pushes
+    #[arg(
+        long,
+        env = "WEATHER_PUSH_INTERVAL_SECONDS",
+        default_value_t = 60,
+        value_name = "SECONDS",
+        help = "Push at most this often, updates in between are sent together"
+    )]
+    push_interval_seconds: u64,
+
+    /// Basic auth user for pushing
+    #[arg(
+        long,
+        env = "WEATHER_PUSH_USERNAME",
+        value_name = "USER",
+        help = "Basic auth username for the push URL"
+    )]
+    push_username: Option<String>,
+
+    /// Basic auth password for pushing
+    #[arg(
+        long,
+        env = "WEATHER_PUSH_PASSWORD",
+        value_name = "PASSWORD",
+        hide_env_values = true,
+        help = "Basic auth password for the push URL"
+    )]
+    push_password: Option<String>,
+
+    /// Job label for pushed metrics
+    #[arg(
+        long,
+        env = "WEATHER_PUSH_JOB",
+        default_value = "weather_exporter",
+        value_name = "JOB",
+        help = "'job' grouping label for pushed metrics"
+    )]
+    push_job: String,
+
+    /// Instance label for pushed metrics
+    #[arg(
+        long,
+        env = "WEATHER_PUSH_INSTANCE",
+        value_name = "INSTANCE",
+        help = "'instance' grouping label for pushed metrics"
+    )]
+    push_instance: Option<String>,
+
+    /// OTLP receiver to export metrics to
+    #[arg(
+        long,
+        env = "WEATHER_OTLP_ENDPOINT",
+        value_name = "URL",
+        help = "OTLP endpoint, e.g. 'http://otel-collector:4317'"
+    )]
+    otlp_endpoint: Option<String>,
+
+    /// OTLP transport
+    #[arg(
+        long,
+        env = "WEATHER_OTLP_PROTOCOL",
+        value_enum,
+        default_value_t = OtlpProtocol::Grpc,
+        value_name = "PROTOCOL",
+        help = "'grpc' or 'http-protobuf'"
+    )]
+    otlp_protocol: OtlpProtocol,
+
+    /// Time between OTLP exports
+    #[arg(
+        long,
+        env = "WEATHER_OTLP_INTERVAL_SECONDS",
+        default_value_t = 60,
+        value_name = "SECONDS",
+        help = "How often metrics are exported over OTLP"
+    )]
+    otlp_interval_seconds: u64,
+
+    /// Resource attributes for OTLP
+    #[arg(
+        long,
+        env = "WEATHER_OTLP_RESOURCE_ATTRIBUTES",
+        value_delimiter = ',',
+        value_name = "KEY=VALUE",
+        help = "Resource attributes identifying this exporter (e.g., 'deployment.environment=prod')"
+    )]
+    otlp_resource_attributes: Vec<String>,
+
+    /// Headers for OTLP requests
+    #[arg(
+        long,
+        env = "WEATHER_OTLP_HEADERS",
+        value_delimiter = ',',
+        value_name = "KEY=VALUE",
+        hide_env_values = true,
+        help = "Headers sent with OTLP exports (e.g., 'authorization=Bearer ...')"
+    )]
+    otlp_headers: Vec<String>,
+
+    /// MQTT broker to publish current values to
+    #[arg(
+        long,
+        env = "WEATHER_MQTT_HOST",
+        value_name = "HOST",
+        help = "MQTT broker host to publish current values to"
+    )]
+    mqtt_host: Option<String>,
+
+    /// MQTT broker port
+    #[arg(
+        long,
+        env = "WEATHER_MQTT_PORT",
+        default_value_t = 1883,
+        value_name = "PORT",
+        help = "MQTT broker port"
+    )]
+    mqtt_port: u16,
+
+    /// MQTT username
+    #[arg(
+        long,
+        env = "WEATHER_MQTT_USERNAME",
+        value_name = "USER",
+        help = "MQTT username"
+    )]
+    mqtt_username: Option<String>,
+
+    /// MQTT password
+    #[arg(
+        long,
+        env = "WEATHER_MQTT_PASSWORD",
+        value_name = "PASSWORD",
+        hide_env_values = true,
+        help = "MQTT password"
+    )]
+    mqtt_password: Option<String>,
+
+    /// MQTT client ID
+    #[arg(
+        long,
+        env = "WEATHER_MQTT_CLIENT_ID",
+        default_value = "weather-exporter",
+        value_name = "ID",
+        help = "MQTT client ID, must be unique per broker"
+    )]
+    mqtt_client_id: String,
+
+    /// Prefix for state topics
+    #[arg(
+        long,
+        env = "WEATHER_MQTT_TOPIC_PREFIX",
+        default_value = "weather-exporter",
+        value_name = "PREFIX",
+        help = "State is published to PREFIX/<location>/state"
+    )]
+    mqtt_topic_prefix: String,
+
+    /// Home Assistant discovery prefix
+    #[arg(
+        long,
+        env = "WEATHER_MQTT_DISCOVERY_PREFIX",
+        default_value = "homeassistant",
+        value_name = "PREFIX",
+        help = "Home Assistant MQTT discovery prefix"
+    )]
+    mqtt_discovery_prefix: String,
+
+    /// Skip Home Assistant discovery
+    #[arg(
+        long,
+        env = "WEATHER_MQTT_DISABLE_DISCOVERY",
+        help = "Don't publish Home Assistant discovery configs"
+    )]
+    mqtt_disable_discovery: bool,
+
+    /// InfluxDB v2 to write current values to
+    #[arg(
+        long,
+        env = "WEATHER_INFLUX_URL",
+        value_name = "URL",
+        help = "InfluxDB v2 base URL to write current values to"
+    )]
+    influx_url: Option<String>,
+
+    /// InfluxDB organization
+    #[arg(
+        long,
+        env = "WEATHER_INFLUX_ORG",
+        value_name = "ORG",
+        help = "InfluxDB organization"
+    )]
+    influx_org: Option<String>,
+
+    /// InfluxDB bucket
+    #[arg(
+        long,
+        env = "WEATHER_INFLUX_BUCKET",
+        value_name = "BUCKET",
+        help = "InfluxDB bucket"
+    )]
+    influx_bucket: Option<String>,
+
+    /// InfluxDB API token
+    #[arg(
+        long,
+        env = "WEATHER_INFLUX_TOKEN",
+        value_name = "TOKEN",
+        hide_env_values = true,
+        help = "InfluxDB API token with write access to the bucket"
+    )]
+    influx_token: Option<String>,
+
+    /// Minimum time between InfluxDB writes
+    #[arg(
+        long,
+        env = "WEATHER_INFLUX_INTERVAL_SECONDS",
+        default_value_t = 60,
+        value_name = "SECONDS",
+        help = "Write at most this often, updates in between are sent together"
+    )]
+    influx_interval_seconds: u64,
+
+    /// Token for the admin API
+    #[arg(
+        long,
+        env = "WEATHER_ADMIN_TOKEN",
+        value_name = "TOKEN",
+        hide_env_values = true,
+        help = "Enable /admin endpoints, authenticated with this token in X-Admin-Token"
+    )]
+    admin_token: Option<String>,
+
+    /// Write admin API changes back to the config file
+    #[arg(
+        long,
+        env = "WEATHER_ADMIN_PERSIST",
+        help = "Save locations added or removed through the admin API to --config-file, keeping the original as <file>.bak"
+    )]
+    admin_persist: bool,
+
+    /// Share of locations that need fresh data for /health/ready
+    #[arg(
+        long,
+        env = "WEATHER_READY_FRACTION",
+        default_value_t = 0.0,
+        value_name = "FRACTION",
+        help = "Report ready once this fraction of locations (0-1) has fresh data, at least one"
+    )]
+    ready_fraction: f64,
+
+    /// Time allowed for in-flight work on SIGTERM or SIGINT
+    #[arg(
+        long,
+        env = "WEATHER_SHUTDOWN_TIMEOUT_SECONDS",
+        default_value_t = 25,
+        value_name = "SECONDS",
+        help = "On SIGTERM or SIGINT, wait this long for in-flight requests and fetches before exiting"
+    )]
+    shutdown_timeout_seconds: u64,
+
+    /// TLS and basic auth settings
+    #[arg(
+        long = "web.config.file",
+        env = "WEATHER_WEB_CONFIG_FILE",
+        value_name = "FILE",
+        help = "Web config file in the Prometheus exporter-toolkit format, for TLS and basic auth"
+    )]
+    web_config_file: Option<PathBuf>,
+
     /// Port for the metrics endpoint
     #[arg(
         short = 'p',
@@ -142,10 +526,20 @@ struct Args {
         env = "PORT",
         default_value_t = 9090,
         value_name = "PORT",
-        help = "Port to listen on"
+        help = "Port to listen on, on all IPv4 interfaces, unless --listen-address is given"
     )]
     port: u16,
 
+    /// Addresses to listen on
+    #[arg(
+        long,
+        env = "WEATHER_LISTEN_ADDRESS",
+        value_delimiter = ',',
+        value_name = "ADDRESS",
+        help = "Addresses to listen on: host:port, [::]:port or unix:/path.sock (e.g., '127.0.0.1:9090,unix:/run/weather.sock')"
+    )]
+    listen_address: Vec<listen::ListenAddress>,
+
     /// Log level
     #[arg(
         long,
@@ -159,190 +553,253 @@ struct Args {
     /// Validate configuration and exit
     #[arg(long, help = "Validate configuration without starting the server")]
     check: bool,
-}
-
-// YR.no API response structures
-#[derive(Debug, Deserialize)]
-struct LocationSearchResponse {
-    #[serde(rename = "_embedded")]
-    embedded: Option<EmbeddedLocations>,
-}
-
-#[derive(Debug, Deserialize)]
-struct EmbeddedLocations {
-    location: Option<Vec<Location>>,
-}
-
-#[derive(Debug, Deserialize, Clone)]
-struct Location {
-    name: String,
-    position: Position,
-}
-
-#[derive(Debug, Deserialize, Clone)]
-struct Position {
-    lat: f64,
-    lon: f64,
-}
-
-impl Position {
-    // Round coordinates to 4 decimals as required by the API
-    fn rounded(&self) -> (f64, f64) {
-        (
-            (self.lat * 10000.0).round() / 10000.0,
-            (self.lon * 10000.0).round() / 10000.0,
-        )
-    }
-}
-
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct WeatherResponse {
-    properties: WeatherProperties,
-}
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct WeatherProperties {
-    timeseries: Vec<TimeSeries>,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct TimeSeries {
-    time: DateTime<Utc>,
-    data: TimeSeriesData,
-}
-
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct TimeSeriesData {
-    instant: InstantData,
-    next_1_hours: Option<NextHours>,
-}
-
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct InstantData {
-    details: WeatherDetails,
-}
-
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct WeatherDetails {
-    air_pressure_at_sea_level: Option<f64>,
-    air_temperature: Option<f64>,
-    cloud_area_fraction: Option<f64>,
-    relative_humidity: Option<f64>,
-    wind_from_direction: Option<f64>,
-    wind_speed: Option<f64>,
-    ultraviolet_index_clear_sky: Option<f64>,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Look up locations without starting the exporter
+    #[command(subcommand)]
+    Locations(LocationsCommand),
+
+    /// Fetch once and print current conditions and the forecast
+    Now {
+        /// Locations to fetch, instead of --locations or the config file
+        #[arg(short = 'l', long, value_delimiter = ',', value_name = "LOCATIONS")]
+        locations: Vec<String>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = commands::OutputFormat::Table)]
+        format: commands::OutputFormat,
+
+        /// Forecast hours to print after the current entry
+        #[arg(long, default_value_t = 12, value_name = "HOURS")]
+        hours: i64,
+    },
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct NextHours {
-    details: NextHoursDetails,
-}
-
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct NextHoursDetails {
-    precipitation_amount: Option<f64>,
-}
-
-// Cache for weather data
-#[derive(Clone)]
-struct WeatherCache {
-    data: Option<WeatherResponse>,
-    expires: Option<DateTime<Utc>>,
-    last_modified: Option<String>,
-}
-
-impl WeatherCache {
-    fn new() -> Self {
-        Self {
-            data: None,
-            expires: None,
-            last_modified: None,
-        }
-    }
-
-    fn is_expired(&self) -> bool {
-        match self.expires {
-            Some(expires) => Utc::now() > expires,
-            None => true,
-        }
-    }
+#[derive(Subcommand, Debug)]
+enum LocationsCommand {
+    /// List every place matching a name, with the yr.no ID to pin it by
+    Search {
+        /// Place name to search for
+        query: String,
+    },
 }
 
 // Data for a single location
 #[derive(Clone)]
 struct LocationData {
+    provider: Arc<dyn WeatherProvider>,
+    /// What the location was configured with
+    spec: LocationSpec,
     location: Option<Location>,
     cache: WeatherCache,
+    accuracy: ForecastTracker,
+    degree_days: DegreeDays,
+    earlier_today: EarlierToday,
+    status: FetchStatus,
 }
 
 impl LocationData {
-    fn new() -> Self {
+    fn new(
+        provider: Arc<dyn WeatherProvider>,
+        spec: LocationSpec,
+        accuracy: Arc<AccuracySettings>,
+    ) -> Self {
         Self {
+            provider,
+            spec,
             location: None,
             cache: WeatherCache::new(),
+            accuracy: ForecastTracker::new(accuracy),
+            degree_days: DegreeDays::default(),
+            earlier_today: EarlierToday::default(),
+            status: FetchStatus::default(),
         }
     }
 }
 
 #[derive(Clone)]
 struct AppState {
-    location_names: Vec<String>,
+    /// Monitored locations in configuration order
+    location_names: Arc<RwLock<Vec<String>>>,
     locations: Arc<RwLock<HashMap<String, LocationData>>>,
-    client: reqwest::Client,
+    /// One shared instance per provider kind
+    providers: Arc<HashMap<ProviderKind, Arc<dyn WeatherProvider>>>,
+    accuracy: Arc<AccuracySettings>,
+    /// How often `periodic_update` checks for expired caches
+    update_interval: Arc<watch::Sender<Duration>>,
+    /// Station ID to location name
+    stations: Arc<HashMap<String, Station>>,
+    degree_day_settings: DegreeDaySettings,
+    state_file: Option<Arc<StateFile>>,
+    textfile: Option<Arc<TextfileWriter>>,
+    pusher: Option<Arc<Pusher>>,
+    mqtt: Option<Arc<MqttPublisher>>,
+    influx: Option<Arc<InfluxWriter>>,
+    otlp: Option<Arc<OtlpExporter>>,
 }
 
 impl AppState {
-    fn new(location_names: Vec<String>, user_agent: String) -> Result<Self> {
-        let client = reqwest::Client::builder()
-            .user_agent(user_agent)
-            .timeout(Duration::from_secs(30))
-            .build()?;
+    fn new(
+        location_specs: Vec<LocationSpec>,
+        stations: HashMap<String, Station>,
+        accuracy: AccuracySettings,
+        degree_day_settings: DegreeDaySettings,
+        state_file: Option<StateFile>,
+        user_agent: String,
+    ) -> Result<Self> {
+        let client = provider::http_client(&user_agent)?;
+        let providers: HashMap<ProviderKind, Arc<dyn WeatherProvider>> =
+            ProviderKind::value_variants()
+                .iter()
+                .map(|kind| (*kind, kind.build(client.clone())))
+                .collect();
+
+        let accuracy = Arc::new(accuracy);
+        let mut persisted = match &state_file {
+            Some(state_file) => state_file.load()?,
+            None => PersistedState::default(),
+        };
 
         // Initialize HashMap with empty LocationData for each location
+        let mut location_names = Vec::new();
         let mut locations = HashMap::new();
-        for name in &location_names {
-            locations.insert(name.clone(), LocationData::new());
+        for spec in location_specs {
+            metrics::set_custom_labels(&spec.name, &spec.labels);
+            let name = spec.name.clone();
+            let mut location_data =
+                LocationData::new(providers[&spec.provider].clone(), spec, accuracy.clone());
+            if let Some(degree_days) = persisted.degree_days.remove(&name) {
+                degree_days.update_metrics(&name);
+                location_data.degree_days = degree_days;
+            }
+            if let Some(earlier_today) = persisted.earlier_today.remove(&name) {
+                location_data.earlier_today = earlier_today;
+            }
+            location_names.push(name.clone());
+            locations.insert(name, location_data);
         }
 
         Ok(Self {
-            location_names,
+            location_names: Arc::new(RwLock::new(location_names)),
             locations: Arc::new(RwLock::new(locations)),
-            client,
+            providers: Arc::new(providers),
+            accuracy,
+            update_interval: Arc::new(watch::Sender::new(UPDATE_INTERVAL)),
+            stations: Arc::new(stations),
+            degree_day_settings,
+            state_file: state_file.map(Arc::new),
+            textfile: None,
+            pusher: None,
+            mqtt: None,
+            influx: None,
+            otlp: None,
         })
     }
 
-    async fn search_location(&self, location_name: &str) -> Result<Location> {
-        let url = format!(
-            "https://www.yr.no/api/v0/locations/search?q={}",
-            urlencoding::encode(location_name)
+    /// Snapshot of the monitored location names, in configuration order
+    async fn location_names(&self) -> Vec<String> {
+        self.location_names.read().await.clone()
+    }
+
+    /// Start monitoring a location. Returns false if one with the same name
+    /// is already monitored.
+    async fn add_location(&self, spec: LocationSpec) -> bool {
+        let mut locations = self.locations.write().await;
+        if locations.contains_key(&spec.name) {
+            return false;
+        }
+        metrics::set_custom_labels(&spec.name, &spec.labels);
+        let name = spec.name.clone();
+        let location_data = LocationData::new(
+            self.providers[&spec.provider].clone(),
+            spec,
+            self.accuracy.clone(),
         );
+        locations.insert(name.clone(), location_data);
+        drop(locations);
 
-        info!("Searching for location: {}", location_name);
+        self.location_names.write().await.push(name);
+        true
+    }
 
-        let response = self
-            .client
-            .get(&url)
-            .send()
-            .await?
-            .json::<LocationSearchResponse>()
-            .await?;
+    /// Stop monitoring a location and drop its series. Returns false if it
+    /// wasn't monitored.
+    async fn remove_location(&self, location_name: &str) -> bool {
+        if self.locations.write().await.remove(location_name).is_none() {
+            return false;
+        }
+        self.location_names
+            .write()
+            .await
+            .retain(|name| name != location_name);
 
-        let location = response
-            .embedded
-            .and_then(|e| e.location)
-            .and_then(|locs| locs.into_iter().next())
-            .ok_or_else(|| anyhow::anyhow!("Location not found: {}", location_name))?;
+        metrics::remove_location(location_name);
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.remove(location_name);
+        }
+        self.save_state().await;
+        true
+    }
 
-        info!(
-            "Found location: {} at ({}, {})",
-            location.name, location.position.lat, location.position.lon
-        );
+    /// Forget a location's coordinates and cached forecast, so the next update
+    /// looks it up again. Its series go too, as the info metric and, depending
+    /// on the label scheme, the values carry the old coordinates. Returns false
+    /// if it isn't monitored.
+    async fn reset_location(&self, location_name: &str) -> bool {
+        let mut locations = self.locations.write().await;
+        let Some(location_data) = locations.get_mut(location_name) else {
+            return false;
+        };
+        metrics::remove_location(location_name);
+        metrics::set_custom_labels(location_name, &location_data.spec.labels);
+        // Degree days don't depend on where the location resolved to
+        location_data.degree_days.update_metrics(location_name);
+        location_data.location = None;
+        location_data.cache = WeatherCache::new();
+        true
+    }
 
-        Ok(location)
+    /// Write persistent state now if it wasn't written for a while, rather
+    /// than on every update
+    async fn save_state_if_due(&self) {
+        if self.state_file.as_ref().is_some_and(|file| file.is_due()) {
+            self.save_state().await;
+        }
+    }
+
+    /// Write persistent state to the state file, if one is configured
+    async fn save_state(&self) {
+        let Some(state_file) = &self.state_file else {
+            return;
+        };
+
+        let state = {
+            let locations = self.locations.read().await;
+            PersistedState {
+                degree_days: locations
+                    .iter()
+                    .map(|(name, data)| (name.clone(), data.degree_days.clone()))
+                    .collect(),
+                earlier_today: locations
+                    .iter()
+                    .map(|(name, data)| (name.clone(), data.earlier_today.clone()))
+                    .collect(),
+            }
+        };
+
+        if let Err(e) = state_file.save(&state).await {
+            error!("Failed to save state: {:#}", e);
+        }
     }
 
     async fn fetch_weather(
         &self,
+        provider: &dyn WeatherProvider,
         location_name: &str,
         location: &Location,
         cache: &WeatherCache,
@@ -353,110 +810,13 @@ impl AppState {
                 "Using cached weather data for {} (expires: {:?})",
                 location_name, cache.expires
             );
-            WEATHER_CACHE_HITS.with_label_values(&[location_name]).inc();
+            WEATHER_CACHE_HITS
+                .with_label_values(&location_labels(location_name, &[]))
+                .inc();
             return Ok(cache.clone());
         }
 
-        // Round coordinates to 4 decimals as required by the API
-        let (lat, lon) = location.position.rounded();
-        let url = format!(
-            "https://api.met.no/weatherapi/locationforecast/2.0/compact?lat={}&lon={}",
-            lat, lon
-        );
-
-        info!(
-            "Fetching weather for {} (rounded coords: {}, {})",
-            location_name, lat, lon
-        );
-
-        // Build request with If-Modified-Since header if we have cached data
-        let mut request = self.client.get(&url);
-        if let Some(ref last_mod) = cache.last_modified {
-            debug!(
-                "Adding If-Modified-Since header for {}: {}",
-                location_name, last_mod
-            );
-            request = request.header("If-Modified-Since", last_mod);
-        }
-
-        let response = request.send().await?;
-
-        WEATHER_API_CALLS.with_label_values(&[location_name]).inc();
-
-        // Handle different status codes
-        match response.status() {
-            StatusCode::OK => {
-                info!("Received new weather data for {}", location_name);
-
-                // Extract headers
-                let expires = response
-                    .headers()
-                    .get("expires")
-                    .and_then(|v| v.to_str().ok())
-                    .and_then(|s| DateTime::parse_from_rfc2822(s).ok())
-                    .map(|dt| dt.with_timezone(&Utc));
-
-                let last_modified = response
-                    .headers()
-                    .get("last-modified")
-                    .and_then(|v| v.to_str().ok())
-                    .map(|s| s.to_string());
-
-                // Check for deprecation warning
-                if response.status() == StatusCode::NON_AUTHORITATIVE_INFORMATION {
-                    warn!("API endpoint is deprecated, please check for updates");
-                }
-
-                let weather_data = response.json::<WeatherResponse>().await?;
-
-                // Return new cache
-                let new_cache = WeatherCache {
-                    data: Some(weather_data),
-                    expires,
-                    last_modified,
-                };
-
-                info!(
-                    "Weather data for {} cached until: {:?}",
-                    location_name, expires
-                );
-                Ok(new_cache)
-            }
-            StatusCode::NOT_MODIFIED => {
-                info!(
-                    "Weather data not modified for {}, using cached version",
-                    location_name
-                );
-                WEATHER_CACHE_HITS.with_label_values(&[location_name]).inc();
-                Ok(cache.clone())
-            }
-            StatusCode::TOO_MANY_REQUESTS => {
-                error!(
-                    "Rate limited by API for {} - too many requests",
-                    location_name
-                );
-                Err(anyhow::anyhow!(
-                    "Rate limited - please reduce request frequency"
-                ))
-            }
-            StatusCode::FORBIDDEN => {
-                error!("Forbidden for {} - check User-Agent header", location_name);
-                Err(anyhow::anyhow!(
-                    "API returned 403 Forbidden - check configuration"
-                ))
-            }
-            _ => {
-                error!(
-                    "Unexpected status code for {}: {}",
-                    location_name,
-                    response.status()
-                );
-                Err(anyhow::anyhow!(
-                    "Unexpected API response: {}",
-                    response.status()
-                ))
-            }
-        }
+        provider.fetch_weather(location_name, location, cache).await
     }
 
     async fn update_metrics_for_location(&self, location_name: &str) -> Result<()> {
@@ -468,50 +828,124 @@ impl AppState {
 
         // Get or search for location coordinates
         if location_data.location.is_none() {
-            match self.search_location(location_name).await {
-                Ok(loc) => {
+            match location_data
+                .provider
+                .search_location(&location_data.spec.query)
+                .await
+            {
+                Ok(mut loc) => {
+                    if let Some(tz) = location_data.spec.timezone {
+                        loc.time_zone = Some(tz.name().to_string());
+                    }
+                    let tz = loc.timezone();
+                    info!("Using timezone {} for {}", tz, location_name);
+                    set_location_info(location_name, &loc, tz.name());
                     location_data.location = Some(loc);
                 }
                 Err(e) => {
                     error!("Failed to search for location {}: {}", location_name, e);
+                    location_data.status.record_failure(
+                        Utc::now(),
+                        &e,
+                        *self.update_interval.borrow(),
+                    );
                     WEATHER_FETCH_SUCCESS
-                        .with_label_values(&[location_name])
+                        .with_label_values(&location_labels(location_name, &[]))
                         .set(0);
                     return Err(e);
                 }
             }
         }
 
+        let provider = location_data.provider.clone();
         let location = location_data.location.as_ref().unwrap().clone();
         let current_cache = location_data.cache.clone();
+        // Same check as in fetch_weather
+        let fetching = current_cache.is_expired() || current_cache.data.is_none();
 
         // Release write lock before making HTTP request
         drop(locations);
 
         // Fetch weather data (will use cache if not expired)
         match self
-            .fetch_weather(location_name, &location, &current_cache)
+            .fetch_weather(provider.as_ref(), location_name, &location, &current_cache)
             .await
         {
             Ok(new_cache) => {
                 // Update cache if we got new data
                 let mut locations = self.locations.write().await;
-                if let Some(location_data) = locations.get_mut(location_name) {
-                    location_data.cache = new_cache.clone();
+                // Removed while fetching, its series are gone and stay gone
+                let Some(location_data) = locations.get_mut(location_name) else {
+                    return Ok(());
+                };
+                if fetching {
+                    location_data
+                        .status
+                        .record_success(Utc::now(), new_cache.expires);
+                } else {
+                    location_data.status.record_cache_hit();
+                }
+                let previous = std::mem::replace(&mut location_data.cache, new_cache.clone());
+                if let Some(weather) = &new_cache.data {
+                    let now = Utc::now();
+                    let tz = location.timezone();
+                    LOCATION_UTC_OFFSET
+                        .with_label_values(&location_labels(location_name, &[]))
+                        .set(now.with_timezone(&tz).offset().fix().local_minus_utc() as i64);
+                    location_data
+                        .earlier_today
+                        .retain(previous.data.as_ref(), weather, now, tz);
+                    daily::update_daily_metrics(
+                        location_name,
+                        &location_data.earlier_today,
+                        weather,
+                        now,
+                        tz,
+                    );
+                    location_data.accuracy.update(location_name, weather, now);
+                    if let Some(temp) = weather.temperature_at(now) {
+                        location_data.degree_days.add_sample(
+                            now,
+                            temp,
+                            tz,
+                            &self.degree_day_settings,
+                        );
+                        location_data.degree_days.update_metrics(location_name);
+                    }
                 }
-                drop(locations);
 
                 WEATHER_FETCH_SUCCESS
-                    .with_label_values(&[location_name])
+                    .with_label_values(&location_labels(location_name, &[]))
                     .set(1);
 
                 // Update metrics from cache
                 self.update_prometheus_metrics(location_name, &location, &new_cache)?;
+                drop(locations);
+                self.save_state_if_due().await;
+
+                if let Some(pusher) = &self.pusher {
+                    pusher.notify();
+                }
+                if let Some(influx) = &self.influx {
+                    influx.notify();
+                }
+                if let Some(mqtt) = &self.mqtt
+                    && let Some(weather) = &new_cache.data
+                {
+                    mqtt.publish(location_name, &location, weather);
+                }
             }
             Err(e) => {
-                WEATHER_FETCH_SUCCESS
-                    .with_label_values(&[location_name])
-                    .set(0);
+                if let Some(location_data) = self.locations.write().await.get_mut(location_name) {
+                    location_data.status.record_failure(
+                        Utc::now(),
+                        &e,
+                        *self.update_interval.borrow(),
+                    );
+                    WEATHER_FETCH_SUCCESS
+                        .with_label_values(&location_labels(location_name, &[]))
+                        .set(0);
+                }
                 return Err(e);
             }
         }
@@ -532,14 +966,7 @@ impl AppState {
 
         // Find the timeseries entry closest to current time
         let now = Utc::now();
-        let current = weather.properties.timeseries.iter().min_by_key(|ts| {
-            let diff = if ts.time > now {
-                ts.time - now
-            } else {
-                now - ts.time
-            };
-            diff.num_seconds().abs()
-        });
+        let current = weather.closest(now);
 
         if let Some(current) = current {
             info!(
@@ -547,11 +974,7 @@ impl AppState {
                 location_name, current.time, now
             );
 
-            let labels = [
-                location_name,
-                &location.position.lat.to_string(),
-                &location.position.lon.to_string(),
-            ];
+            let labels = value_labels(location_name, Some(location), "forecast");
 
             let details = &current.data.instant.details;
 
@@ -583,6 +1006,15 @@ impl AppState {
                 UV_INDEX.with_label_values(&labels).set(uv);
             }
 
+            if let Some(temp) = details.air_temperature {
+                update_comfort_metrics(
+                    &labels,
+                    temp,
+                    details.relative_humidity,
+                    details.wind_speed,
+                );
+            }
+
             // Precipitation from next hour forecast
             if let Some(next_hour) = &current.data.next_1_hours
                 && let Some(precip) = next_hour.details.precipitation_amount
@@ -600,7 +1032,7 @@ impl AppState {
 
     async fn update_all_metrics(&self) {
         // Update metrics for all locations
-        for location_name in &self.location_names {
+        for location_name in &self.location_names().await {
             if let Err(e) = self.update_metrics_for_location(location_name).await {
                 error!("Failed to update metrics for {}: {}", location_name, e);
             }
@@ -610,37 +1042,123 @@ impl AppState {
     }
 }
 
-async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+#[cfg(test)]
+impl AppState {
+    /// State for the locations of a config file, with nothing looked up or
+    /// fetched yet and no outputs
+    fn for_tests(config: &str) -> Self {
+        let file = ConfigFile::parse(config).expect("valid test config");
+        Self::new(
+            location_specs(&file.locations, &[], ProviderKind::Yr).expect("valid locations"),
+            HashMap::new(),
+            AccuracySettings {
+                lead_hours: vec![24],
+                window: chrono::Duration::days(7),
+            },
+            DegreeDaySettings {
+                heating_base: 18.0,
+                cooling_base: 22.0,
+            },
+            None,
+            "test-exporter/1.0 example.com/test".to_string(),
+        )
+        .expect("state can be created")
+    }
+}
+
+fn update_comfort_metrics(
+    labels: &[String],
+    temp: f64,
+    humidity: Option<f64>,
+    wind_speed: Option<f64>,
+) {
+    FEELS_LIKE
+        .with_label_values(labels)
+        .set(comfort::feels_like(temp, humidity, wind_speed));
+
+    // Wind chill and heat index are only meaningful in some conditions, drop
+    // the series rather than leave a stale value behind
+    match wind_speed.and_then(|wind| comfort::wind_chill(temp, wind)) {
+        Some(wind_chill) => WIND_CHILL.with_label_values(labels).set(wind_chill),
+        None => {
+            let _ = WIND_CHILL.remove_label_values(labels);
+        }
+    }
+
+    let Some(humidity) = humidity else {
+        return;
+    };
+
+    match comfort::heat_index(temp, humidity) {
+        Some(heat_index) => HEAT_INDEX.with_label_values(labels).set(heat_index),
+        None => {
+            let _ = HEAT_INDEX.remove_label_values(labels);
+        }
+    }
+
+    if let Some(dew_point) = comfort::dew_point(temp, humidity) {
+        DEW_POINT.with_label_values(labels).set(dew_point);
+    }
+
+    if let Some(humidex) = comfort::humidex(temp, humidity) {
+        HUMIDEX.with_label_values(labels).set(humidex);
+    }
+
+    ABSOLUTE_HUMIDITY
+        .with_label_values(labels)
+        .set(comfort::absolute_humidity(temp, humidity));
+}
+
+async fn metrics_handler(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
     // Update metrics before serving them
     state.update_all_metrics().await;
 
-    let encoder = TextEncoder::new();
-    let metric_families = REGISTRY.gather();
-    let mut buffer = vec![];
-    encoder.encode(&metric_families, &mut buffer).unwrap();
-
-    String::from_utf8(buffer).unwrap()
+    if openmetrics::requested(&headers) {
+        (
+            [(header::CONTENT_TYPE, openmetrics::CONTENT_TYPE)],
+            metrics::gather_openmetrics(),
+        )
+    } else {
+        (
+            [(header::CONTENT_TYPE, metrics::TEXT_CONTENT_TYPE)],
+            metrics::gather_text(),
+        )
+    }
 }
 
 async fn health_handler() -> impl IntoResponse {
     (StatusCode::OK, "OK")
 }
 
-async fn periodic_update(state: AppState) {
-    let mut interval = tokio::time::interval(Duration::from_secs(60)); // Check every minute
+/// Check for expired forecasts until `shutdown` turns true. A fetch that
+/// is running then finishes, but no new one starts.
+async fn periodic_update(state: AppState, mut shutdown: watch::Receiver<bool>) {
+    let mut period = state.update_interval.subscribe();
+    let mut interval = tokio::time::interval(*period.borrow_and_update());
 
     loop {
-        interval.tick().await;
+        tokio::select! {
+            _ = interval.tick() => {}
+            Ok(()) = period.changed() => {
+                let period = *period.borrow_and_update();
+                info!("Checking for expired forecasts every {}s", period.as_secs());
+                interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+                continue;
+            }
+            _ = shutdown.changed() => return,
+        }
 
         // Check each location and update if cache expired
-        for location_name in &state.location_names {
+        for location_name in &state.location_names().await {
+            if *shutdown.borrow() {
+                return;
+            }
             let should_update = {
                 let locations = state.locations.read().await;
-                if let Some(location_data) = locations.get(location_name) {
-                    location_data.cache.is_expired()
-                } else {
-                    true // If not initialized, we should update
-                }
+                // Locations removed since the names were read are skipped
+                locations
+                    .get(location_name)
+                    .is_some_and(|location_data| location_data.cache.is_expired())
             };
 
             if should_update {
@@ -661,9 +1179,77 @@ async fn periodic_update(state: AppState) {
             // Small delay between locations
             tokio::time::sleep(Duration::from_millis(100)).await;
         }
+
+        // Rewritten every tick so its age shows whether the exporter is alive
+        if let Some(textfile) = &state.textfile
+            && let Err(e) = textfile.write().await
+        {
+            error!("Failed to write textfile: {}", e);
+        }
     }
 }
 
+/// Start listening for SIGTERM and SIGINT, returning a future that resolves
+/// on the first of them. Signals that arrive before it is polled still count.
+fn shutdown_signal() -> Result<impl Future<Output = ()>> {
+    let mut terminate = signal(SignalKind::terminate()).context("Failed to listen for SIGTERM")?;
+    let mut interrupt = signal(SignalKind::interrupt()).context("Failed to listen for SIGINT")?;
+    Ok(async move {
+        tokio::select! {
+            _ = interrupt.recv() => info!("Received SIGINT, shutting down"),
+            _ = terminate.recv() => info!("Received SIGTERM, shutting down"),
+        }
+    })
+}
+
+/// Tell `tasks` to stop, then `sinks`, so their final flush carries the last
+/// update. Together they get `timeout` to finish what they are doing, then
+/// persistent state is flushed.
+async fn shut_down(
+    state: &AppState,
+    shutdown: watch::Sender<bool>,
+    tasks: JoinSet<()>,
+    sink_shutdown: watch::Sender<bool>,
+    sinks: JoinSet<()>,
+    timeout: Duration,
+    started: Instant,
+) {
+    let deadline = tokio::time::Instant::now() + timeout;
+    shutdown.send_replace(true);
+    let tasks_finished = tokio::time::timeout_at(deadline, tasks.join_all())
+        .await
+        .is_ok();
+    sink_shutdown.send_replace(true);
+    let sinks_finished = tokio::time::timeout_at(deadline, sinks.join_all())
+        .await
+        .is_ok();
+    if !tasks_finished || !sinks_finished {
+        warn!(
+            "In-flight requests and writes did not finish within {}s, exiting anyway",
+            timeout.as_secs()
+        );
+    }
+
+    state.save_state().await;
+
+    let locations = state.locations.read().await;
+    let (fetches, failures) = locations
+        .values()
+        .fold((0, 0), |(fetches, failures), data| {
+            (
+                fetches + data.status.fetches,
+                failures + data.status.failures,
+            )
+        });
+    info!(
+        "Stopped after {}s: {} locations, {} fetches, {} failed",
+        started.elapsed().as_secs(),
+        locations.len(),
+        fetches,
+        failures
+    );
+}
+
 fn validate_user_agent(user_agent: &str) -> Result<()> {
     let ua = user_agent.trim();
 
@@ -703,6 +1289,26 @@ fn validate_user_agent(user_agent: &str) -> Result<()> {
     Ok(())
 }
 
+/// Locations from the config file, or from `cli_locations` if the file has
+/// none
+fn location_specs(
+    file_locations: &[LocationEntry],
+    cli_locations: &[String],
+    default_provider: ProviderKind,
+) -> Result<Vec<LocationSpec>> {
+    if file_locations.is_empty() {
+        return Ok(clean_locations(cli_locations)
+            .iter()
+            .map(|spec| LocationSpec::parse(spec, default_provider))
+            .filter(|spec| !spec.name.is_empty())
+            .collect());
+    }
+    file_locations
+        .iter()
+        .map(|entry| LocationSpec::from_config(entry.clone().into_config(), default_provider))
+        .collect()
+}
+
 fn clean_locations(locations: &[String]) -> Vec<String> {
     locations
         .iter()
@@ -711,98 +1317,665 @@ fn clean_locations(locations: &[String]) -> Vec<String> {
         .collect()
 }
 
+/// Parse `ID=LOCATION[:PASSWORD]`, the password running to the end so it can
+/// contain colons
+fn parse_stations(stations: &[String]) -> Result<HashMap<String, Station>> {
+    clean_locations(stations)
+        .iter()
+        .map(|entry| {
+            // Only the ID goes into errors, the rest may hold a password
+            let (id, rest) = entry.split_once('=').unwrap_or((entry, ""));
+            let (id, rest) = (id.trim(), rest.trim());
+            let (location, password) = match rest.split_once(':') {
+                Some((location, password)) => (location.trim(), Some(password.to_string())),
+                None => (rest, None),
+            };
+            if id.is_empty() || location.is_empty() || password.as_deref() == Some("") {
+                return Err(anyhow::anyhow!(
+                    "Invalid station '{}', expected ID=LOCATION[:PASSWORD]",
+                    id
+                ));
+            }
+            Ok((
+                id.to_string(),
+                Station {
+                    location: location.to_string(),
+                    password,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Parse `KEY=VALUE` pairs, keeping their order
+fn parse_key_values(entries: &[String], option: &str) -> Result<Vec<(String, String)>> {
+    clean_locations(entries)
+        .iter()
+        .map(|entry| {
+            entry
+                .split_once('=')
+                .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+                .filter(|(key, _)| !key.is_empty())
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Invalid {} '{}', expected KEY=VALUE", option, entry)
+                })
+        })
+        .collect()
+}
+
+/// A location name together with the provider used to fetch it
+#[derive(Debug, Clone, PartialEq)]
+struct LocationSpec {
+    name: String,
+    query: LocationQuery,
+    provider: ProviderKind,
+    /// Overrides the timezone of the geocoding result
+    timezone: Option<Tz>,
+    labels: BTreeMap<String, String>,
+}
+
+impl LocationSpec {
+    /// Parse `[provider:]name`, e.g. `open-meteo:Tokyo`. Names without a known
+    /// provider prefix use `default_provider`.
+    fn parse(spec: &str, default_provider: ProviderKind) -> Self {
+        if let Some((prefix, name)) = spec.split_once(':')
+            && let Ok(provider) = ProviderKind::from_str(prefix.trim(), true)
+        {
+            return Self {
+                name: name.trim().to_string(),
+                query: LocationQuery::parse(name),
+                provider,
+                timezone: None,
+                labels: BTreeMap::new(),
+            };
+        }
+
+        Self {
+            name: spec.to_string(),
+            query: LocationQuery::parse(spec),
+            provider: default_provider,
+            timezone: None,
+            labels: BTreeMap::new(),
+        }
+    }
+
+    /// The search (or name) may carry a country qualifier (`Lund, SE`),
+    /// which the `country` and `yr_id` settings override.
+    fn from_config(config: LocationConfig, default_provider: ProviderKind) -> Result<Self> {
+        let name = config.name.trim().to_string();
+        let provider = config.provider.unwrap_or(default_provider);
+        let mut query = LocationQuery::parse(config.search.as_deref().unwrap_or(&name));
+        if config.country.is_some() {
+            query.country = config.country;
+        }
+        if let Some(id) = config.yr_id {
+            if provider != ProviderKind::Yr {
+                return Err(anyhow::anyhow!(
+                    "Location {} is pinned to a yr.no ID but uses the {} provider",
+                    name,
+                    provider
+                ));
+            }
+            query.id = Some(id);
+        }
+
+        let timezone = config
+            .timezone
+            .map(|tz| {
+                tz.parse::<Tz>()
+                    .map_err(|_| anyhow::anyhow!("Unknown timezone '{}' for location {}", tz, name))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            name,
+            query,
+            provider,
+            timezone,
+            labels: config.labels,
+        })
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let started = Instant::now();
     let args = Args::parse();
-
-    // Initialize tracing with the specified log level
-    tracing_subscriber::fmt::init();
+    let shutdown_timeout = Duration::from_secs(args.shutdown_timeout_seconds);
+
+    // Initialize tracing with the specified log level, which the config
+    // file can change at runtime. Subcommands log to stderr so their output
+    // can be piped.
+    let writer = if args.command.is_some() {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
+    let (log_level_filter, log_level) = tracing_subscriber::reload::Layer::new(LevelFilter::INFO);
+    tracing_subscriber::registry()
+        .with(log_level_filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(writer))
+        .init();
+    let cli_log_level = reload::parse_log_level(&args.log_level);
+    log_level.modify(|filter| *filter = cli_log_level)?;
 
     // Validate user agent
     validate_user_agent(&args.user_agent)?;
 
-    // Clean and validate locations
-    let location_names = clean_locations(&args.locations);
-    if location_names.is_empty() {
+    if let Some(Command::Locations(LocationsCommand::Search { query })) = &args.command {
+        let client = provider::http_client(&args.user_agent)?;
+        return commands::search_locations(args.provider.build(client).as_ref(), query).await;
+    }
+
+    let config_file = match &args.config_file {
+        Some(path) => ConfigFile::load(path)?,
+        None => ConfigFile::default(),
+    };
+    if let Some(level) = &config_file.log_level {
+        let level: LevelFilter = level.parse()?;
+        log_level.modify(|filter| *filter = level)?;
+    }
+
+    // Clean and validate locations. Locations from the config file replace
+    // --locations, as they can carry more settings, unless `now` was given
+    // its own.
+    let (cli_locations, from_command) = match &args.command {
+        Some(Command::Now { locations, .. }) if !locations.is_empty() => (locations, true),
+        _ => (&args.locations, false),
+    };
+    let locations_from_file = !config_file.locations.is_empty() && !from_command;
+    let location_specs = location_specs(
+        if from_command {
+            &[]
+        } else {
+            &config_file.locations
+        },
+        cli_locations,
+        args.provider,
+    )?;
+    if location_specs.is_empty() {
         return Err(anyhow::anyhow!("No valid locations provided"));
     }
+    // Stations only report to a running server
+    let stations = match &args.command {
+        Some(Command::Now { .. }) => HashMap::new(),
+        _ => parse_stations(&args.stations)?,
+    };
+    let otlp_resource_attributes =
+        parse_key_values(&args.otlp_resource_attributes, "OTLP resource attribute")?;
+    let otlp_headers = parse_key_values(&args.otlp_headers, "OTLP header")?;
+    let influx_target = match (&args.influx_url, &args.influx_org, &args.influx_bucket) {
+        (None, _, _) => None,
+        (Some(url), Some(org), Some(bucket)) => Some((url.clone(), org.clone(), bucket.clone())),
+        _ => {
+            return Err(anyhow::anyhow!(
+                "--influx-url needs --influx-org and --influx-bucket"
+            ));
+        }
+    };
+    if !stations.is_empty() && args.textfile_directory.is_some() {
+        return Err(anyhow::anyhow!(
+            "Stations upload over HTTP, which --textfile-directory disables"
+        ));
+    }
+    if args.admin_token.is_some() && args.textfile_directory.is_some() {
+        return Err(anyhow::anyhow!(
+            "The admin API is served over HTTP, which --textfile-directory disables"
+        ));
+    }
+    health::validate_fraction(args.ready_fraction)?;
+    if !args.listen_address.is_empty() && args.textfile_directory.is_some() {
+        return Err(anyhow::anyhow!(
+            "--listen-address is for the HTTP server, which --textfile-directory disables"
+        ));
+    }
+    let listen_addresses = if args.listen_address.is_empty() {
+        vec![listen::ListenAddress::Tcp(format!("0.0.0.0:{}", args.port))]
+    } else {
+        args.listen_address.clone()
+    };
+    let web_config = match &args.web_config_file {
+        Some(_) if args.textfile_directory.is_some() => {
+            return Err(anyhow::anyhow!(
+                "--web.config.file configures the HTTP server, which --textfile-directory disables"
+            ));
+        }
+        Some(path) => web::WebConfig::load(path)?,
+        None => web::WebConfig::default(),
+    };
+    // Writing back a file that didn't provide the locations would replace
+    // them on the next start
+    if args.admin_persist && !locations_from_file {
+        return Err(anyhow::anyhow!(
+            "--admin-persist needs the locations to come from --config-file"
+        ));
+    }
+    for (id, station) in &stations {
+        if !location_specs
+            .iter()
+            .any(|spec| spec.name == station.location)
+        {
+            return Err(anyhow::anyhow!(
+                "Station {} is mapped to {}, which is not a monitored location",
+                id,
+                station.location
+            ));
+        }
+    }
+    let location_names: Vec<String> = location_specs
+        .iter()
+        .map(|spec| {
+            let mut description = format!("{} ({}", spec.name, spec.provider);
+            if let Some(id) = &spec.query.id {
+                description.push_str(&format!(", yr_id={}", id));
+            } else if let Some(country) = &spec.query.country {
+                description.push_str(&format!(", country={}", country));
+            }
+            for (key, value) in &spec.labels {
+                description.push_str(&format!(", {}={}", key, value));
+            }
+            description + ")"
+        })
+        .collect();
+    let custom_labels: BTreeSet<String> = location_specs
+        .iter()
+        .flat_map(|spec| spec.labels.keys().cloned())
+        .collect();
 
     // If --check flag is set, just validate and exit
     if args.check {
         println!("✓ Configuration is valid");
+        if let Some(path) = &args.config_file {
+            println!("  Config file: {}", path.display());
+        }
         println!("  User-Agent: {}", args.user_agent);
         println!("  Locations: {}", location_names.join(", "));
-        println!("  Port: {}", args.port);
+        println!("  Default provider: {}", args.provider);
+        if !stations.is_empty() {
+            let mut stations: Vec<String> = stations
+                .iter()
+                .map(|(id, station)| match station.password {
+                    Some(_) => format!("{} → {} (password)", id, station.location),
+                    None => format!("{} → {}", id, station.location),
+                })
+                .collect();
+            stations.sort();
+            println!("  Stations: {}", stations.join(", "));
+        }
+        println!(
+            "  Degree day bases: {}°C heating, {}°C cooling",
+            args.heating_base_celsius, args.cooling_base_celsius
+        );
+        if let Some(state_file) = &args.state_file {
+            println!("  State file: {}", state_file.display());
+        }
+        println!(
+            "  Label scheme: {}",
+            args.label_scheme
+                .to_possible_value()
+                .expect("no skipped variants")
+                .get_name()
+        );
+        if let Some(url) = &args.push_url {
+            println!(
+                "  Push: {} ({}, job={}{})",
+                url,
+                args.push_mode
+                    .to_possible_value()
+                    .expect("no skipped variants")
+                    .get_name(),
+                args.push_job,
+                args.push_instance
+                    .as_ref()
+                    .map(|instance| format!(", instance={}", instance))
+                    .unwrap_or_default()
+            );
+        }
+        if let Some((url, org, bucket)) = &influx_target {
+            println!("  InfluxDB: {} (org {}, bucket {})", url, org, bucket);
+        }
+        if let Some(host) = &args.mqtt_host {
+            println!(
+                "  MQTT: {}:{} (topics {}/<location>/state{})",
+                host,
+                args.mqtt_port,
+                args.mqtt_topic_prefix,
+                if args.mqtt_disable_discovery {
+                    String::new()
+                } else {
+                    format!(", discovery under {}", args.mqtt_discovery_prefix)
+                }
+            );
+        }
+        if let Some(endpoint) = &args.otlp_endpoint {
+            println!(
+                "  OTLP: {} ({})",
+                endpoint,
+                args.otlp_protocol
+                    .to_possible_value()
+                    .expect("no skipped variants")
+                    .get_name()
+            );
+        }
+        if args.admin_token.is_some() {
+            println!(
+                "  Admin API: enabled{}",
+                if args.admin_persist {
+                    ", changes saved to the config file"
+                } else {
+                    ""
+                }
+            );
+        }
+        match &args.textfile_directory {
+            Some(dir) => println!("  Textfile directory: {}", dir.display()),
+            None => println!(
+                "  Listen addresses: {}",
+                listen_addresses
+                    .iter()
+                    .map(|address| address.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+        if let Some(path) = &args.web_config_file {
+            println!(
+                "  Web config: {} (TLS {}, {} basic auth users)",
+                path.display(),
+                if web_config.tls_enabled() {
+                    "on"
+                } else {
+                    "off"
+                },
+                web_config.basic_auth_users.len()
+            );
+        }
         println!("  Log level: {}", args.log_level);
         return Ok(());
     }
 
+    // Register metrics
+    metrics::register(args.label_scheme, custom_labels.into_iter().collect());
+
+    let accuracy = AccuracySettings {
+        lead_hours: args.accuracy_lead_hours,
+        window: chrono::Duration::hours(args.accuracy_window_hours),
+    };
+
+    let degree_day_settings = DegreeDaySettings {
+        heating_base: args.heating_base_celsius,
+        cooling_base: args.cooling_base_celsius,
+    };
+
+    // A one-shot run leaves the state file to the server
+    if let Some(Command::Now { format, hours, .. }) = args.command {
+        let state = AppState::new(
+            location_specs,
+            stations,
+            accuracy,
+            degree_day_settings,
+            None,
+            args.user_agent,
+        )?;
+        return commands::now(&state, format, hours).await;
+    }
+
     info!("Starting Weather Exporter v{}", env!("CARGO_PKG_VERSION"));
     info!("User-Agent: {}", args.user_agent);
     info!("Monitoring locations: {}", location_names.join(", "));
-    info!("Metrics endpoint: http://0.0.0.0:{}/metrics", args.port);
 
-    // Register metrics
-    REGISTRY
-        .register(Box::new(TEMPERATURE.clone()))
-        .expect("collector can be registered");
-    REGISTRY
-        .register(Box::new(HUMIDITY.clone()))
-        .expect("collector can be registered");
-    REGISTRY
-        .register(Box::new(WIND_SPEED.clone()))
-        .expect("collector can be registered");
-    REGISTRY
-        .register(Box::new(WIND_DIRECTION.clone()))
-        .expect("collector can be registered");
-    REGISTRY
-        .register(Box::new(PRESSURE.clone()))
-        .expect("collector can be registered");
-    REGISTRY
-        .register(Box::new(PRECIPITATION.clone()))
-        .expect("collector can be registered");
-    REGISTRY
-        .register(Box::new(CLOUD_COVERAGE.clone()))
-        .expect("collector can be registered");
-    REGISTRY
-        .register(Box::new(UV_INDEX.clone()))
-        .expect("collector can be registered");
-    REGISTRY
-        .register(Box::new(WEATHER_FETCH_SUCCESS.clone()))
-        .expect("collector can be registered");
-    REGISTRY
-        .register(Box::new(WEATHER_CACHE_HITS.clone()))
-        .expect("collector can be registered");
-    REGISTRY
-        .register(Box::new(WEATHER_API_CALLS.clone()))
-        .expect("collector can be registered");
-
-    let state = AppState::new(location_names, args.user_agent)?;
+    let mut state = AppState::new(
+        location_specs,
+        stations,
+        accuracy,
+        degree_day_settings,
+        args.state_file.map(StateFile::new),
+        args.user_agent.clone(),
+    )?;
+
+    // Listening from here on, so a signal during the initial fetch still
+    // saves state
+    let mut signal = std::pin::pin!(shutdown_signal()?);
+
+    // The update loop, servers and reloader stop first, the outputs after
+    // them
+    let (shutdown, stop) = watch::channel(false);
+    let mut tasks = JoinSet::new();
+    let (sink_shutdown, sink_stop) = watch::channel(false);
+    let mut sinks = JoinSet::new();
+
+    if let Some(url) = args.push_url {
+        let settings = PushSettings {
+            url,
+            mode: args.push_mode,
+            interval: Duration::from_secs(
+                config_file
+                    .push_interval_seconds
+                    .unwrap_or(args.push_interval_seconds),
+            ),
+            username: args.push_username,
+            password: args.push_password,
+            job: args.push_job,
+            instance: args.push_instance,
+        };
+        info!(
+            "Pushing metrics to {} at most every {}s",
+            settings.url,
+            settings.interval.as_secs()
+        );
+        let pusher = Arc::new(Pusher::new(
+            settings,
+            provider::http_client(&args.user_agent)?,
+        ));
+        state.pusher = Some(pusher.clone());
+        let stop = sink_stop.clone();
+        sinks.spawn(async move { pusher.run(stop).await });
+    }
+
+    if let Some((url, org, bucket)) = influx_target {
+        info!("Writing to InfluxDB bucket {} at {}", bucket, url);
+        let writer = Arc::new(InfluxWriter::new(
+            InfluxSettings {
+                url,
+                org,
+                bucket,
+                token: args.influx_token,
+                interval: Duration::from_secs(
+                    config_file
+                        .influx_interval_seconds
+                        .unwrap_or(args.influx_interval_seconds),
+                ),
+            },
+            provider::http_client(&args.user_agent)?,
+        ));
+        state.influx = Some(writer.clone());
+        let writer_state = state.clone();
+        let stop = sink_stop.clone();
+        sinks.spawn(async move { writer.run(writer_state, stop).await });
+    }
+
+    if let Some(host) = args.mqtt_host {
+        info!("Publishing to MQTT broker {}:{}", host, args.mqtt_port);
+        let (publisher, connection) = MqttPublisher::new(MqttSettings {
+            host,
+            port: args.mqtt_port,
+            client_id: args.mqtt_client_id,
+            username: args.mqtt_username,
+            password: args.mqtt_password,
+            topic_prefix: args.mqtt_topic_prefix,
+            discovery_prefix: (!args.mqtt_disable_discovery).then_some(args.mqtt_discovery_prefix),
+        });
+        state.mqtt = Some(Arc::new(publisher));
+        sinks.spawn(connection.run(sink_stop.clone()));
+    }
+
+    if let Some(endpoint) = args.otlp_endpoint {
+        info!("Exporting metrics over OTLP to {}", endpoint);
+        let exporter = OtlpExporter::new(OtlpSettings {
+            endpoint,
+            protocol: args.otlp_protocol,
+            interval: Duration::from_secs(
+                config_file
+                    .otlp_interval_seconds
+                    .unwrap_or(args.otlp_interval_seconds),
+            ),
+            resource_attributes: otlp::resource_attributes(&otlp_resource_attributes),
+            headers: otlp_headers,
+        })?;
+        let exporter = Arc::new(exporter);
+        state.otlp = Some(exporter.clone());
+        let stop = sink_stop.clone();
+        sinks.spawn(async move { exporter.run(stop).await });
+    }
+
+    if let Some(seconds) = config_file.update_interval_seconds {
+        state
+            .update_interval
+            .send_replace(Duration::from_secs(seconds));
+    }
+    if let Some(path) = args.config_file.clone() {
+        info!("Watching {} for changes", path.display());
+        let reloader = reload::Reloader::new(
+            state.clone(),
+            path,
+            reload::Defaults {
+                locations: args.locations.clone(),
+                provider: args.provider,
+                log_level: cli_log_level,
+                push_interval: Duration::from_secs(args.push_interval_seconds),
+                influx_interval: Duration::from_secs(args.influx_interval_seconds),
+                otlp_interval: Duration::from_secs(args.otlp_interval_seconds),
+            },
+            log_level,
+        );
+        tasks.spawn(reloader.run(stop.clone()));
+    }
+
+    // Without a listener the update loop is all there is to run
+    if let Some(dir) = args.textfile_directory {
+        let textfile = TextfileWriter::new(dir);
+        info!("Writing metrics to {}", textfile.path().display());
+        state.textfile = Some(Arc::new(textfile));
+        tasks.spawn(periodic_update(state.clone(), stop));
+        signal.await;
+        shut_down(
+            &state,
+            shutdown,
+            tasks,
+            sink_shutdown,
+            sinks,
+            shutdown_timeout,
+            started,
+        )
+        .await;
+        return Ok(());
+    }
+
+    // Bound before the initial fetch, so a taken port fails right away
+    let listeners = listen::bind(&listen_addresses).await?;
+    let scheme = if web_config.tls_enabled() {
+        "https"
+    } else {
+        "http"
+    };
+    for listener in &listeners {
+        match listener {
+            listen::Bound::Tcp(_) => info!("Metrics endpoint: {}://{}/metrics", scheme, listener),
+            listen::Bound::Unix(..) => info!("Metrics endpoint: /metrics on {}", listener),
+        }
+    }
 
     // Initial fetch to validate locations
-    state.update_all_metrics().await;
+    tokio::select! {
+        _ = state.update_all_metrics() => {}
+        _ = &mut signal => {
+            shut_down(
+                &state,
+                shutdown,
+                tasks,
+                sink_shutdown,
+                sinks,
+                shutdown_timeout,
+                started,
+            )
+            .await;
+            return Ok(());
+        }
+    }
 
     // Start background update task
-    let update_state = state.clone();
-    tokio::spawn(periodic_update(update_state));
+    tasks.spawn(periodic_update(state.clone(), stop.clone()));
 
     // Build the router
-    let app = Router::new()
+    let ready_fraction = args.ready_fraction;
+    let mut app = Router::new()
+        .route("/", get(status::handler))
+        .route("/status", get(status::handler))
         .route("/metrics", get(metrics_handler))
+        .route("/metrics.influx", get(influx::handler))
         .route("/health", get(health_handler))
-        .with_state(state);
+        .route("/health/live", get(health::live_handler))
+        .route(
+            "/health/ready",
+            get(move |state| health::ready_handler(state, ready_fraction)),
+        )
+        .route(station::ECOWITT_PATH, post(station::ecowitt_handler))
+        .route(
+            station::WUNDERGROUND_PATH,
+            get(station::wunderground_handler),
+        )
+        .with_state(state.clone())
+        .merge(api::router(state.clone()));
+
+    if let Some(token) = args.admin_token {
+        let config_file = args.config_file.filter(|_| args.admin_persist);
+        match &config_file {
+            Some(path) => info!("Admin API enabled, saving changes to {}", path.display()),
+            None => info!("Admin API enabled"),
+        }
+        app = app.merge(admin::router(
+            state.clone(),
+            admin::AdminSettings {
+                token,
+                config_file,
+                default_provider: args.provider,
+            },
+        ));
+    }
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], args.port));
+    // Compresses when the client asks for it, which Prometheus does
+    app = app.layer(tower_http::compression::CompressionLayer::new());
 
-    let listener = tokio::net::TcpListener::bind(&addr)
-        .await
-        .expect("Failed to bind to address");
+    // Applies to every route, the admin API included
+    if let Some(settings) = web_config.http_settings()? {
+        app = app.layer(axum::middleware::from_fn_with_state(
+            Arc::new(settings),
+            web::middleware,
+        ));
+    }
 
-    info!("Weather exporter listening on {}", addr);
+    // Servers stop accepting connections on shutdown and wait for the
+    // requests being served, including fetches they started
+    let tls = web_config.tls_acceptor()?;
+    for listener in listeners {
+        info!("Weather exporter listening on {}", listener);
+        listener.spawn(&mut tasks, app.clone(), tls.clone(), stop.clone());
+    }
 
-    axum::serve(listener, app)
-        .await
-        .expect("Failed to start server");
+    tokio::select! {
+        _ = signal => {}
+        Some(_) = tasks.join_next() => return Err(anyhow::anyhow!("Server stopped unexpectedly")),
+    }
+    shut_down(
+        &state,
+        shutdown,
+        tasks,
+        sink_shutdown,
+        sinks,
+        shutdown_timeout,
+        started,
+    )
+    .await;
 
     Ok(())
 }
@@ -0,0 +1,747 @@
+use crate::openmetrics;
+use crate::provider::Location;
+use lazy_static::lazy_static;
+use prometheus::core::{Collector, MetricVec, MetricVecBuilder};
+use prometheus::{Encoder, GaugeVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{OnceLock, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Which labels identify a location on the weather value series
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum LabelScheme {
+    /// Only `location`; coordinates are in `weather_location_info`
+    #[default]
+    Info,
+    /// `location`, `latitude` and `longitude` on every series
+    Coordinates,
+}
+
+/// Label names shared by every series of a location
+struct LabelConfig {
+    scheme: LabelScheme,
+    /// User-defined label names, sorted
+    custom: Vec<String>,
+}
+
+static LABEL_CONFIG: OnceLock<LabelConfig> = OnceLock::new();
+
+lazy_static! {
+    /// User-defined label values per location, ordered like `LabelConfig::custom`
+    static ref CUSTOM_LABEL_VALUES: RwLock<HashMap<String, Vec<String>>> =
+        RwLock::new(HashMap::new());
+    /// When the counters of a location last started over from zero, for
+    /// locations whose series were removed since startup
+    static ref COUNTERS_RESET: RwLock<HashMap<String, f64>> = RwLock::new(HashMap::new());
+    /// Unix time the metrics were registered, when every counter was zero
+    static ref REGISTERED: f64 = unix_time();
+}
+
+fn label_config() -> &'static LabelConfig {
+    LABEL_CONFIG.get_or_init(default_label_config)
+}
+
+#[cfg(not(test))]
+fn default_label_config() -> LabelConfig {
+    LabelConfig {
+        scheme: LabelScheme::default(),
+        custom: Vec::new(),
+    }
+}
+
+/// Tests don't register the metrics and get every optional label instead
+#[cfg(test)]
+fn default_label_config() -> LabelConfig {
+    LabelConfig {
+        scheme: LabelScheme::Coordinates,
+        custom: vec!["team".to_string()],
+    }
+}
+
+/// Names for a per-location metric: `location`, the user-defined labels, then `extra`
+fn location_label_names(extra: &[&'static str]) -> Vec<&'static str> {
+    let mut names = vec!["location"];
+    names.extend(label_config().custom.iter().map(String::as_str));
+    names.extend_from_slice(extra);
+    names
+}
+
+/// Like [`location_label_names`], with coordinates if the label scheme asks for them
+fn value_label_names() -> Vec<&'static str> {
+    let mut names = vec!["location"];
+    if value_labels_need_location() {
+        names.extend(["latitude", "longitude"]);
+    }
+    names.extend(label_config().custom.iter().map(String::as_str));
+    names.push("source");
+    names
+}
+
+fn custom_label_values(location_name: &str) -> Vec<String> {
+    let values = CUSTOM_LABEL_VALUES.read().expect("label lock poisoned");
+    values
+        .get(location_name)
+        .cloned()
+        .unwrap_or_else(|| vec![String::new(); label_config().custom.len()])
+}
+
+/// Set the user-defined labels of a location. Labels not given are empty.
+pub fn set_custom_labels(location_name: &str, labels: &BTreeMap<String, String>) {
+    let values = label_config()
+        .custom
+        .iter()
+        .map(|name| labels.get(name).cloned().unwrap_or_default())
+        .collect();
+    CUSTOM_LABEL_VALUES
+        .write()
+        .expect("label lock poisoned")
+        .insert(location_name.to_string(), values);
+}
+
+/// The user-defined label names, fixed once the metrics are registered
+pub fn custom_label_names() -> &'static [String] {
+    &label_config().custom
+}
+
+/// Every per-location gauge
+fn location_gauges() -> [&'static GaugeVec; 24] {
+    [
+        &TEMPERATURE,
+        &HUMIDITY,
+        &WIND_SPEED,
+        &WIND_DIRECTION,
+        &PRESSURE,
+        &PRECIPITATION,
+        &CLOUD_COVERAGE,
+        &UV_INDEX,
+        &FEELS_LIKE,
+        &DEW_POINT,
+        &WIND_CHILL,
+        &HEAT_INDEX,
+        &HUMIDEX,
+        &ABSOLUTE_HUMIDITY,
+        &DAILY_TEMPERATURE_MIN,
+        &DAILY_TEMPERATURE_MAX,
+        &DAILY_TEMPERATURE_MEAN,
+        &DAILY_PRECIPITATION,
+        &DAILY_WIND_GUST_MAX,
+        &DAILY_UV_INDEX_MAX,
+        &FORECAST_ERROR,
+        &FORECAST_MAE,
+        &HEATING_DEGREE_DAYS,
+        &COOLING_DEGREE_DAYS,
+    ]
+}
+
+fn location_int_gauges() -> [&'static IntGaugeVec; 4] {
+    [
+        &LOCATION_INFO,
+        &LOCATION_UTC_OFFSET,
+        &WEATHER_FETCH_SUCCESS,
+        &STATION_LAST_REPORT,
+    ]
+}
+
+fn location_counters() -> [&'static IntCounterVec; 2] {
+    [&WEATHER_CACHE_HITS, &WEATHER_API_CALLS]
+}
+
+/// Drop every series of a location that is no longer monitored, along with
+/// its user-defined labels
+pub fn remove_location(location_name: &str) {
+    for gauge in location_gauges() {
+        remove_series(gauge, location_name);
+    }
+    for gauge in location_int_gauges() {
+        remove_series(gauge, location_name);
+    }
+    for counter in location_counters() {
+        remove_series(counter, location_name);
+    }
+    COUNTERS_RESET
+        .write()
+        .expect("counter lock poisoned")
+        .insert(location_name.to_string(), unix_time());
+
+    CUSTOM_LABEL_VALUES
+        .write()
+        .expect("label lock poisoned")
+        .remove(location_name);
+}
+
+/// Change the user-defined labels of a location, moving its series over to
+/// the new values. Counters keep counting, so they don't get a new created
+/// time.
+pub fn relabel_location(location_name: &str, labels: &BTreeMap<String, String>) {
+    set_custom_labels(location_name, labels);
+    let values: Vec<(&str, String)> = label_config()
+        .custom
+        .iter()
+        .map(String::as_str)
+        .zip(custom_label_values(location_name))
+        .collect();
+
+    for gauge in location_gauges() {
+        move_series(
+            gauge,
+            location_name,
+            &values,
+            |m| m.gauge.value(),
+            |g, v| g.set(v),
+        );
+    }
+    for gauge in location_int_gauges() {
+        move_series(
+            gauge,
+            location_name,
+            &values,
+            |m| m.gauge.value() as i64,
+            |g, v| g.set(v),
+        );
+    }
+    for counter in location_counters() {
+        move_series(
+            counter,
+            location_name,
+            &values,
+            |m| m.counter.value() as u64,
+            |c, v| c.inc_by(v),
+        );
+    }
+}
+
+/// Replace the series of `vec` for `location_name` with ones carrying the
+/// label `values`, copying each value over with `get` and `set`
+fn move_series<T: MetricVecBuilder, V>(
+    vec: &MetricVec<T>,
+    location_name: &str,
+    values: &[(&str, String)],
+    get: impl Fn(&prometheus::proto::Metric) -> V,
+    set: impl Fn(&T::M, V),
+) {
+    for family in vec.collect() {
+        for metric in &family.metric {
+            let labels: HashMap<&str, &str> = metric
+                .label
+                .iter()
+                .map(|pair| (pair.name(), pair.value()))
+                .collect();
+            if labels.get("location") != Some(&location_name) {
+                continue;
+            }
+            let mut moved = labels.clone();
+            moved.extend(values.iter().map(|(name, value)| (*name, value.as_str())));
+            if moved == labels {
+                continue;
+            }
+            let _ = vec.remove(&labels);
+            if let Ok(series) = vec.get_metric_with(&moved) {
+                set(&series, get(metric));
+            }
+        }
+    }
+}
+
+/// Remove the series of `vec` whose `location` label is `location_name`,
+/// whatever their other labels
+fn remove_series<T: MetricVecBuilder>(vec: &MetricVec<T>, location_name: &str) {
+    for family in vec.collect() {
+        for metric in &family.metric {
+            let labels: HashMap<&str, &str> = metric
+                .label
+                .iter()
+                .map(|pair| (pair.name(), pair.value()))
+                .collect();
+            if labels.get("location") == Some(&location_name) {
+                let _ = vec.remove(&labels);
+            }
+        }
+    }
+}
+
+/// Label values for a per-location metric, matching [`location_label_names`]
+pub fn location_labels(location_name: &str, extra: &[&str]) -> Vec<String> {
+    let mut values = vec![location_name.to_string()];
+    values.extend(custom_label_values(location_name));
+    values.extend(extra.iter().map(|v| v.to_string()));
+    values
+}
+
+/// The user-defined labels of a location that have a value, for outputs
+/// other than Prometheus
+pub fn custom_labels(location_name: &str) -> Vec<(&'static str, String)> {
+    label_config()
+        .custom
+        .iter()
+        .map(String::as_str)
+        .zip(custom_label_values(location_name))
+        .filter(|(_, value)| !value.is_empty())
+        .collect()
+}
+
+/// Whether weather value series carry the coordinates, and can't be set
+/// before a location is resolved
+pub fn value_labels_need_location() -> bool {
+    label_config().scheme == LabelScheme::Coordinates
+}
+
+/// Label values for a weather value series, matching [`value_label_names`]
+pub fn value_labels(location_name: &str, location: Option<&Location>, source: &str) -> Vec<String> {
+    let mut values = vec![location_name.to_string()];
+    if value_labels_need_location() {
+        // Rounded like the info metric, so the series join on them
+        let (lat, lon) = location
+            .map(|loc| {
+                let (lat, lon) = loc.position.rounded();
+                (lat.to_string(), lon.to_string())
+            })
+            .unwrap_or_default();
+        values.extend([lat, lon]);
+    }
+    values.extend(custom_label_values(location_name));
+    values.push(source.to_string());
+    values
+}
+
+/// Export the metadata of a resolved location
+pub fn set_location_info(location_name: &str, location: &Location, timezone: &str) {
+    // Rounded like the forecast request, so small geocoding changes don't
+    // produce new series
+    let (lat, lon) = location.position.rounded();
+    let elevation = location
+        .elevation
+        .map(|e| e.to_string())
+        .unwrap_or_default();
+    LOCATION_INFO
+        .with_label_values(&location_labels(
+            location_name,
+            &[
+                &lat.to_string(),
+                &lon.to_string(),
+                &elevation,
+                location.country.as_ref().map_or("", |c| c.name.as_str()),
+                location.region.as_ref().map_or("", |r| r.name.as_str()),
+                location.id.as_deref().unwrap_or_default(),
+                timezone,
+            ],
+        ))
+        .set(1);
+}
+
+// Prometheus metrics
+lazy_static! {
+    pub static ref TEMPERATURE: GaugeVec = GaugeVec::new(
+        Opts::new("weather_temperature_celsius", "Temperature in Celsius"),
+        &value_label_names()
+    )
+    .expect("metric can be created");
+    pub static ref HUMIDITY: GaugeVec = GaugeVec::new(
+        Opts::new("weather_humidity_percent", "Relative humidity percentage"),
+        &value_label_names()
+    )
+    .expect("metric can be created");
+    pub static ref WIND_SPEED: GaugeVec = GaugeVec::new(
+        Opts::new("weather_wind_speed_mps", "Wind speed in meters per second"),
+        &value_label_names()
+    )
+    .expect("metric can be created");
+    pub static ref WIND_DIRECTION: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "weather_wind_direction_degrees",
+            "Wind direction in degrees"
+        ),
+        &value_label_names()
+    )
+    .expect("metric can be created");
+    pub static ref PRESSURE: GaugeVec = GaugeVec::new(
+        Opts::new("weather_pressure_hpa", "Air pressure in hectopascals"),
+        &value_label_names()
+    )
+    .expect("metric can be created");
+    pub static ref PRECIPITATION: GaugeVec = GaugeVec::new(
+        Opts::new("weather_precipitation_mm", "Precipitation in millimeters"),
+        &value_label_names()
+    )
+    .expect("metric can be created");
+    pub static ref CLOUD_COVERAGE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "weather_cloud_coverage_percent",
+            "Cloud coverage percentage"
+        ),
+        &value_label_names()
+    )
+    .expect("metric can be created");
+    pub static ref UV_INDEX: GaugeVec = GaugeVec::new(
+        Opts::new("weather_uv_index", "UV index"),
+        &value_label_names()
+    )
+    .expect("metric can be created");
+    pub static ref FEELS_LIKE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "weather_feels_like_celsius",
+            "Apparent temperature in Celsius"
+        ),
+        &value_label_names()
+    )
+    .expect("metric can be created");
+    pub static ref DEW_POINT: GaugeVec = GaugeVec::new(
+        Opts::new("weather_dew_point_celsius", "Dew point in Celsius"),
+        &value_label_names()
+    )
+    .expect("metric can be created");
+    pub static ref WIND_CHILL: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "weather_wind_chill_celsius",
+            "Wind chill in Celsius, only present when cold and windy"
+        ),
+        &value_label_names()
+    )
+    .expect("metric can be created");
+    pub static ref HEAT_INDEX: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "weather_heat_index_celsius",
+            "Heat index in Celsius, only present when hot and humid"
+        ),
+        &value_label_names()
+    )
+    .expect("metric can be created");
+    pub static ref HUMIDEX: GaugeVec = GaugeVec::new(
+        Opts::new("weather_humidex", "Canadian humidex"),
+        &value_label_names()
+    )
+    .expect("metric can be created");
+    pub static ref ABSOLUTE_HUMIDITY: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "weather_absolute_humidity_grams_per_cubic_meter",
+            "Absolute humidity in grams per cubic meter"
+        ),
+        &value_label_names()
+    )
+    .expect("metric can be created");
+    pub static ref DAILY_TEMPERATURE_MIN: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "weather_daily_temperature_min_celsius",
+            "Forecast minimum temperature for the day"
+        ),
+        &location_label_names(&["day"])
+    )
+    .expect("metric can be created");
+    pub static ref DAILY_TEMPERATURE_MAX: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "weather_daily_temperature_max_celsius",
+            "Forecast maximum temperature for the day"
+        ),
+        &location_label_names(&["day"])
+    )
+    .expect("metric can be created");
+    pub static ref DAILY_TEMPERATURE_MEAN: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "weather_daily_temperature_mean_celsius",
+            "Forecast mean temperature for the day"
+        ),
+        &location_label_names(&["day"])
+    )
+    .expect("metric can be created");
+    pub static ref DAILY_PRECIPITATION: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "weather_daily_precipitation_mm",
+            "Forecast total precipitation for the day"
+        ),
+        &location_label_names(&["day"])
+    )
+    .expect("metric can be created");
+    pub static ref DAILY_WIND_GUST_MAX: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "weather_daily_wind_gust_max_mps",
+            "Forecast strongest wind gust for the day"
+        ),
+        &location_label_names(&["day"])
+    )
+    .expect("metric can be created");
+    pub static ref DAILY_UV_INDEX_MAX: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "weather_daily_uv_index_max",
+            "Forecast highest UV index for the day"
+        ),
+        &location_label_names(&["day"])
+    )
+    .expect("metric can be created");
+    pub static ref LOCATION_INFO: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "weather_location_info",
+            "Metadata about a monitored location, always 1"
+        ),
+        &location_label_names(&[
+            "latitude",
+            "longitude",
+            "elevation",
+            "country",
+            "region",
+            "yr_id",
+            "timezone"
+        ])
+    )
+    .expect("metric can be created");
+    pub static ref LOCATION_UTC_OFFSET: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "weather_location_utc_offset_seconds",
+            "Current offset of the location's local time from UTC"
+        ),
+        &location_label_names(&[])
+    )
+    .expect("metric can be created");
+    pub static ref WEATHER_FETCH_SUCCESS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "weather_fetch_success",
+            "Whether the last weather fetch was successful"
+        ),
+        &location_label_names(&[])
+    )
+    .expect("metric can be created");
+    pub static ref WEATHER_CACHE_HITS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "weather_cache_hits_total",
+            "Number of times cached data was used"
+        ),
+        &location_label_names(&[])
+    )
+    .expect("metric can be created");
+    pub static ref WEATHER_API_CALLS: IntCounterVec = IntCounterVec::new(
+        Opts::new("weather_api_calls_total", "Total number of API calls made"),
+        &location_label_names(&[])
+    )
+    .expect("metric can be created");
+    pub static ref STATION_LAST_REPORT: IntGaugeVec = IntGaugeVec::new(
+        Opts::new(
+            "weather_station_last_report_timestamp_seconds",
+            "Unix time of the last upload received from a weather station"
+        ),
+        &location_label_names(&["station"])
+    )
+    .expect("metric can be created");
+    pub static ref FORECAST_ERROR: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "weather_forecast_error_celsius",
+            "Forecast minus actual temperature for the most recently passed hour"
+        ),
+        &location_label_names(&["lead_hours"])
+    )
+    .expect("metric can be created");
+    pub static ref FORECAST_MAE: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "weather_forecast_mae_celsius",
+            "Rolling mean absolute temperature forecast error"
+        ),
+        &location_label_names(&["lead_hours"])
+    )
+    .expect("metric can be created");
+    // Gauges, as they restart from zero every day or month, which a counter's
+    // created time can't express
+    pub static ref HEATING_DEGREE_DAYS: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "weather_heating_degree_days",
+            "Heating degree days accumulated so far this day or month"
+        ),
+        &location_label_names(&["period"])
+    )
+    .expect("metric can be created");
+    pub static ref COOLING_DEGREE_DAYS: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "weather_cooling_degree_days",
+            "Cooling degree days accumulated so far this day or month"
+        ),
+        &location_label_names(&["period"])
+    )
+    .expect("metric can be created");
+    pub static ref REGISTRY: Registry = Registry::new();
+}
+
+/// Content type of [`gather_text`]
+pub const TEXT_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Everything in [`REGISTRY`] in the Prometheus text exposition format
+pub fn gather_text() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("metrics can be encoded");
+    String::from_utf8(buffer).expect("metrics are valid UTF-8")
+}
+
+/// Everything in [`REGISTRY`] in the OpenMetrics text format
+pub fn gather_openmetrics() -> String {
+    openmetrics::encode(&REGISTRY.gather())
+}
+
+/// Unix time a location's counters started from zero, for `_created`
+pub fn counters_created(location_name: Option<&str>) -> f64 {
+    location_name
+        .and_then(|name| {
+            COUNTERS_RESET
+                .read()
+                .expect("counter lock poisoned")
+                .get(name)
+                .copied()
+        })
+        .unwrap_or(*REGISTERED)
+}
+
+fn unix_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs_f64())
+        .unwrap_or_default()
+}
+
+/// Registers all collectors with the global registry. Must be called before
+/// any metric is used, as the label scheme decides the label names.
+pub fn register(scheme: LabelScheme, custom_labels: Vec<String>) {
+    let config = LabelConfig {
+        scheme,
+        custom: custom_labels,
+    };
+    if LABEL_CONFIG.set(config).is_err() {
+        panic!("metrics are only registered once");
+    }
+    lazy_static::initialize(&REGISTERED);
+
+    REGISTRY
+        .register(Box::new(TEMPERATURE.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(HUMIDITY.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(WIND_SPEED.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(WIND_DIRECTION.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(PRESSURE.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(PRECIPITATION.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(CLOUD_COVERAGE.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(UV_INDEX.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(FEELS_LIKE.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(DEW_POINT.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(WIND_CHILL.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(HEAT_INDEX.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(HUMIDEX.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(ABSOLUTE_HUMIDITY.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(DAILY_TEMPERATURE_MIN.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(DAILY_TEMPERATURE_MAX.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(DAILY_TEMPERATURE_MEAN.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(DAILY_PRECIPITATION.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(DAILY_WIND_GUST_MAX.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(DAILY_UV_INDEX_MAX.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(LOCATION_INFO.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(LOCATION_UTC_OFFSET.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(WEATHER_FETCH_SUCCESS.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(WEATHER_CACHE_HITS.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(WEATHER_API_CALLS.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(STATION_LAST_REPORT.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(FORECAST_ERROR.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(FORECAST_MAE.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(HEATING_DEGREE_DAYS.clone()))
+        .expect("collector can be registered");
+    REGISTRY
+        .register(Box::new(COOLING_DEGREE_DAYS.clone()))
+        .expect("collector can be registered");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::Position;
+
+    #[test]
+    fn value_labels_round_coordinates_like_the_info_metric() {
+        let location = Location {
+            id: None,
+            name: "Oslo".to_string(),
+            position: Position {
+                lat: 59.912_734_9,
+                lon: 10.746_05,
+            },
+            elevation: None,
+            time_zone: None,
+            country: None,
+            region: None,
+        };
+        set_custom_labels("MetricsRounded", &BTreeMap::new());
+        set_location_info("MetricsRounded", &location, "Europe/Oslo");
+
+        let labels = value_labels("MetricsRounded", Some(&location), "forecast");
+        assert_eq!(
+            labels,
+            ["MetricsRounded", "59.9127", "10.7461", "", "forecast"]
+        );
+
+        let info = LOCATION_INFO
+            .collect()
+            .into_iter()
+            .flat_map(|mut family| family.take_metric())
+            .find(|metric| {
+                metric
+                    .label
+                    .iter()
+                    .any(|pair| pair.name() == "location" && pair.value() == "MetricsRounded")
+            })
+            .expect("info metric is set");
+        for (name, value) in [("latitude", &labels[1]), ("longitude", &labels[2])] {
+            let pair = info.label.iter().find(|pair| pair.name() == name).unwrap();
+            assert_eq!(pair.value(), value);
+        }
+        remove_location("MetricsRounded");
+    }
+}
@@ -0,0 +1,685 @@
+//! MQTT sink publishing each location's current values, with Home Assistant
+//! discovery so the sensors show up without configuration.
+
+use crate::comfort;
+use crate::provider::{Location, WeatherResponse};
+use chrono::{DateTime, Utc};
+use ring::digest;
+use rumqttc::{AsyncClient, Event, EventLoop, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{debug, error, info, warn};
+
+// Room for discovery configs of many locations queued before the broker
+// connection is up
+const QUEUE_CAPACITY: usize = 1000;
+
+// Wait before polling again after a connection error, the event loop
+// reconnects on the next poll
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// A Home Assistant sensor reading one field of the state message
+struct Sensor {
+    field: &'static str,
+    name: &'static str,
+    unit: Option<&'static str>,
+    device_class: Option<&'static str>,
+}
+
+const SENSORS: &[Sensor] = &[
+    Sensor {
+        field: "temperature",
+        name: "Temperature",
+        unit: Some("°C"),
+        device_class: Some("temperature"),
+    },
+    Sensor {
+        field: "feels_like",
+        name: "Feels like",
+        unit: Some("°C"),
+        device_class: Some("temperature"),
+    },
+    Sensor {
+        field: "humidity",
+        name: "Humidity",
+        unit: Some("%"),
+        device_class: Some("humidity"),
+    },
+    Sensor {
+        field: "wind_speed",
+        name: "Wind speed",
+        unit: Some("m/s"),
+        device_class: Some("wind_speed"),
+    },
+    Sensor {
+        field: "wind_gust",
+        name: "Wind gust",
+        unit: Some("m/s"),
+        device_class: Some("wind_speed"),
+    },
+    Sensor {
+        field: "wind_direction",
+        name: "Wind direction",
+        unit: Some("°"),
+        device_class: None,
+    },
+    Sensor {
+        field: "pressure",
+        name: "Pressure",
+        unit: Some("hPa"),
+        device_class: Some("atmospheric_pressure"),
+    },
+    Sensor {
+        field: "precipitation",
+        name: "Precipitation next hour",
+        unit: Some("mm"),
+        device_class: Some("precipitation"),
+    },
+    Sensor {
+        field: "cloud_coverage",
+        name: "Cloud coverage",
+        unit: Some("%"),
+        device_class: None,
+    },
+    Sensor {
+        field: "uv_index",
+        name: "UV index",
+        unit: None,
+        device_class: None,
+    },
+];
+
+#[derive(Debug, Clone)]
+pub struct MqttSettings {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// State topics are `<topic_prefix>/<location>/state`
+    pub topic_prefix: String,
+    /// Home Assistant's discovery prefix, discovery is off when `None`
+    pub discovery_prefix: Option<String>,
+}
+
+/// Retained state message for one location
+#[derive(Serialize)]
+struct State {
+    time: DateTime<Utc>,
+    temperature: Option<f64>,
+    feels_like: Option<f64>,
+    humidity: Option<f64>,
+    wind_speed: Option<f64>,
+    wind_gust: Option<f64>,
+    wind_direction: Option<f64>,
+    pressure: Option<f64>,
+    precipitation: Option<f64>,
+    cloud_coverage: Option<f64>,
+    uv_index: Option<f64>,
+    latitude: f64,
+    longitude: f64,
+}
+
+pub struct MqttPublisher {
+    client: AsyncClient,
+    settings: MqttSettings,
+    /// Locations whose discovery config has been published
+    announced: Mutex<HashSet<String>>,
+}
+
+/// Drives the MQTT connection, must be polled for anything to be sent
+pub struct MqttConnection {
+    client: AsyncClient,
+    eventloop: EventLoop,
+    availability_topic: String,
+}
+
+impl MqttPublisher {
+    pub fn new(settings: MqttSettings) -> (Self, MqttConnection) {
+        let availability_topic = availability_topic(&settings.topic_prefix);
+
+        let mut options = MqttOptions::new(&settings.client_id, &settings.host, settings.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        options.set_last_will(LastWill::new(
+            &availability_topic,
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if let Some(username) = &settings.username {
+            options.set_credentials(username, settings.password.clone().unwrap_or_default());
+        }
+
+        let (client, eventloop) = AsyncClient::new(options, QUEUE_CAPACITY);
+        let connection = MqttConnection {
+            client: client.clone(),
+            eventloop,
+            availability_topic,
+        };
+
+        let publisher = Self {
+            client,
+            settings,
+            announced: Mutex::new(HashSet::new()),
+        };
+        (publisher, connection)
+    }
+
+    /// Publish the forecast entry closest to now as `location_name`'s state
+    pub fn publish(&self, location_name: &str, location: &Location, weather: &WeatherResponse) {
+        let Some(current) = weather.closest(Utc::now()) else {
+            return;
+        };
+        let details = &current.data.instant.details;
+
+        let state = State {
+            time: current.time,
+            temperature: details.air_temperature,
+            feels_like: details.air_temperature.map(|temp| {
+                comfort::feels_like(temp, details.relative_humidity, details.wind_speed)
+            }),
+            humidity: details.relative_humidity,
+            wind_speed: details.wind_speed,
+            wind_gust: details.wind_speed_of_gust,
+            wind_direction: details.wind_from_direction,
+            pressure: details.air_pressure_at_sea_level,
+            precipitation: current
+                .data
+                .next_1_hours
+                .as_ref()
+                .and_then(|next| next.details.precipitation_amount),
+            cloud_coverage: details.cloud_area_fraction,
+            uv_index: details.ultraviolet_index_clear_sky,
+            latitude: location.position.lat,
+            longitude: location.position.lon,
+        };
+
+        if let Some(discovery_prefix) = &self.settings.discovery_prefix {
+            let mut announced = self.announced.lock().expect("lock is not poisoned");
+            if !announced.contains(location_name)
+                && self.announce(discovery_prefix, location_name, location)
+            {
+                announced.insert(location_name.to_string());
+            }
+        }
+
+        match serde_json::to_vec(&state) {
+            Ok(payload) => {
+                self.send(&self.state_topic(location_name), payload);
+            }
+            Err(e) => error!("Failed to encode MQTT state for {}: {}", location_name, e),
+        }
+    }
+
+    /// Publish retained Home Assistant discovery configs, one sensor per
+    /// field. Returns whether all of them were queued.
+    fn announce(&self, discovery_prefix: &str, location_name: &str, location: &Location) -> bool {
+        let node_id = node_id(location_name);
+        let device = json!({
+            "identifiers": [node_id],
+            "name": format!("Weather {}", location_name),
+            "manufacturer": "weather-exporter",
+            "model": location.describe(),
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        });
+
+        for sensor in SENSORS {
+            let mut config = json!({
+                "name": sensor.name,
+                "unique_id": format!("{}_{}", node_id, sensor.field),
+                "object_id": format!("{}_{}", node_id, sensor.field),
+                "state_topic": self.state_topic(location_name),
+                "value_template": format!("{{{{ value_json.{} }}}}", sensor.field),
+                "state_class": "measurement",
+                "availability_topic": availability_topic(&self.settings.topic_prefix),
+                "device": device,
+            });
+            if let Some(unit) = sensor.unit {
+                config["unit_of_measurement"] = json!(unit);
+            }
+            if let Some(device_class) = sensor.device_class {
+                config["device_class"] = json!(device_class);
+            }
+
+            let topic = discovery_topic(discovery_prefix, location_name, sensor);
+            if !self.send(&topic, config.to_string().into_bytes()) {
+                return false;
+            }
+        }
+        info!("Published Home Assistant discovery for {}", location_name);
+        true
+    }
+
+    /// Clear the retained state and discovery configs of a location that is
+    /// no longer monitored, so neither the broker nor Home Assistant keep it
+    pub fn remove(&self, location_name: &str) {
+        self.announced
+            .lock()
+            .expect("lock is not poisoned")
+            .remove(location_name);
+
+        // An empty retained message deletes the retained one
+        self.send(&self.state_topic(location_name), Vec::new());
+        if let Some(discovery_prefix) = &self.settings.discovery_prefix {
+            for sensor in SENSORS {
+                self.send(
+                    &discovery_topic(discovery_prefix, location_name, sensor),
+                    Vec::new(),
+                );
+            }
+        }
+        info!("Cleared MQTT topics of {}", location_name);
+    }
+
+    fn state_topic(&self, location_name: &str) -> String {
+        format!(
+            "{}/{}/state",
+            self.settings.topic_prefix,
+            slug(location_name)
+        )
+    }
+
+    /// Queue a retained message. Updates must not wait on the broker, so
+    /// messages are dropped while the queue is full.
+    fn send(&self, topic: &str, payload: Vec<u8>) -> bool {
+        match self
+            .client
+            .try_publish(topic, QoS::AtLeastOnce, true, payload)
+        {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Dropped MQTT message for {}: {}", topic, e);
+                false
+            }
+        }
+    }
+}
+
+impl MqttConnection {
+    /// Keep the connection up until `stop` changes, then disconnect cleanly
+    pub async fn run(mut self, mut stop: watch::Receiver<bool>) {
+        loop {
+            let event = tokio::select! {
+                event = self.eventloop.poll() => event,
+                _ = stop.changed() => break,
+            };
+            match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("Connected to MQTT broker");
+                    if let Err(e) = self.client.try_publish(
+                        &self.availability_topic,
+                        QoS::AtLeastOnce,
+                        true,
+                        "online",
+                    ) {
+                        warn!("Failed to publish MQTT availability: {}", e);
+                    }
+                }
+                Ok(event) => debug!("MQTT event: {:?}", event),
+                Err(e) => {
+                    error!("MQTT connection error: {}", e);
+                    tokio::select! {
+                        _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+                        _ = stop.changed() => break,
+                    }
+                }
+            }
+        }
+        self.disconnect().await;
+    }
+
+    /// Send what is still queued, then mark the exporter offline, which the
+    /// broker doesn't do with the last will on a clean disconnect
+    async fn disconnect(&mut self) {
+        if let Err(e) =
+            self.client
+                .try_publish(&self.availability_topic, QoS::AtLeastOnce, true, "offline")
+        {
+            warn!("Failed to publish MQTT availability: {}", e);
+        }
+        if self.client.try_disconnect().is_err() {
+            return;
+        }
+        loop {
+            match self.eventloop.poll().await {
+                Ok(Event::Outgoing(Outgoing::Disconnect)) => {
+                    info!("Disconnected from MQTT broker");
+                    return;
+                }
+                Ok(event) => debug!("MQTT event: {:?}", event),
+                // Not connected, queued messages are lost
+                Err(e) => {
+                    warn!("MQTT connection error while disconnecting: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Home Assistant device ID of a location
+fn node_id(location_name: &str) -> String {
+    format!("weather_exporter_{}", slug(location_name))
+}
+
+fn discovery_topic(discovery_prefix: &str, location_name: &str, sensor: &Sensor) -> String {
+    format!(
+        "{}/sensor/{}/{}/config",
+        discovery_prefix,
+        node_id(location_name),
+        sensor.field
+    )
+}
+
+fn availability_topic(topic_prefix: &str) -> String {
+    format!("{}/status", topic_prefix)
+}
+
+/// Topic and ID safe form of a location name, e.g. `New York` →
+/// `new_york_815eca`. Names that only differ in case, punctuation or letters
+/// outside ASCII, like `Malmö` and `Malmå`, are told apart by the hash.
+fn slug(name: &str) -> String {
+    let readable: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let hash: String = digest::digest(&digest::SHA256, name.as_bytes()).as_ref()[..3]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("{}_{}", readable, hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppState;
+    use std::sync::Arc;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    /// What the broker stand-in received
+    #[derive(Debug, PartialEq)]
+    enum Received {
+        Connect {
+            client_id: String,
+            will: Option<(String, Vec<u8>, bool)>,
+        },
+        Publish {
+            topic: String,
+            payload: Vec<u8>,
+            retain: bool,
+        },
+        Disconnect,
+    }
+
+    async fn read_string(reader: &mut (impl AsyncRead + Unpin)) -> String {
+        let len = reader.read_u16().await.unwrap();
+        let mut bytes = vec![0; len as usize];
+        reader.read_exact(&mut bytes).await.unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    /// A minimal MQTT 3.1.1 broker for one connection, answering CONNECT,
+    /// PUBLISH with QoS 1 and PINGREQ
+    async fn broker() -> (u16, mpsc::UnboundedReceiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, received) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            loop {
+                let Ok(first) = stream.read_u8().await else {
+                    return;
+                };
+                let mut len = 0usize;
+                for shift in (0..4).map(|i| i * 7) {
+                    let byte = stream.read_u8().await.unwrap();
+                    len |= ((byte & 0x7f) as usize) << shift;
+                    if byte & 0x80 == 0 {
+                        break;
+                    }
+                }
+                let mut body = vec![0; len];
+                stream.read_exact(&mut body).await.unwrap();
+                let mut body = body.as_slice();
+
+                match first >> 4 {
+                    1 => {
+                        assert_eq!(read_string(&mut body).await, "MQTT");
+                        let _level = body.read_u8().await.unwrap();
+                        let flags = body.read_u8().await.unwrap();
+                        let _keep_alive = body.read_u16().await.unwrap();
+                        let client_id = read_string(&mut body).await;
+                        let will = if flags & 0x04 != 0 {
+                            let topic = read_string(&mut body).await;
+                            let len = body.read_u16().await.unwrap();
+                            let mut message = vec![0; len as usize];
+                            body.read_exact(&mut message).await.unwrap();
+                            Some((topic, message, flags & 0x20 != 0))
+                        } else {
+                            None
+                        };
+                        let _ = sender.send(Received::Connect { client_id, will });
+                        stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+                    }
+                    3 => {
+                        let topic = read_string(&mut body).await;
+                        let id = if (first >> 1) & 0x03 > 0 {
+                            Some(body.read_u16().await.unwrap())
+                        } else {
+                            None
+                        };
+                        let _ = sender.send(Received::Publish {
+                            topic,
+                            payload: body.to_vec(),
+                            retain: first & 0x01 != 0,
+                        });
+                        // The client may already have hung up after its last
+                        // publish, as it doesn't wait for the acknowledgement
+                        if let Some([high, low]) = id.map(u16::to_be_bytes) {
+                            let _ = stream.write_all(&[0x40, 0x02, high, low]).await;
+                        }
+                    }
+                    12 => stream.write_all(&[0xd0, 0x00]).await.unwrap(),
+                    14 => {
+                        let _ = sender.send(Received::Disconnect);
+                        return;
+                    }
+                    other => panic!("Unexpected MQTT packet type {}", other),
+                }
+            }
+        });
+        (port, received)
+    }
+
+    async fn next(received: &mut mpsc::UnboundedReceiver<Received>) -> Received {
+        tokio::time::timeout(Duration::from_secs(10), received.recv())
+            .await
+            .expect("broker received nothing within 10s")
+            .expect("broker stopped")
+    }
+
+    fn publish(topic: &str, payload: &[u8]) -> Received {
+        Received::Publish {
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            retain: true,
+        }
+    }
+
+    #[test]
+    fn slugs_are_topic_safe_and_distinct() {
+        assert_eq!(slug("New York"), "new_york_815eca");
+        assert!(slug("Zürich/Nord").starts_with("z_rich_nord_"));
+
+        let names = [
+            "Malmö", "Malmå", "Malm_", "New York", "new york", "New_York", "Zürich", "Zärich",
+        ];
+        let slugs: HashSet<String> = names.iter().map(|name| slug(name)).collect();
+        assert_eq!(slugs.len(), names.len());
+        for slug in slugs {
+            assert!(
+                slug.chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'),
+                "{}",
+                slug
+            );
+        }
+    }
+
+    fn settings(port: u16) -> MqttSettings {
+        MqttSettings {
+            host: "127.0.0.1".to_string(),
+            port,
+            client_id: "weather-test".to_string(),
+            username: None,
+            password: None,
+            topic_prefix: "weather".to_string(),
+            discovery_prefix: Some("homeassistant".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn publishes_state_and_discovery_then_disconnects() {
+        let (port, mut received) = broker().await;
+        let (publisher, connection) = MqttPublisher::new(settings(port));
+        let (stop, stopped) = watch::channel(false);
+        let run = tokio::spawn(connection.run(stopped));
+
+        assert_eq!(
+            next(&mut received).await,
+            Received::Connect {
+                client_id: "weather-test".to_string(),
+                will: Some(("weather/status".to_string(), b"offline".to_vec(), true)),
+            }
+        );
+        assert_eq!(
+            next(&mut received).await,
+            publish("weather/status", b"online")
+        );
+
+        let location: Location = serde_json::from_value(json!({
+            "name": "New York",
+            "position": { "lat": 40.7143, "lon": -74.006 },
+            "elevation": null,
+            "id": null,
+            "country": { "id": "US", "name": "United States" },
+            "region": null
+        }))
+        .unwrap();
+        let weather: WeatherResponse = serde_json::from_value(json!({
+            "properties": { "timeseries": [{
+                "time": Utc::now(),
+                "data": {
+                    "instant": { "details": { "air_temperature": 21.5, "relative_humidity": 40.0 } },
+                    "next_1_hours": null
+                }
+            }] }
+        }))
+        .unwrap();
+        publisher.publish("New York", &location, &weather);
+        // Discovery is only sent once
+        publisher.publish("New York", &location, &weather);
+
+        let node_id = format!("weather_exporter_{}", slug("New York"));
+        let state_topic = format!("weather/{}/state", slug("New York"));
+        for sensor in SENSORS {
+            let Received::Publish {
+                topic,
+                payload,
+                retain,
+            } = next(&mut received).await
+            else {
+                panic!("Expected a discovery config");
+            };
+            assert_eq!(
+                topic,
+                format!("homeassistant/sensor/{}/{}/config", node_id, sensor.field)
+            );
+            assert!(retain);
+            let config: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+            assert_eq!(config["state_topic"], state_topic);
+            assert_eq!(config["availability_topic"], "weather/status");
+            assert_eq!(config["device"]["model"], "New York, United States");
+        }
+        for _ in 0..2 {
+            let Received::Publish { topic, payload, .. } = next(&mut received).await else {
+                panic!("Expected a state message");
+            };
+            assert_eq!(topic, state_topic);
+            let state: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+            assert_eq!(state["temperature"], 21.5);
+            assert_eq!(state["humidity"], 40.0);
+            assert_eq!(state["wind_speed"], serde_json::Value::Null);
+            assert_eq!(state["longitude"], -74.006);
+        }
+
+        // Shutting down marks the exporter offline, which the broker wouldn't
+        // do with the last will on a clean disconnect
+        stop.send_replace(true);
+        assert_eq!(
+            next(&mut received).await,
+            publish("weather/status", b"offline")
+        );
+        assert_eq!(next(&mut received).await, Received::Disconnect);
+        tokio::time::timeout(Duration::from_secs(10), run)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn removing_a_location_clears_its_retained_topics() {
+        let (port, mut received) = broker().await;
+        let (publisher, connection) = MqttPublisher::new(settings(port));
+        let (stop, stopped) = watch::channel(false);
+        let run = tokio::spawn(connection.run(stopped));
+        assert!(matches!(
+            next(&mut received).await,
+            Received::Connect { .. }
+        ));
+        assert_eq!(
+            next(&mut received).await,
+            publish("weather/status", b"online")
+        );
+
+        // Through the state, as the admin API and config reloads remove them
+        let mut state = AppState::for_tests("locations: [MqttGone, MqttKept]");
+        state.mqtt = Some(Arc::new(publisher));
+        assert!(state.remove_location("MqttGone").await);
+
+        assert_eq!(
+            next(&mut received).await,
+            publish(&format!("weather/{}/state", slug("MqttGone")), b"")
+        );
+        for sensor in SENSORS {
+            assert_eq!(
+                next(&mut received).await,
+                publish(&discovery_topic("homeassistant", "MqttGone", sensor), b"")
+            );
+        }
+
+        stop.send_replace(true);
+        assert_eq!(
+            next(&mut received).await,
+            publish("weather/status", b"offline")
+        );
+        tokio::time::timeout(Duration::from_secs(10), run)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+//! The OpenMetrics text format, served on `/metrics` to scrapers that ask
+//! for it. The prometheus crate only encodes the older text format.
+
+use crate::metrics::counters_created;
+use axum::http::{HeaderMap, header};
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+use std::fmt::Write;
+
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Units the exporter's metric names end in, for `# UNIT`
+const UNITS: &[&str] = &[
+    "celsius",
+    "percent",
+    "degrees",
+    "hpa",
+    "mm",
+    "mps",
+    "seconds",
+    "grams_per_cubic_meter",
+];
+
+/// Encode `families`, skipping histograms and summaries as the exporter has
+/// none
+pub fn encode(families: &[MetricFamily]) -> String {
+    let mut out = String::new();
+    for family in families {
+        let (kind, name) = match family.type_() {
+            MetricType::GAUGE => ("gauge", family.name()),
+            // The family is named without the suffix its samples carry
+            MetricType::COUNTER => (
+                "counter",
+                family
+                    .name()
+                    .strip_suffix("_total")
+                    .unwrap_or(family.name()),
+            ),
+            MetricType::UNTYPED => ("unknown", family.name()),
+            _ => continue,
+        };
+
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        if let Some(unit) = UNITS
+            .iter()
+            .find(|unit| name.ends_with(&format!("_{}", unit)))
+        {
+            let _ = writeln!(out, "# UNIT {} {}", name, unit);
+        }
+        let _ = writeln!(out, "# HELP {} {}", name, escape(family.help()));
+
+        for metric in &family.metric {
+            let labels = labels(&metric.label);
+            match family.type_() {
+                MetricType::COUNTER => {
+                    let location = metric
+                        .label
+                        .iter()
+                        .find(|pair| pair.name() == "location")
+                        .map(|pair| pair.value());
+                    let _ = writeln!(
+                        out,
+                        "{}_total{} {}",
+                        name,
+                        labels,
+                        value(metric.counter.value())
+                    );
+                    let _ = writeln!(
+                        out,
+                        "{}_created{} {}",
+                        name,
+                        labels,
+                        counters_created(location)
+                    );
+                }
+                MetricType::GAUGE => {
+                    let _ = writeln!(out, "{}{} {}", name, labels, value(metric.gauge.value()));
+                }
+                _ => {
+                    let _ = writeln!(out, "{}{} {}", name, labels, value(metric.untyped.value()));
+                }
+            }
+        }
+    }
+    out.push_str("# EOF\n");
+    out
+}
+
+fn labels(pairs: &[LabelPair]) -> String {
+    if pairs.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = pairs
+        .iter()
+        .map(|pair| format!("{}=\"{}\"", pair.name(), escape(pair.value())))
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+fn value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Escape a label value or help text
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '"' => escaped.push_str("\\\""),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Whether the `Accept` header prefers OpenMetrics over the Prometheus text
+/// format, going by quality values and then order, like Prometheus' own
+/// negotiation
+pub fn requested(headers: &HeaderMap) -> bool {
+    let mut best: Option<(f32, bool)> = None;
+    for accept in headers.get_all(header::ACCEPT) {
+        let Ok(accept) = accept.to_str() else {
+            continue;
+        };
+        for range in accept.split(',') {
+            let mut params = range.split(';').map(str::trim);
+            let openmetrics = match params.next().unwrap_or_default() {
+                "application/openmetrics-text" => true,
+                "text/plain" | "*/*" => false,
+                _ => continue,
+            };
+            let mut quality = 1.0;
+            for param in params {
+                match param.split_once('=') {
+                    Some(("q", q)) => quality = q.trim().parse().unwrap_or(0.0),
+                    // Only 1.0.0 is produced, and 0.0.1 is the same format
+                    Some(("version", version))
+                        if openmetrics && !matches!(version.trim(), "1.0.0" | "0.0.1") =>
+                    {
+                        quality = 0.0;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            if quality > 0.0 && best.is_none_or(|(best, _)| quality > best) {
+                best = Some((quality, openmetrics));
+            }
+        }
+    }
+    best.is_some_and(|(_, openmetrics)| openmetrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use prometheus::{Encoder, GaugeVec, IntCounterVec, Opts, Registry};
+
+    fn accept(values: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(header::ACCEPT, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn requested_follows_quality_and_order() {
+        assert!(!requested(&HeaderMap::new()));
+        assert!(!requested(&accept(&["text/plain"])));
+        assert!(requested(&accept(&["application/openmetrics-text"])));
+        // What Prometheus sends
+        assert!(requested(&accept(&[
+            "application/openmetrics-text;version=1.0.0;q=0.5,application/openmetrics-text;version=0.0.1;q=0.4,text/plain;version=0.0.4;q=0.3,*/*;q=0.2"
+        ])));
+        assert!(!requested(&accept(&[
+            "text/plain;q=0.9,application/openmetrics-text;q=0.5"
+        ])));
+        // Ties go to the earlier entry
+        assert!(requested(&accept(&[
+            "application/openmetrics-text, text/plain"
+        ])));
+        assert!(!requested(&accept(&[
+            "text/plain",
+            "application/openmetrics-text"
+        ])));
+    }
+
+    #[test]
+    fn requested_ignores_unknown_versions_and_zero_quality() {
+        assert!(!requested(&accept(&[
+            "application/openmetrics-text;version=2.0.0"
+        ])));
+        assert!(!requested(&accept(&[
+            "application/openmetrics-text;q=0,text/plain;q=0.1"
+        ])));
+        assert!(requested(&accept(&[
+            "application/json,application/openmetrics-text; version=0.0.1"
+        ])));
+    }
+
+    #[test]
+    fn encode_types_units_and_counters() {
+        let registry = Registry::new();
+        let gauge = GaugeVec::new(
+            Opts::new("test_temperature_celsius", "Line one\nwith \"quotes\""),
+            &["location"],
+        )
+        .unwrap();
+        let counter =
+            IntCounterVec::new(Opts::new("test_calls_total", "Calls made"), &["location"]).unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        gauge.with_label_values(&["Back\\slash"]).set(f64::NAN);
+        counter.with_label_values(&["OpenMetrics test"]).inc_by(3);
+
+        let created = counters_created(Some("OpenMetrics test"));
+        let expected = format!(
+            "# TYPE test_calls counter\n\
+             # HELP test_calls Calls made\n\
+             test_calls_total{{location=\"OpenMetrics test\"}} 3\n\
+             test_calls_created{{location=\"OpenMetrics test\"}} {}\n\
+             # TYPE test_temperature_celsius gauge\n\
+             # UNIT test_temperature_celsius celsius\n\
+             # HELP test_temperature_celsius Line one\\nwith \\\"quotes\\\"\n\
+             test_temperature_celsius{{location=\"Back\\\\slash\"}} NaN\n\
+             # EOF\n",
+            created
+        );
+        assert_eq!(encode(&registry.gather()), expected);
+
+        // The classic format keeps the suffix on the family name
+        let mut text = Vec::new();
+        prometheus::TextEncoder::new()
+            .encode(&registry.gather(), &mut text)
+            .unwrap();
+        assert!(
+            String::from_utf8(text)
+                .unwrap()
+                .contains("# TYPE test_calls_total counter")
+        );
+    }
+
+    #[test]
+    fn encode_special_values() {
+        assert_eq!(value(f64::INFINITY), "+Inf");
+        assert_eq!(value(f64::NEG_INFINITY), "-Inf");
+        assert_eq!(value(1.5), "1.5");
+        assert_eq!(encode(&[]), "# EOF\n");
+    }
+}
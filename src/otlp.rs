@@ -0,0 +1,457 @@
+//! Periodic export of the registry as OTLP metrics, over gRPC or HTTP.
+
+use crate::metrics::{REGISTRY, counters_created};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use opentelemetry_proto::tonic::collector::metrics::v1::{
+    ExportMetricsServiceRequest, metrics_service_client::MetricsServiceClient,
+};
+use opentelemetry_proto::tonic::common::v1::{AnyValue, InstrumentationScope, KeyValue, any_value};
+use opentelemetry_proto::tonic::metrics::v1::{
+    AggregationTemporality, Gauge, Metric, NumberDataPoint, ResourceMetrics, ScopeMetrics, Sum,
+    metric, number_data_point,
+};
+use opentelemetry_proto::tonic::resource::v1::Resource;
+use prometheus::proto::{MetricFamily, MetricType};
+use prost::Message;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::watch;
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+use tracing::{debug, error, info};
+
+/// OTLP transport
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OtlpProtocol {
+    /// OTLP/gRPC, usually on port 4317
+    Grpc,
+    /// OTLP/HTTP with protobuf bodies, usually on port 4318
+    HttpProtobuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct OtlpSettings {
+    pub endpoint: String,
+    pub protocol: OtlpProtocol,
+    pub interval: Duration,
+    /// Attributes of the exporter instance, sent with every export
+    pub resource_attributes: Vec<(String, String)>,
+    /// Extra request headers, e.g. for authentication
+    pub headers: Vec<(String, String)>,
+}
+
+enum Transport {
+    Grpc {
+        client: Box<MetricsServiceClient<Channel>>,
+        metadata: MetadataMap,
+    },
+    Http {
+        client: reqwest::Client,
+        url: String,
+    },
+}
+
+/// Sends all series in the registry to an OTLP receiver on a fixed interval
+pub struct OtlpExporter {
+    transport: Transport,
+    interval: watch::Sender<Duration>,
+    resource: Resource,
+}
+
+impl OtlpExporter {
+    pub fn new(settings: OtlpSettings) -> Result<Self> {
+        let transport = match settings.protocol {
+            OtlpProtocol::Grpc => {
+                let mut endpoint = Endpoint::from_shared(settings.endpoint.clone())
+                    .with_context(|| format!("Invalid OTLP endpoint {}", settings.endpoint))?;
+                if settings.endpoint.starts_with("https://") {
+                    endpoint = endpoint.tls_config(ClientTlsConfig::new().with_webpki_roots())?;
+                }
+
+                let mut metadata = MetadataMap::new();
+                for (name, value) in &settings.headers {
+                    let key = MetadataKey::from_str(&name.to_ascii_lowercase())
+                        .with_context(|| format!("Invalid OTLP header name {}", name))?;
+                    let value = MetadataValue::from_str(value)
+                        .with_context(|| format!("Invalid value for OTLP header {}", name))?;
+                    metadata.insert(key, value);
+                }
+
+                // Connects on first use, so an unreachable collector doesn't
+                // keep the exporter from starting
+                Transport::Grpc {
+                    client: Box::new(MetricsServiceClient::new(endpoint.connect_lazy())),
+                    metadata,
+                }
+            }
+            OtlpProtocol::HttpProtobuf => {
+                let mut headers = reqwest::header::HeaderMap::new();
+                for (name, value) in &settings.headers {
+                    headers.insert(
+                        reqwest::header::HeaderName::from_str(name)
+                            .with_context(|| format!("Invalid OTLP header name {}", name))?,
+                        reqwest::header::HeaderValue::from_str(value)
+                            .with_context(|| format!("Invalid value for OTLP header {}", name))?,
+                    );
+                }
+                let client = reqwest::Client::builder()
+                    .default_headers(headers)
+                    .timeout(Duration::from_secs(30))
+                    .build()?;
+
+                // The base endpoint gets the signal path appended, as with
+                // OTEL_EXPORTER_OTLP_ENDPOINT
+                let base = settings.endpoint.trim_end_matches('/');
+                let url = if base.ends_with("/v1/metrics") {
+                    base.to_string()
+                } else {
+                    format!("{}/v1/metrics", base)
+                };
+                Transport::Http { client, url }
+            }
+        };
+
+        Ok(Self {
+            transport,
+            interval: watch::Sender::new(settings.interval),
+            resource: Resource {
+                attributes: settings
+                    .resource_attributes
+                    .iter()
+                    .map(|(key, value)| string_attribute(key, value))
+                    .collect(),
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Change how often metrics are exported, e.g. on a config reload
+    pub fn set_interval(&self, interval: Duration) {
+        self.interval
+            .send_if_modified(|current| std::mem::replace(current, interval) != interval);
+    }
+
+    /// Export every interval until `stop` changes, then export once more
+    pub async fn run(&self, mut stop: watch::Receiver<bool>) {
+        let mut period = self.interval.subscribe();
+        let mut interval = tokio::time::interval(*period.borrow_and_update());
+        // The first tick would fire before the initial update is done
+        interval.tick().await;
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                Ok(()) = period.changed() => {
+                    let period = *period.borrow_and_update();
+                    info!("Exporting metrics over OTLP every {}s", period.as_secs());
+                    interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+                    continue;
+                }
+                _ = stop.changed() => break,
+            }
+            self.export_logged().await;
+        }
+        self.export_logged().await;
+    }
+
+    async fn export_logged(&self) {
+        match self.export().await {
+            Ok(()) => debug!("Exported metrics over OTLP"),
+            Err(e) => error!("Failed to export metrics over OTLP: {:#}", e),
+        }
+    }
+
+    async fn export(&self) -> Result<()> {
+        let request = self.request(&REGISTRY.gather());
+
+        match &self.transport {
+            Transport::Grpc { client, metadata } => {
+                let mut request = tonic::Request::new(request);
+                *request.metadata_mut() = metadata.clone();
+                client.as_ref().clone().export(request).await?;
+            }
+            Transport::Http { client, url } => {
+                client
+                    .post(url)
+                    .header("Content-Type", "application/x-protobuf")
+                    .body(request.encode_to_vec())
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Gauges become OTLP gauges and counters cumulative sums, with the
+    /// Prometheus labels as data point attributes
+    fn request(&self, families: &[MetricFamily]) -> ExportMetricsServiceRequest {
+        let now = unix_nanos();
+
+        let metrics = families
+            .iter()
+            .filter_map(|family| {
+                let data_points = |value: fn(&prometheus::proto::Metric) -> f64| {
+                    family
+                        .metric
+                        .iter()
+                        .map(|metric| NumberDataPoint {
+                            attributes: metric
+                                .label
+                                .iter()
+                                .map(|pair| string_attribute(pair.name(), pair.value()))
+                                .collect(),
+                            time_unix_nano: now,
+                            value: Some(number_data_point::Value::AsDouble(value(metric))),
+                            ..Default::default()
+                        })
+                        .collect::<Vec<_>>()
+                };
+
+                let data = match family.type_() {
+                    MetricType::GAUGE => metric::Data::Gauge(Gauge {
+                        data_points: data_points(|metric| metric.gauge.value()),
+                    }),
+                    MetricType::COUNTER => metric::Data::Sum(Sum {
+                        data_points: data_points(|metric| metric.counter.value())
+                            .into_iter()
+                            .zip(&family.metric)
+                            .map(|(point, metric)| NumberDataPoint {
+                                start_time_unix_nano: start_time(metric),
+                                ..point
+                            })
+                            .collect(),
+                        aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                        is_monotonic: true,
+                    }),
+                    // The exporter has no histograms or summaries
+                    _ => return None,
+                };
+
+                Some(Metric {
+                    name: family.name().to_string(),
+                    description: family.help().to_string(),
+                    data: Some(data),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(self.resource.clone()),
+                scope_metrics: vec![ScopeMetrics {
+                    scope: Some(InstrumentationScope {
+                        name: env!("CARGO_PKG_NAME").to_string(),
+                        version: env!("CARGO_PKG_VERSION").to_string(),
+                        ..Default::default()
+                    }),
+                    metrics,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        }
+    }
+}
+
+/// Default resource attributes, overridden by `configured` ones with the
+/// same key
+pub fn resource_attributes(configured: &[(String, String)]) -> Vec<(String, String)> {
+    let mut attributes = vec![
+        (
+            "service.name".to_string(),
+            env!("CARGO_PKG_NAME").to_string(),
+        ),
+        (
+            "service.version".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+    ];
+    if let Some(host) = hostname() {
+        attributes.push(("host.name".to_string(), host));
+    }
+
+    attributes.retain(|(key, _)| !configured.iter().any(|(k, _)| k == key));
+    attributes.extend(configured.iter().cloned());
+    attributes
+}
+
+fn hostname() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+}
+
+fn string_attribute(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::StringValue(value.to_string())),
+        }),
+    }
+}
+
+/// When a counter started from zero, which is later than the exporter's start
+/// for locations that were removed and added again
+fn start_time(metric: &prometheus::proto::Metric) -> u64 {
+    let location = metric
+        .label
+        .iter()
+        .find(|pair| pair.name() == "location")
+        .map(|pair| pair.value());
+    (counters_created(location) * 1e9) as u64
+}
+
+fn unix_nanos() -> u64 {
+    Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_proto::tonic::common::v1::any_value::Value;
+    use prometheus::{GaugeVec, Histogram, HistogramOpts, IntCounterVec, Opts, Registry};
+
+    fn exporter(resource_attributes: &[(&str, &str)]) -> OtlpExporter {
+        OtlpExporter::new(OtlpSettings {
+            endpoint: "http://127.0.0.1:4318".to_string(),
+            protocol: OtlpProtocol::HttpProtobuf,
+            interval: Duration::from_secs(60),
+            resource_attributes: resource_attributes
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            headers: Vec::new(),
+        })
+        .unwrap()
+    }
+
+    fn attributes(attributes: &[KeyValue]) -> Vec<(&str, &str)> {
+        attributes
+            .iter()
+            .map(|attribute| {
+                let Some(Value::StringValue(value)) = attribute
+                    .value
+                    .as_ref()
+                    .and_then(|value| value.value.as_ref())
+                else {
+                    panic!("{} is not a string", attribute.key);
+                };
+                (attribute.key.as_str(), value.as_str())
+            })
+            .collect()
+    }
+
+    fn value(point: &NumberDataPoint) -> f64 {
+        match point.value {
+            Some(number_data_point::Value::AsDouble(value)) => value,
+            _ => panic!("Expected a double"),
+        }
+    }
+
+    #[test]
+    fn request_has_gauges_cumulative_sums_and_resource() {
+        let registry = Registry::new();
+        let gauge = GaugeVec::new(
+            Opts::new("test_temperature_celsius", "Temperature"),
+            &["location"],
+        )
+        .unwrap();
+        let counter =
+            IntCounterVec::new(Opts::new("test_calls_total", "Calls"), &["location"]).unwrap();
+        let histogram =
+            Histogram::with_opts(HistogramOpts::new("test_duration_seconds", "Skipped")).unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        registry.register(Box::new(histogram.clone())).unwrap();
+        gauge.with_label_values(&["OtlpOslo"]).set(-3.5);
+        counter.with_label_values(&["OtlpOslo"]).inc_by(4);
+        histogram.observe(0.1);
+
+        let before = unix_nanos();
+        let exporter = exporter(&[("service.name", "weather"), ("deployment", "test")]);
+        let request = exporter.request(&registry.gather());
+        // Survives the wire format
+        let request =
+            ExportMetricsServiceRequest::decode(request.encode_to_vec().as_slice()).unwrap();
+
+        assert_eq!(request.resource_metrics.len(), 1);
+        let resource_metrics = &request.resource_metrics[0];
+        assert_eq!(
+            attributes(&resource_metrics.resource.as_ref().unwrap().attributes),
+            [("service.name", "weather"), ("deployment", "test")]
+        );
+        let scope_metrics = &resource_metrics.scope_metrics[0];
+        assert_eq!(
+            scope_metrics.scope.as_ref().unwrap().name,
+            env!("CARGO_PKG_NAME")
+        );
+
+        // Sorted by name, without the histogram
+        let names: Vec<&str> = scope_metrics
+            .metrics
+            .iter()
+            .map(|metric| metric.name.as_str())
+            .collect();
+        assert_eq!(names, ["test_calls_total", "test_temperature_celsius"]);
+
+        let Some(metric::Data::Sum(sum)) = &scope_metrics.metrics[0].data else {
+            panic!("Counters should be sums");
+        };
+        assert_eq!(
+            sum.aggregation_temporality,
+            AggregationTemporality::Cumulative as i32
+        );
+        assert!(sum.is_monotonic);
+        let point = &sum.data_points[0];
+        assert_eq!(value(point), 4.0);
+        assert_eq!(attributes(&point.attributes), [("location", "OtlpOslo")]);
+        assert!(point.time_unix_nano >= before);
+        // Counters started when they were registered, or their location was
+        // last removed
+        assert_eq!(
+            point.start_time_unix_nano,
+            (counters_created(Some("OtlpOslo")) * 1e9) as u64
+        );
+        assert!(
+            point.start_time_unix_nano > 0 && point.start_time_unix_nano <= point.time_unix_nano
+        );
+
+        let Some(metric::Data::Gauge(gauge)) = &scope_metrics.metrics[1].data else {
+            panic!("Gauges should be gauges");
+        };
+        assert_eq!(scope_metrics.metrics[1].description, "Temperature");
+        let point = &gauge.data_points[0];
+        assert_eq!(value(point), -3.5);
+        assert_eq!(attributes(&point.attributes), [("location", "OtlpOslo")]);
+        assert!(point.time_unix_nano >= before);
+        assert_eq!(point.start_time_unix_nano, 0);
+    }
+
+    #[test]
+    fn configured_resource_attributes_override_defaults() {
+        let attributes = resource_attributes(&[
+            ("service.name".to_string(), "weather".to_string()),
+            ("region".to_string(), "eu".to_string()),
+        ]);
+        assert!(attributes.contains(&(
+            "service.version".to_string(),
+            env!("CARGO_PKG_VERSION").to_string()
+        )));
+        assert_eq!(
+            attributes
+                .iter()
+                .filter(|(key, _)| key == "service.name")
+                .collect::<Vec<_>>(),
+            [&("service.name".to_string(), "weather".to_string())]
+        );
+        assert_eq!(
+            attributes.last().unwrap(),
+            &("region".to_string(), "eu".to_string())
+        );
+    }
+}
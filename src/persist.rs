@@ -0,0 +1,79 @@
+use crate::daily::EarlierToday;
+use crate::degree_days::DegreeDays;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use std::{collections::HashMap, path::PathBuf};
+use tracing::{debug, info};
+
+/// State that should survive a restart, keyed by location name
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PersistedState {
+    #[serde(default)]
+    pub degree_days: HashMap<String, DegreeDays>,
+    /// Forecast hours of today that newer forecasts dropped
+    #[serde(default)]
+    pub earlier_today: HashMap<String, EarlierToday>,
+}
+
+/// How often updates save the state. A crash loses nothing in between, as the
+/// next sample integrates from the last saved one.
+const SAVE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// JSON file holding [`PersistedState`]
+pub struct StateFile {
+    path: PathBuf,
+    last_saved: Mutex<Option<Instant>>,
+}
+
+impl StateFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            last_saved: Mutex::new(None),
+        }
+    }
+
+    /// Whether the state hasn't been saved for a while
+    pub fn is_due(&self) -> bool {
+        self.last_saved
+            .lock()
+            .expect("state file lock poisoned")
+            .is_none_or(|saved| saved.elapsed() >= SAVE_INTERVAL)
+    }
+
+    /// Load the saved state. A missing file is not an error.
+    pub fn load(&self) -> Result<PersistedState> {
+        match std::fs::read(&self.path) {
+            Ok(bytes) => {
+                info!("Loaded persisted state from {}", self.path.display());
+                serde_json::from_slice(&bytes)
+                    .with_context(|| format!("Invalid state file {}", self.path.display()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(PersistedState::default()),
+            Err(e) => {
+                Err(e).with_context(|| format!("Failed to read state file {}", self.path.display()))
+            }
+        }
+    }
+
+    /// Write the state to a temporary file and rename it into place, so a
+    /// crash mid-write never leaves a truncated file behind
+    pub async fn save(&self, state: &PersistedState) -> Result<()> {
+        let bytes = serde_json::to_vec_pretty(state)?;
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+
+        tokio::fs::write(&tmp, bytes)
+            .await
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        tokio::fs::rename(&tmp, &self.path)
+            .await
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+
+        *self.last_saved.lock().expect("state file lock poisoned") = Some(Instant::now());
+        debug!("Saved persisted state to {}", self.path.display());
+        Ok(())
+    }
+}
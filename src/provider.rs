@@ -0,0 +1,413 @@
+use crate::timezones;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr, sync::Arc, time::Duration};
+use tracing::{info, warn};
+
+mod open_meteo;
+mod yr;
+
+pub use open_meteo::OpenMeteoProvider;
+pub use yr::YrProvider;
+
+/// A source of geocoding and forecast data.
+///
+/// Providers normalize their responses into [`WeatherResponse`] so metrics are
+/// updated the same way regardless of where the data came from.
+#[async_trait]
+pub trait WeatherProvider: Send + Sync {
+    /// All places matching `query`, best match first.
+    async fn search_locations(&self, query: &str) -> Result<Vec<Location>>;
+
+    /// Look up a location by its yr.no ID.
+    async fn location_by_id(&self, id: &str) -> Result<Location> {
+        Err(anyhow::anyhow!(
+            "Location ID {} cannot be resolved by this provider",
+            id
+        ))
+    }
+
+    /// Resolve a query to a single location, using the pinned ID or country
+    /// qualifier when given and the best match otherwise.
+    async fn search_location(&self, query: &LocationQuery) -> Result<Location> {
+        if let Some(id) = &query.id {
+            return self.location_by_id(id).await;
+        }
+
+        let candidates = self.search_locations(&query.name).await?;
+        let total = candidates.len();
+        let mut matching = candidates
+            .into_iter()
+            .filter(|candidate| query.matches_country(candidate));
+        let location = matching.next().ok_or_else(|| match &query.country {
+            Some(country) => anyhow::anyhow!("Location not found: {} in {}", query.name, country),
+            None => anyhow::anyhow!("Location not found: {}", query.name),
+        })?;
+
+        let others = matching.count();
+        if query.country.is_none() && total > 1 {
+            warn!(
+                "{} matches for {}, using {}; run `locations search` to pick another",
+                total,
+                query.name,
+                location.describe()
+            );
+        } else if others > 0 {
+            warn!(
+                "{} matches for {}, using {}",
+                others + 1,
+                query,
+                location.describe()
+            );
+        }
+
+        info!(
+            "Found location: {} at ({}, {})",
+            location.name, location.position.lat, location.position.lon
+        );
+        Ok(location)
+    }
+
+    /// Fetch a forecast for `location`, revalidating `cache` where the
+    /// upstream API supports it. Callers are expected to check
+    /// [`WeatherCache::is_expired`] before calling this.
+    async fn fetch_weather(
+        &self,
+        location_name: &str,
+        location: &Location,
+        cache: &WeatherCache,
+    ) -> Result<WeatherCache>;
+}
+
+/// HTTP client shared by the providers, identifying the exporter with
+/// `user_agent` as yr.no requires
+pub fn http_client(user_agent: &str) -> Result<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .user_agent(user_agent)
+        .timeout(Duration::from_secs(30))
+        .build()?)
+}
+
+/// Available weather data backends
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProviderKind {
+    /// yr.no / MET Norway
+    Yr,
+    /// open-meteo.com
+    OpenMeteo,
+}
+
+impl ProviderKind {
+    pub fn build(self, client: reqwest::Client) -> Arc<dyn WeatherProvider> {
+        match self {
+            ProviderKind::Yr => Arc::new(YrProvider::new(client)),
+            ProviderKind::OpenMeteo => Arc::new(OpenMeteoProvider::new(client)),
+        }
+    }
+}
+
+impl fmt::Display for ProviderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderKind::Yr => write!(f, "yr"),
+            ProviderKind::OpenMeteo => write!(f, "open-meteo"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Location {
+    /// yr.no location ID, only set by the yr.no provider
+    pub id: Option<String>,
+    pub name: String,
+    pub position: Position,
+    pub elevation: Option<f64>,
+    /// IANA timezone, when the geocoder provides one
+    #[serde(rename = "timeZone")]
+    pub time_zone: Option<String>,
+    pub country: Option<Area>,
+    pub region: Option<Area>,
+}
+
+/// A named country or region
+#[derive(Debug, Deserialize, Clone)]
+pub struct Area {
+    /// Short code, e.g. the ISO 3166 code for countries
+    pub id: Option<String>,
+    pub name: String,
+}
+
+/// What to look up for a monitored location
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocationQuery {
+    pub name: String,
+    /// Country code or name the match has to be in
+    pub country: Option<String>,
+    /// yr.no location ID, which skips the search entirely
+    pub id: Option<String>,
+}
+
+impl LocationQuery {
+    /// Parse `name` or `name, country`
+    pub fn parse(query: &str) -> Self {
+        let (name, country) = match query.rsplit_once(',') {
+            Some((name, country)) if !country.trim().is_empty() => {
+                (name.trim(), Some(country.trim().to_string()))
+            }
+            _ => (query.trim(), None),
+        };
+        Self {
+            name: name.to_string(),
+            country,
+            id: None,
+        }
+    }
+
+    fn matches_country(&self, location: &Location) -> bool {
+        let Some(wanted) = &self.country else {
+            return true;
+        };
+        location.country.as_ref().is_some_and(|country| {
+            country.name.eq_ignore_ascii_case(wanted)
+                || country
+                    .id
+                    .as_deref()
+                    .is_some_and(|id| id.eq_ignore_ascii_case(wanted))
+        })
+    }
+}
+
+impl fmt::Display for LocationQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.id, &self.country) {
+            (Some(id), _) => write!(f, "yr.no ID {}", id),
+            (None, Some(country)) => write!(f, "{}, {}", self.name, country),
+            (None, None) => write!(f, "{}", self.name),
+        }
+    }
+}
+
+impl Location {
+    /// Name with region and country, for telling search results apart
+    pub fn describe(&self) -> String {
+        [
+            Some(&self.name),
+            self.region.as_ref().map(|r| &r.name),
+            self.country.as_ref().map(|c| &c.name),
+        ]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+
+    /// The location's timezone, looked up from its coordinates if the
+    /// geocoder didn't provide a valid one
+    pub fn timezone(&self) -> Tz {
+        self.time_zone
+            .as_deref()
+            .and_then(|tz| Tz::from_str(tz).ok())
+            .unwrap_or_else(|| timezones::lookup(self.position.lat, self.position.lon))
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Position {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl Position {
+    // Round coordinates to 4 decimals as required by the API
+    pub fn rounded(&self) -> (f64, f64) {
+        (
+            (self.lat * 10000.0).round() / 10000.0,
+            (self.lon * 10000.0).round() / 10000.0,
+        )
+    }
+}
+
+// Common forecast model. This mirrors the yr.no locationforecast format,
+// other providers convert into it.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WeatherResponse {
+    pub properties: WeatherProperties,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WeatherProperties {
+    pub timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TimeSeries {
+    pub time: DateTime<Utc>,
+    pub data: TimeSeriesData,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TimeSeriesData {
+    pub instant: InstantData,
+    pub next_1_hours: Option<NextHours>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct InstantData {
+    pub details: WeatherDetails,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WeatherDetails {
+    pub air_pressure_at_sea_level: Option<f64>,
+    pub air_temperature: Option<f64>,
+    pub cloud_area_fraction: Option<f64>,
+    pub relative_humidity: Option<f64>,
+    pub wind_from_direction: Option<f64>,
+    pub wind_speed: Option<f64>,
+    pub wind_speed_of_gust: Option<f64>,
+    pub ultraviolet_index_clear_sky: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NextHours {
+    pub details: NextHoursDetails,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NextHoursDetails {
+    pub precipitation_amount: Option<f64>,
+}
+
+// Cache for weather data
+#[derive(Clone)]
+pub struct WeatherCache {
+    pub data: Option<WeatherResponse>,
+    pub expires: Option<DateTime<Utc>>,
+    pub last_modified: Option<String>,
+}
+
+impl WeatherCache {
+    pub fn new() -> Self {
+        Self {
+            data: None,
+            expires: None,
+            last_modified: None,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        match self.expires {
+            Some(expires) => Utc::now() > expires,
+            None => true,
+        }
+    }
+}
+
+impl WeatherResponse {
+    /// The forecast entry closest to `now`
+    pub fn closest(&self, now: DateTime<Utc>) -> Option<&TimeSeries> {
+        self.properties
+            .timeseries
+            .iter()
+            .min_by_key(|ts| (ts.time - now).num_seconds().abs())
+    }
+
+    /// Air temperature at `time`, linearly interpolated between the two
+    /// surrounding forecast entries
+    pub fn temperature_at(&self, time: DateTime<Utc>) -> Option<f64> {
+        let entries: Vec<(DateTime<Utc>, f64)> = self
+            .properties
+            .timeseries
+            .iter()
+            .filter_map(|ts| Some((ts.time, ts.data.instant.details.air_temperature?)))
+            .collect();
+
+        let after = entries.iter().position(|(t, _)| *t >= time)?;
+        let (t1, temp1) = entries[after];
+        if after == 0 || t1 == time {
+            return Some(temp1);
+        }
+
+        let (t0, temp0) = entries[after - 1];
+        let fraction = (time - t0).num_seconds() as f64 / (t1 - t0).num_seconds() as f64;
+        Some(temp0 + (temp1 - temp0) * fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(name: &str, country: Option<&str>) -> LocationQuery {
+        LocationQuery {
+            name: name.to_string(),
+            country: country.map(str::to_string),
+            id: None,
+        }
+    }
+
+    fn location_in(country: Option<(&str, &str)>) -> Location {
+        Location {
+            id: None,
+            name: "Lund".to_string(),
+            position: Position {
+                lat: 55.7,
+                lon: 13.2,
+            },
+            elevation: None,
+            time_zone: None,
+            country: country.map(|(id, name)| Area {
+                id: Some(id.to_string()),
+                name: name.to_string(),
+            }),
+            region: None,
+        }
+    }
+
+    #[test]
+    fn parse_name_and_country() {
+        assert_eq!(LocationQuery::parse("Oslo"), query("Oslo", None));
+        assert_eq!(LocationQuery::parse("  Oslo "), query("Oslo", None));
+        assert_eq!(LocationQuery::parse("Lund, SE"), query("Lund", Some("SE")));
+        assert_eq!(
+            LocationQuery::parse("Lund,Sweden"),
+            query("Lund", Some("Sweden"))
+        );
+        // Only the last part is the country
+        assert_eq!(
+            LocationQuery::parse("Washington, D.C., US"),
+            query("Washington, D.C.", Some("US"))
+        );
+    }
+
+    #[test]
+    fn parse_ignores_empty_country() {
+        assert_eq!(LocationQuery::parse("Lund,").country, None);
+        assert_eq!(LocationQuery::parse("Lund, ").country, None);
+    }
+
+    #[test]
+    fn matches_country_by_code_or_name() {
+        let lund = location_in(Some(("SE", "Sweden")));
+        assert!(query("Lund", None).matches_country(&lund));
+        assert!(query("Lund", Some("se")).matches_country(&lund));
+        assert!(query("Lund", Some("SWEDEN")).matches_country(&lund));
+        assert!(!query("Lund", Some("US")).matches_country(&lund));
+        assert!(!query("Lund", Some("SE")).matches_country(&location_in(None)));
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(query("Lund", Some("SE")).to_string(), "Lund, SE");
+        let pinned = LocationQuery {
+            id: Some("2-2693678".to_string()),
+            ..query("Lund", None)
+        };
+        assert_eq!(pinned.to_string(), "yr.no ID 2-2693678");
+    }
+}
@@ -0,0 +1,288 @@
+use super::{
+    Area, InstantData, Location, NextHours, NextHoursDetails, Position, TimeSeries, TimeSeriesData,
+    WeatherCache, WeatherDetails, WeatherProperties, WeatherProvider, WeatherResponse,
+};
+use crate::metrics::{WEATHER_API_CALLS, location_labels};
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use tracing::{error, info};
+
+// Open-Meteo does not send cache headers, and its models update hourly
+const CACHE_DURATION_MINUTES: i64 = 15;
+
+// Candidates returned by a search, the API's own default
+const SEARCH_RESULTS: u32 = 10;
+
+const HOURLY_VARIABLES: &str = "temperature_2m,relative_humidity_2m,wind_speed_10m,\
+    wind_direction_10m,wind_gusts_10m,pressure_msl,cloud_cover,uv_index_clear_sky,precipitation";
+
+// Open-Meteo API response structures
+#[derive(Debug, Deserialize)]
+struct GeocodingResponse {
+    results: Option<Vec<GeocodingResult>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GeocodingResult {
+    name: String,
+    latitude: f64,
+    longitude: f64,
+    elevation: Option<f64>,
+    timezone: Option<String>,
+    country: Option<String>,
+    country_code: Option<String>,
+    admin1: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ForecastResponse {
+    hourly: Hourly,
+}
+
+// Each field is a column, indexed the same as `time`
+#[derive(Debug, Deserialize)]
+struct Hourly {
+    time: Vec<i64>,
+    temperature_2m: Vec<Option<f64>>,
+    relative_humidity_2m: Vec<Option<f64>>,
+    wind_speed_10m: Vec<Option<f64>>,
+    wind_direction_10m: Vec<Option<f64>>,
+    wind_gusts_10m: Vec<Option<f64>>,
+    pressure_msl: Vec<Option<f64>>,
+    cloud_cover: Vec<Option<f64>>,
+    uv_index_clear_sky: Vec<Option<f64>>,
+    /// Sum over the preceding hour
+    precipitation: Vec<Option<f64>>,
+}
+
+impl Hourly {
+    fn into_weather_response(self) -> WeatherResponse {
+        let column = |values: &[Option<f64>], i: usize| values.get(i).copied().flatten();
+
+        let timeseries = self
+            .time
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &timestamp)| {
+                let time = DateTime::<Utc>::from_timestamp(timestamp, 0)?;
+                // yr.no reports precipitation for the hour following `time`
+                let next_1_hours = column(&self.precipitation, i + 1).map(|amount| NextHours {
+                    details: NextHoursDetails {
+                        precipitation_amount: Some(amount),
+                    },
+                });
+
+                Some(TimeSeries {
+                    time,
+                    data: TimeSeriesData {
+                        instant: InstantData {
+                            details: WeatherDetails {
+                                air_pressure_at_sea_level: column(&self.pressure_msl, i),
+                                air_temperature: column(&self.temperature_2m, i),
+                                cloud_area_fraction: column(&self.cloud_cover, i),
+                                relative_humidity: column(&self.relative_humidity_2m, i),
+                                wind_from_direction: column(&self.wind_direction_10m, i),
+                                wind_speed: column(&self.wind_speed_10m, i),
+                                wind_speed_of_gust: column(&self.wind_gusts_10m, i),
+                                ultraviolet_index_clear_sky: column(&self.uv_index_clear_sky, i),
+                            },
+                        },
+                        next_1_hours,
+                    },
+                })
+            })
+            .collect();
+
+        WeatherResponse {
+            properties: WeatherProperties { timeseries },
+        }
+    }
+}
+
+/// Open-Meteo geocoding and forecast APIs
+pub struct OpenMeteoProvider {
+    client: reqwest::Client,
+}
+
+impl OpenMeteoProvider {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl WeatherProvider for OpenMeteoProvider {
+    async fn search_locations(&self, query: &str) -> Result<Vec<Location>> {
+        let url = format!(
+            "https://geocoding-api.open-meteo.com/v1/search?name={}&count={}&format=json",
+            urlencoding::encode(query),
+            SEARCH_RESULTS
+        );
+
+        info!("Searching for location (Open-Meteo): {}", query);
+
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await?
+            .json::<GeocodingResponse>()
+            .await?;
+
+        Ok(response
+            .results
+            .unwrap_or_default()
+            .into_iter()
+            .map(|result| Location {
+                id: None,
+                name: result.name,
+                position: Position {
+                    lat: result.latitude,
+                    lon: result.longitude,
+                },
+                elevation: result.elevation,
+                time_zone: result.timezone,
+                country: result.country.map(|name| Area {
+                    id: result.country_code,
+                    name,
+                }),
+                region: result.admin1.map(|name| Area { id: None, name }),
+            })
+            .collect())
+    }
+
+    async fn fetch_weather(
+        &self,
+        location_name: &str,
+        location: &Location,
+        _cache: &WeatherCache,
+    ) -> Result<WeatherCache> {
+        let (lat, lon) = location.position.rounded();
+        let url = format!(
+            "https://api.open-meteo.com/v1/forecast?latitude={}&longitude={}&hourly={}\
+             &wind_speed_unit=ms&timeformat=unixtime&timezone=GMT",
+            lat, lon, HOURLY_VARIABLES
+        );
+
+        info!(
+            "Fetching weather for {} from Open-Meteo (rounded coords: {}, {})",
+            location_name, lat, lon
+        );
+
+        let response = self.client.get(&url).send().await?;
+
+        WEATHER_API_CALLS
+            .with_label_values(&location_labels(location_name, &[]))
+            .inc();
+
+        match response.status() {
+            StatusCode::OK => {
+                let forecast = response.json::<ForecastResponse>().await?;
+                let expires = Utc::now() + Duration::minutes(CACHE_DURATION_MINUTES);
+
+                info!(
+                    "Weather data for {} cached until: {:?}",
+                    location_name, expires
+                );
+                Ok(WeatherCache {
+                    data: Some(forecast.hourly.into_weather_response()),
+                    expires: Some(expires),
+                    last_modified: None,
+                })
+            }
+            StatusCode::TOO_MANY_REQUESTS => {
+                error!(
+                    "Rate limited by Open-Meteo for {} - too many requests",
+                    location_name
+                );
+                Err(anyhow::anyhow!(
+                    "Rate limited - please reduce request frequency"
+                ))
+            }
+            status => {
+                error!("Unexpected status code for {}: {}", location_name, status);
+                Err(anyhow::anyhow!("Unexpected API response: {}", status))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// A trimmed forecast as the API sends it, with a null value and a
+    /// column cut short
+    fn forecast() -> WeatherResponse {
+        let body = include_str!("../../tests/fixtures/open_meteo/forecast.json");
+        serde_json::from_str::<ForecastResponse>(body)
+            .unwrap()
+            .hourly
+            .into_weather_response()
+    }
+
+    #[test]
+    fn columns_map_to_details() {
+        let forecast = forecast();
+        let timeseries = &forecast.properties.timeseries;
+        let times: Vec<_> = timeseries.iter().map(|entry| entry.time).collect();
+        assert_eq!(
+            times,
+            (12..16)
+                .map(|hour| Utc.with_ymd_and_hms(2026, 1, 15, hour, 0, 0).unwrap())
+                .collect::<Vec<_>>()
+        );
+
+        let details = &timeseries[1].data.instant.details;
+        assert_eq!(details.air_temperature, Some(-1.8));
+        assert_eq!(details.relative_humidity, Some(84.0));
+        assert_eq!(details.wind_speed, Some(3.5));
+        assert_eq!(details.wind_from_direction, Some(215.0));
+        assert_eq!(details.wind_speed_of_gust, Some(6.8));
+        assert_eq!(details.air_pressure_at_sea_level, Some(1012.1));
+        assert_eq!(details.cloud_area_fraction, Some(100.0));
+        assert_eq!(details.ultraviolet_index_clear_sky, Some(0.5));
+    }
+
+    #[test]
+    fn precipitation_moves_to_the_hour_before() {
+        let forecast = forecast();
+        let precipitation: Vec<_> = forecast
+            .properties
+            .timeseries
+            .iter()
+            .map(|entry| {
+                entry
+                    .data
+                    .next_1_hours
+                    .as_ref()
+                    .map(|next| next.details.precipitation_amount)
+            })
+            .collect();
+        // The 13:00 sum covers 12:00-13:00, which yr.no reports at 12:00. The
+        // last hour's next hour is past the end of the forecast.
+        assert_eq!(
+            precipitation,
+            [Some(Some(0.2)), None, Some(Some(1.1)), None]
+        );
+    }
+
+    #[test]
+    fn null_and_missing_values_are_none() {
+        let forecast = forecast();
+        let timeseries = &forecast.properties.timeseries;
+        // Null in the column
+        assert_eq!(timeseries[2].data.instant.details.air_temperature, None);
+        assert_eq!(timeseries[2].data.instant.details.wind_speed, Some(4.0));
+        // Past the end of a short column
+        assert_eq!(timeseries[3].data.instant.details.wind_speed_of_gust, None);
+        assert_eq!(
+            timeseries[3].data.instant.details.air_temperature,
+            Some(-0.9)
+        );
+    }
+}
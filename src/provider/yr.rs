@@ -0,0 +1,191 @@
+use super::{Location, WeatherCache, WeatherProvider, WeatherResponse};
+use crate::metrics::{WEATHER_API_CALLS, WEATHER_CACHE_HITS, location_labels};
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use tracing::{debug, error, info, warn};
+
+// YR.no API response structures
+#[derive(Debug, Deserialize)]
+struct LocationSearchResponse {
+    #[serde(rename = "_embedded")]
+    embedded: Option<EmbeddedLocations>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddedLocations {
+    location: Option<Vec<Location>>,
+}
+
+/// yr.no location search and MET Norway locationforecast
+pub struct YrProvider {
+    client: reqwest::Client,
+}
+
+impl YrProvider {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl WeatherProvider for YrProvider {
+    async fn search_locations(&self, query: &str) -> Result<Vec<Location>> {
+        let url = format!(
+            "https://www.yr.no/api/v0/locations/search?q={}",
+            urlencoding::encode(query)
+        );
+
+        info!("Searching for location: {}", query);
+
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await?
+            .json::<LocationSearchResponse>()
+            .await?;
+
+        Ok(response
+            .embedded
+            .and_then(|e| e.location)
+            .unwrap_or_default())
+    }
+
+    async fn location_by_id(&self, id: &str) -> Result<Location> {
+        let url = format!(
+            "https://www.yr.no/api/v0/locations/{}",
+            urlencoding::encode(id)
+        );
+
+        info!("Looking up yr.no location ID: {}", id);
+
+        let response = self.client.get(&url).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(anyhow::anyhow!("Unknown yr.no location ID: {}", id));
+        }
+        let location = response.error_for_status()?.json::<Location>().await?;
+
+        info!(
+            "Found location: {} at ({}, {})",
+            location.name, location.position.lat, location.position.lon
+        );
+
+        Ok(location)
+    }
+
+    async fn fetch_weather(
+        &self,
+        location_name: &str,
+        location: &Location,
+        cache: &WeatherCache,
+    ) -> Result<WeatherCache> {
+        // Round coordinates to 4 decimals as required by the API. The complete
+        // product is used because compact leaves out wind gusts.
+        let (lat, lon) = location.position.rounded();
+        let url = format!(
+            "https://api.met.no/weatherapi/locationforecast/2.0/complete?lat={}&lon={}",
+            lat, lon
+        );
+
+        info!(
+            "Fetching weather for {} (rounded coords: {}, {})",
+            location_name, lat, lon
+        );
+
+        // Build request with If-Modified-Since header if we have cached data
+        let mut request = self.client.get(&url);
+        if let Some(ref last_mod) = cache.last_modified {
+            debug!(
+                "Adding If-Modified-Since header for {}: {}",
+                location_name, last_mod
+            );
+            request = request.header("If-Modified-Since", last_mod);
+        }
+
+        let response = request.send().await?;
+
+        WEATHER_API_CALLS
+            .with_label_values(&location_labels(location_name, &[]))
+            .inc();
+
+        // Handle different status codes
+        match response.status() {
+            StatusCode::OK => {
+                info!("Received new weather data for {}", location_name);
+
+                // Extract headers
+                let expires = response
+                    .headers()
+                    .get("expires")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|s| DateTime::parse_from_rfc2822(s).ok())
+                    .map(|dt| dt.with_timezone(&Utc));
+
+                let last_modified = response
+                    .headers()
+                    .get("last-modified")
+                    .and_then(|v| v.to_str().ok())
+                    .map(|s| s.to_string());
+
+                // Check for deprecation warning
+                if response.status() == StatusCode::NON_AUTHORITATIVE_INFORMATION {
+                    warn!("API endpoint is deprecated, please check for updates");
+                }
+
+                let weather_data = response.json::<WeatherResponse>().await?;
+
+                // Return new cache
+                let new_cache = WeatherCache {
+                    data: Some(weather_data),
+                    expires,
+                    last_modified,
+                };
+
+                info!(
+                    "Weather data for {} cached until: {:?}",
+                    location_name, expires
+                );
+                Ok(new_cache)
+            }
+            StatusCode::NOT_MODIFIED => {
+                info!(
+                    "Weather data not modified for {}, using cached version",
+                    location_name
+                );
+                WEATHER_CACHE_HITS
+                    .with_label_values(&location_labels(location_name, &[]))
+                    .inc();
+                Ok(cache.clone())
+            }
+            StatusCode::TOO_MANY_REQUESTS => {
+                error!(
+                    "Rate limited by API for {} - too many requests",
+                    location_name
+                );
+                Err(anyhow::anyhow!(
+                    "Rate limited - please reduce request frequency"
+                ))
+            }
+            StatusCode::FORBIDDEN => {
+                error!("Forbidden for {} - check User-Agent header", location_name);
+                Err(anyhow::anyhow!(
+                    "API returned 403 Forbidden - check configuration"
+                ))
+            }
+            _ => {
+                error!(
+                    "Unexpected status code for {}: {}",
+                    location_name,
+                    response.status()
+                );
+                Err(anyhow::anyhow!(
+                    "Unexpected API response: {}",
+                    response.status()
+                ))
+            }
+        }
+    }
+}
@@ -0,0 +1,347 @@
+//! Pushing metrics for exporters that Prometheus can't scrape, either to a
+//! Pushgateway or to a remote_write endpoint.
+
+use crate::metrics::{self, REGISTRY};
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE;
+use chrono::Utc;
+use prometheus::proto::{MetricFamily, MetricType};
+use prost::Message;
+use std::time::Duration;
+use tokio::sync::{Notify, watch};
+use tracing::{debug, error, info};
+
+/// Where pushed metrics go
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PushMode {
+    /// PUT the text exposition to a Pushgateway group
+    Pushgateway,
+    /// Send samples with the Prometheus remote_write protocol
+    RemoteWrite,
+}
+
+#[derive(Debug, Clone)]
+pub struct PushSettings {
+    pub url: String,
+    pub mode: PushMode,
+    /// Minimum time between two pushes, until changed with
+    /// [`Pusher::set_interval`]
+    pub interval: Duration,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub job: String,
+    pub instance: Option<String>,
+}
+
+/// Pushes the registry after updates, at most once per interval
+pub struct Pusher {
+    settings: PushSettings,
+    interval: watch::Sender<Duration>,
+    client: reqwest::Client,
+    pending: Notify,
+}
+
+impl Pusher {
+    pub fn new(settings: PushSettings, client: reqwest::Client) -> Self {
+        Self {
+            interval: watch::Sender::new(settings.interval),
+            settings,
+            client,
+            pending: Notify::new(),
+        }
+    }
+
+    /// Change the minimum time between pushes, e.g. on a config reload
+    pub fn set_interval(&self, interval: Duration) {
+        if self.interval.send_replace(interval) != interval {
+            info!("Pushing metrics at most every {}s", interval.as_secs());
+        }
+    }
+
+    /// Request a push. Requests made while waiting out the interval are
+    /// folded into the next push.
+    pub fn notify(&self) {
+        self.pending.notify_one();
+    }
+
+    /// Push on request until `stop` changes, then push once more so the
+    /// last update isn't lost
+    pub async fn run(&self, mut stop: watch::Receiver<bool>) {
+        loop {
+            tokio::select! {
+                _ = self.pending.notified() => {}
+                _ = stop.changed() => break,
+            }
+            self.push_logged().await;
+            tokio::select! {
+                _ = tokio::time::sleep(*self.interval.borrow()) => {}
+                _ = stop.changed() => break,
+            }
+        }
+        self.push_logged().await;
+    }
+
+    async fn push_logged(&self) {
+        match self.push().await {
+            Ok(()) => debug!("Pushed metrics to {}", self.settings.url),
+            Err(e) => error!("Failed to push metrics to {}: {:#}", self.settings.url, e),
+        }
+    }
+
+    async fn push(&self) -> Result<()> {
+        let request = match self.settings.mode {
+            PushMode::Pushgateway => self
+                .client
+                .put(self.pushgateway_url())
+                .header("Content-Type", metrics::TEXT_CONTENT_TYPE)
+                .body(metrics::gather_text()),
+            PushMode::RemoteWrite => {
+                let body = write_request(&REGISTRY.gather(), &self.grouping_labels());
+                let compressed = snap::raw::Encoder::new()
+                    .compress_vec(&body.encode_to_vec())
+                    .context("Failed to compress remote_write request")?;
+                self.client
+                    .post(&self.settings.url)
+                    .header("Content-Type", "application/x-protobuf")
+                    .header("Content-Encoding", "snappy")
+                    .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+                    .body(compressed)
+            }
+        };
+
+        let request = match &self.settings.username {
+            Some(username) => request.basic_auth(username, self.settings.password.as_ref()),
+            None => request,
+        };
+
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    /// `<url>/metrics/job/<job>[/instance/<instance>]`, replacing the whole
+    /// group on every push
+    fn pushgateway_url(&self) -> String {
+        let mut url = self.settings.url.trim_end_matches('/').to_string();
+        url.push_str("/metrics");
+        for (name, value) in self.grouping_labels() {
+            url.push_str(&grouping_segment(&name, &value));
+        }
+        url
+    }
+
+    fn grouping_labels(&self) -> Vec<(String, String)> {
+        let mut labels = vec![("job".to_string(), self.settings.job.clone())];
+        if let Some(instance) = &self.settings.instance {
+            labels.push(("instance".to_string(), instance.clone()));
+        }
+        labels
+    }
+}
+
+/// Pushgateway path segments can't contain `/`, such values are sent base64
+/// encoded instead. Empty values are a lone `=`, as an empty segment would
+/// be dropped.
+fn grouping_segment(name: &str, value: &str) -> String {
+    if value.is_empty() {
+        format!("/{}@base64/=", name)
+    } else if value.contains('/') {
+        format!("/{}@base64/{}", name, URL_SAFE.encode(value))
+    } else {
+        format!("/{}/{}", name, urlencoding::encode(value))
+    }
+}
+
+// remote_write protobuf messages, see prometheus/prompb/types.proto
+#[derive(Clone, PartialEq, Message)]
+struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Label {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Sample {
+    #[prost(double, tag = "1")]
+    value: f64,
+    #[prost(int64, tag = "2")]
+    timestamp: i64,
+}
+
+/// One sample per series, all stamped with the current time. Histograms and
+/// summaries are skipped, the exporter has none.
+fn write_request(families: &[MetricFamily], extra_labels: &[(String, String)]) -> WriteRequest {
+    let timestamp = Utc::now().timestamp_millis();
+    let mut timeseries = Vec::new();
+
+    for family in families {
+        for metric in &family.metric {
+            let value = match family.type_() {
+                MetricType::GAUGE => metric.gauge.value(),
+                MetricType::COUNTER => metric.counter.value(),
+                MetricType::UNTYPED => metric.untyped.value(),
+                _ => continue,
+            };
+
+            let mut labels: Vec<Label> = metric
+                .label
+                .iter()
+                .map(|pair| Label {
+                    name: pair.name().to_string(),
+                    value: pair.value().to_string(),
+                })
+                .chain(extra_labels.iter().map(|(name, value)| Label {
+                    name: name.clone(),
+                    value: value.clone(),
+                }))
+                .collect();
+            labels.push(Label {
+                name: "__name__".to_string(),
+                value: family.name().to_string(),
+            });
+            // Receivers require labels sorted by name
+            labels.sort_by(|a, b| a.name.cmp(&b.name));
+
+            timeseries.push(TimeSeries {
+                labels,
+                samples: vec![Sample { value, timestamp }],
+            });
+        }
+    }
+
+    WriteRequest { timeseries }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{GaugeVec, Histogram, HistogramOpts, IntCounter, Opts, Registry};
+
+    fn settings(url: &str, instance: Option<&str>) -> PushSettings {
+        PushSettings {
+            url: url.to_string(),
+            mode: PushMode::Pushgateway,
+            interval: Duration::from_secs(60),
+            username: None,
+            password: None,
+            job: "weather_exporter".to_string(),
+            instance: instance.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn grouping_segments() {
+        assert_eq!(grouping_segment("job", "weather"), "/job/weather");
+        assert_eq!(grouping_segment("instance", "a b"), "/instance/a%20b");
+        assert_eq!(
+            grouping_segment("instance", "host/1"),
+            "/instance@base64/aG9zdC8x"
+        );
+        assert_eq!(grouping_segment("instance", ""), "/instance@base64/=");
+    }
+
+    #[test]
+    fn pushgateway_url_has_job_and_instance() {
+        let client = reqwest::Client::new();
+        let pusher = Pusher::new(settings("http://pgw:9091/", None), client.clone());
+        assert_eq!(
+            pusher.pushgateway_url(),
+            "http://pgw:9091/metrics/job/weather_exporter"
+        );
+        let pusher = Pusher::new(settings("http://pgw:9091", Some("site/a")), client);
+        assert_eq!(
+            pusher.pushgateway_url(),
+            "http://pgw:9091/metrics/job/weather_exporter/instance@base64/c2l0ZS9h"
+        );
+    }
+
+    #[test]
+    fn write_request_has_sorted_labels_and_one_sample_per_series() {
+        let registry = Registry::new();
+        let gauge = GaugeVec::new(
+            Opts::new("test_temperature_celsius", "Temperature"),
+            &["location", "source"],
+        )
+        .unwrap();
+        let counter = IntCounter::new("test_calls_total", "Calls").unwrap();
+        let histogram =
+            Histogram::with_opts(HistogramOpts::new("test_duration_seconds", "Skipped")).unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        registry.register(Box::new(histogram.clone())).unwrap();
+        gauge.with_label_values(&["Oslo", "forecast"]).set(-3.5);
+        gauge.with_label_values(&["Lund", "station"]).set(7.0);
+        counter.inc_by(4);
+        histogram.observe(0.1);
+
+        let before = Utc::now().timestamp_millis();
+        let request = write_request(
+            &registry.gather(),
+            &[("job".to_string(), "weather_exporter".to_string())],
+        );
+        // Survives the wire format
+        let request = WriteRequest::decode(request.encode_to_vec().as_slice()).unwrap();
+
+        let series: Vec<(Vec<(&str, &str)>, f64)> = request
+            .timeseries
+            .iter()
+            .map(|series| {
+                assert_eq!(series.samples.len(), 1);
+                assert!(series.samples[0].timestamp >= before);
+                (
+                    series
+                        .labels
+                        .iter()
+                        .map(|label| (label.name.as_str(), label.value.as_str()))
+                        .collect(),
+                    series.samples[0].value,
+                )
+            })
+            .collect();
+        assert_eq!(
+            series,
+            vec![
+                (
+                    vec![
+                        ("__name__", "test_calls_total"),
+                        ("job", "weather_exporter")
+                    ],
+                    4.0
+                ),
+                (
+                    vec![
+                        ("__name__", "test_temperature_celsius"),
+                        ("job", "weather_exporter"),
+                        ("location", "Lund"),
+                        ("source", "station"),
+                    ],
+                    7.0
+                ),
+                (
+                    vec![
+                        ("__name__", "test_temperature_celsius"),
+                        ("job", "weather_exporter"),
+                        ("location", "Oslo"),
+                        ("source", "forecast"),
+                    ],
+                    -3.5
+                ),
+            ]
+        );
+    }
+}
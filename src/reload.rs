@@ -0,0 +1,486 @@
+//! Applying config file changes without a restart, when the file changes or
+//! on SIGHUP.
+
+use crate::config::ConfigFile;
+use crate::metrics;
+use crate::provider::ProviderKind;
+use crate::{AppState, LocationSpec};
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::watch;
+use tracing::level_filters::LevelFilter;
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::{Registry, reload};
+
+/// How often the config file is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Changes the log level of the running subscriber
+pub type LogLevelHandle = reload::Handle<LevelFilter, Registry>;
+
+/// `--log-level` falls back to `info` rather than refusing to start, as
+/// `RUST_LOG` may hold filter directives meant for other tools
+pub fn parse_log_level(level: &str) -> LevelFilter {
+    LevelFilter::from_str(level).unwrap_or_else(|_| {
+        warn!("Invalid log level '{}', using info", level);
+        LevelFilter::INFO
+    })
+}
+
+/// Settings that are reapplied from the command line when the config file no
+/// longer overrides them
+pub struct Defaults {
+    pub locations: Vec<String>,
+    pub provider: ProviderKind,
+    pub log_level: LevelFilter,
+    pub push_interval: Duration,
+    pub influx_interval: Duration,
+    pub otlp_interval: Duration,
+}
+
+pub struct Reloader {
+    state: AppState,
+    path: PathBuf,
+    defaults: Defaults,
+    log_level: LogLevelHandle,
+    /// File contents last applied, or that failed to apply
+    applied: Option<String>,
+}
+
+impl Reloader {
+    pub fn new(
+        state: AppState,
+        path: PathBuf,
+        defaults: Defaults,
+        log_level: LogLevelHandle,
+    ) -> Self {
+        // The file was read at startup, a change before the first poll is
+        // picked up as well
+        let applied = std::fs::read_to_string(&path).ok();
+        Self {
+            state,
+            path,
+            defaults,
+            log_level,
+            applied,
+        }
+    }
+
+    /// Watch for changes until `stop` changes
+    pub async fn run(mut self, mut stop: watch::Receiver<bool>) {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => Some(hangup),
+            Err(e) => {
+                error!("Failed to listen for SIGHUP: {}", e);
+                None
+            }
+        };
+        let mut poll = tokio::time::interval(WATCH_INTERVAL);
+
+        loop {
+            let forced = tokio::select! {
+                Some(()) = async { hangup.as_mut()?.recv().await } => {
+                    info!("Received SIGHUP, reloading {}", self.path.display());
+                    true
+                }
+                _ = poll.tick() => false,
+                _ = stop.changed() => return,
+            };
+
+            let contents = match std::fs::read_to_string(&self.path) {
+                Ok(contents) => contents,
+                // Config management may replace the file in several steps,
+                // only complain when asked to reload
+                Err(e) if forced => {
+                    error!("Failed to read {}: {}", self.path.display(), e);
+                    continue;
+                }
+                Err(e) => {
+                    debug!("Failed to read {}: {}", self.path.display(), e);
+                    continue;
+                }
+            };
+            if !forced && self.applied.as_ref() == Some(&contents) {
+                continue;
+            }
+
+            match self.apply(&contents).await {
+                Ok(()) => info!("Reloaded {}", self.path.display()),
+                Err(e) => error!(
+                    "Failed to reload {}, keeping the current configuration: {:#}",
+                    self.path.display(),
+                    e
+                ),
+            }
+            // A broken file is not retried until it changes again
+            self.applied = Some(contents);
+        }
+    }
+
+    async fn apply(&self, contents: &str) -> Result<()> {
+        let file = ConfigFile::parse(contents)?;
+        let specs = crate::location_specs(
+            &file.locations,
+            &self.defaults.locations,
+            self.defaults.provider,
+        )?;
+        let log_level = match &file.log_level {
+            Some(level) => LevelFilter::from_str(level)?,
+            None => self.defaults.log_level,
+        };
+
+        let refresh = apply_locations(&self.state, specs).await?;
+
+        self.log_level
+            .modify(|filter| *filter = log_level)
+            .context("Failed to change the log level")?;
+        let update_interval = file
+            .update_interval_seconds
+            .map(Duration::from_secs)
+            .unwrap_or(crate::UPDATE_INTERVAL);
+        self.state.update_interval.send_if_modified(|interval| {
+            std::mem::replace(interval, update_interval) != update_interval
+        });
+        let interval = |seconds: Option<u64>, default| seconds.map_or(default, Duration::from_secs);
+        if let Some(pusher) = &self.state.pusher {
+            pusher.set_interval(interval(
+                file.push_interval_seconds,
+                self.defaults.push_interval,
+            ));
+        }
+        if let Some(influx) = &self.state.influx {
+            influx.set_interval(interval(
+                file.influx_interval_seconds,
+                self.defaults.influx_interval,
+            ));
+        }
+        if let Some(otlp) = &self.state.otlp {
+            otlp.set_interval(interval(
+                file.otlp_interval_seconds,
+                self.defaults.otlp_interval,
+            ));
+        }
+
+        // Fetch new locations and republish relabelled ones from their cache
+        for location_name in refresh {
+            if let Err(e) = self.state.update_metrics_for_location(&location_name).await {
+                error!("Failed to update metrics for {}: {}", location_name, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Make the monitored locations match `specs`. Locations whose settings
+/// didn't change keep their cache and history. Returns the locations that
+/// need an update.
+async fn apply_locations(state: &AppState, specs: Vec<LocationSpec>) -> Result<Vec<String>> {
+    if specs.is_empty() {
+        return Err(anyhow::anyhow!("No valid locations provided"));
+    }
+    // Label names are part of every series and fixed at startup
+    if let Some(label) = specs
+        .iter()
+        .flat_map(|spec| spec.labels.keys())
+        .find(|label| !metrics::custom_label_names().contains(label))
+    {
+        return Err(anyhow::anyhow!(
+            "Label {} is new, label names can only change with a restart",
+            label
+        ));
+    }
+    let names: HashSet<&str> = specs.iter().map(|spec| spec.name.as_str()).collect();
+    if let Some((id, station)) = state
+        .stations
+        .iter()
+        .find(|(_, station)| !names.contains(station.location.as_str()))
+    {
+        return Err(anyhow::anyhow!(
+            "Station {} reports to {}, which is no longer configured",
+            id,
+            station.location
+        ));
+    }
+
+    let current: HashMap<String, LocationSpec> = state
+        .locations
+        .read()
+        .await
+        .iter()
+        .map(|(name, data)| (name.clone(), data.spec.clone()))
+        .collect();
+
+    for location_name in current.keys() {
+        if !names.contains(location_name.as_str()) {
+            state.remove_location(location_name).await;
+            info!("Removed location {}", location_name);
+        }
+    }
+
+    let mut refresh = Vec::new();
+    let order: Vec<String> = specs.iter().map(|spec| spec.name.clone()).collect();
+    for spec in specs {
+        let name = spec.name.clone();
+        match current.get(&name) {
+            Some(old) if *old == spec => continue,
+            Some(old)
+                if old.provider == spec.provider
+                    && old.query == spec.query
+                    && old.timezone == spec.timezone =>
+            {
+                relabel_location(state, spec).await;
+                info!("Updated labels of {}", name);
+            }
+            Some(_) => {
+                // A different place, nothing of the old one carries over
+                state.remove_location(&name).await;
+                state.add_location(spec).await;
+                info!("Location {} changed, looking it up again", name);
+            }
+            None => {
+                state.add_location(spec).await;
+                info!("Added location {}", name);
+            }
+        }
+        refresh.push(name);
+    }
+
+    *state.location_names.write().await = order;
+    Ok(refresh)
+}
+
+/// Replace a location's labels, moving its series over to the new ones
+async fn relabel_location(state: &AppState, spec: LocationSpec) {
+    let mut locations = state.locations.write().await;
+    let Some(data) = locations.get_mut(&spec.name) else {
+        return;
+    };
+    metrics::relabel_location(&spec.name, &spec.labels);
+    data.spec = spec;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{Location, Position};
+    use prometheus::core::Collector;
+    use std::sync::Arc;
+
+    fn specs(yaml: &str) -> Vec<LocationSpec> {
+        let file = ConfigFile::parse(yaml).unwrap();
+        crate::location_specs(&file.locations, &[], ProviderKind::Yr).unwrap()
+    }
+
+    fn oslo() -> Location {
+        Location {
+            id: Some("1-72837".to_string()),
+            name: "Oslo".to_string(),
+            position: Position {
+                lat: 59.9127,
+                lon: 10.7461,
+            },
+            elevation: Some(23.0),
+            time_zone: Some("Europe/Oslo".to_string()),
+            country: None,
+            region: None,
+        }
+    }
+
+    /// Pretend every location was looked up already
+    async fn resolve_all(state: &AppState) {
+        for data in state.locations.write().await.values_mut() {
+            data.location = Some(oslo());
+        }
+    }
+
+    /// Values of the series of `vec` for a location, with their `team` label
+    fn series(vec: &impl Collector, location_name: &str) -> Vec<(String, f64)> {
+        vec.collect()
+            .iter()
+            .flat_map(|family| &family.metric)
+            .filter(|metric| {
+                metric
+                    .label
+                    .iter()
+                    .any(|pair| pair.name() == "location" && pair.value() == location_name)
+            })
+            .map(|metric| {
+                let team = metric
+                    .label
+                    .iter()
+                    .find(|pair| pair.name() == "team")
+                    .map(|pair| pair.value().to_string())
+                    .unwrap_or_default();
+                let value = metric.gauge.value() + metric.counter.value();
+                (team, value)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn applies_added_removed_relabelled_and_moved_locations() {
+        let state = AppState::for_tests(
+            "
+locations:
+  - ReloadKeep
+  - name: ReloadRelabel
+    labels: { team: a }
+  - name: ReloadMove
+    search: Oslo
+  - ReloadZone
+  - ReloadDrop
+",
+        );
+        resolve_all(&state).await;
+        metrics::WEATHER_API_CALLS
+            .with_label_values(&metrics::location_labels("ReloadRelabel", &[]))
+            .inc_by(3);
+        metrics::FORECAST_MAE
+            .with_label_values(&metrics::location_labels("ReloadRelabel", &["24"]))
+            .set(1.5);
+        metrics::WEATHER_API_CALLS
+            .with_label_values(&metrics::location_labels("ReloadDrop", &[]))
+            .inc();
+        let created = metrics::counters_created(Some("ReloadRelabel"));
+
+        let refresh = apply_locations(
+            &state,
+            specs(
+                "
+locations:
+  - ReloadAdd
+  - ReloadKeep
+  - name: ReloadRelabel
+    labels: { team: b }
+  - name: ReloadMove
+    search: Bergen
+  - name: ReloadZone
+    timezone: Europe/Lisbon
+",
+            ),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            refresh,
+            ["ReloadAdd", "ReloadRelabel", "ReloadMove", "ReloadZone"]
+        );
+        assert_eq!(
+            state.location_names().await,
+            [
+                "ReloadAdd",
+                "ReloadKeep",
+                "ReloadRelabel",
+                "ReloadMove",
+                "ReloadZone"
+            ]
+        );
+        let locations = state.locations.read().await;
+        assert!(!locations.contains_key("ReloadDrop"));
+        assert!(locations["ReloadAdd"].location.is_none());
+        // Unchanged and relabelled locations keep what was looked up
+        assert!(locations["ReloadKeep"].location.is_some());
+        assert!(locations["ReloadRelabel"].location.is_some());
+        assert_eq!(locations["ReloadRelabel"].spec.labels["team"], "b");
+        assert!(locations["ReloadMove"].location.is_none());
+        // Days begin at a different time, so degree days start over as well
+        assert!(locations["ReloadZone"].location.is_none());
+        assert_eq!(
+            locations["ReloadZone"].spec.timezone,
+            Some(chrono_tz::Tz::Europe__Lisbon)
+        );
+        drop(locations);
+
+        // Relabelled series keep their values, and counters their start
+        assert_eq!(
+            series(&*metrics::WEATHER_API_CALLS, "ReloadRelabel"),
+            [("b".to_string(), 3.0)]
+        );
+        assert_eq!(
+            series(&*metrics::FORECAST_MAE, "ReloadRelabel"),
+            [("b".to_string(), 1.5)]
+        );
+        assert_eq!(metrics::counters_created(Some("ReloadRelabel")), created);
+        assert!(series(&*metrics::WEATHER_API_CALLS, "ReloadDrop").is_empty());
+    }
+
+    #[tokio::test]
+    async fn unchanged_locations_are_left_alone() {
+        let yaml = "
+locations:
+  - name: ReloadSame
+    labels: { team: a }
+";
+        let state = AppState::for_tests(yaml);
+        resolve_all(&state).await;
+        metrics::WEATHER_API_CALLS
+            .with_label_values(&metrics::location_labels("ReloadSame", &[]))
+            .inc();
+
+        let refresh = apply_locations(&state, specs(yaml)).await.unwrap();
+
+        assert!(refresh.is_empty());
+        assert!(
+            state.locations.read().await["ReloadSame"]
+                .location
+                .is_some()
+        );
+        assert_eq!(
+            series(&*metrics::WEATHER_API_CALLS, "ReloadSame"),
+            [("a".to_string(), 1.0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn new_label_names_are_rejected() {
+        let state = AppState::for_tests("locations: [ReloadLabels]");
+
+        let error = apply_locations(
+            &state,
+            specs(
+                "
+locations:
+  - name: ReloadLabels
+    labels: { site: dc1 }
+",
+            ),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(error.to_string().contains("restart"), "{}", error);
+        assert!(
+            state.locations.read().await["ReloadLabels"]
+                .spec
+                .labels
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn locations_with_stations_cannot_be_removed() {
+        let mut state = AppState::for_tests("locations: [ReloadStation, ReloadOther]");
+        state.stations = Arc::new(HashMap::from([(
+            "KOSLO1".to_string(),
+            crate::station::Station {
+                location: "ReloadStation".to_string(),
+                password: None,
+            },
+        )]));
+
+        assert!(
+            apply_locations(&state, specs("locations: [ReloadOther]"))
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            state.location_names().await,
+            ["ReloadStation", "ReloadOther"]
+        );
+    }
+}
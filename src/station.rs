@@ -0,0 +1,488 @@
+use crate::AppState;
+use crate::auth::constant_time_eq;
+use crate::metrics::*;
+use axum::{
+    Form,
+    extract::{Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+};
+use chrono::Utc;
+use std::collections::HashMap;
+use tracing::{debug, warn};
+
+/// Where Ecowitt consoles post their "customized" uploads
+pub const ECOWITT_PATH: &str = "/data/report/";
+/// Where Weather Underground compatible stations send their uploads
+pub const WUNDERGROUND_PATH: &str = "/weatherstation/updateweatherstation.php";
+
+/// A station accepted for upload, from `--stations`
+#[derive(Debug, Clone)]
+pub struct Station {
+    pub location: String,
+    /// Weather Underground uploads must carry this `PASSWORD`, as station IDs
+    /// are public. Ecowitt uploads have none and are only accepted without.
+    pub password: Option<String>,
+}
+
+/// Observed values from a station upload, converted to metric units
+#[derive(Debug, Default)]
+struct Observation {
+    temperature_celsius: Option<f64>,
+    humidity_percent: Option<f64>,
+    wind_speed_mps: Option<f64>,
+    wind_direction_degrees: Option<f64>,
+    pressure_hpa: Option<f64>,
+    precipitation_mm: Option<f64>,
+    uv_index: Option<f64>,
+}
+
+fn fahrenheit_to_celsius(f: f64) -> f64 {
+    (f - 32.0) * 5.0 / 9.0
+}
+
+fn mph_to_mps(mph: f64) -> f64 {
+    mph * 0.44704
+}
+
+fn inhg_to_hpa(inhg: f64) -> f64 {
+    inhg * 33.8639
+}
+
+fn inches_to_mm(inches: f64) -> f64 {
+    inches * 25.4
+}
+
+impl Observation {
+    /// Both protocols use imperial units and mostly the same field names. The
+    /// rain and pressure field names differ, so they are passed in.
+    fn from_params(params: &HashMap<String, String>, pressure_key: &str, rain_key: &str) -> Self {
+        let get = |key: &str| {
+            params
+                .get(key)
+                .and_then(|v| v.parse::<f64>().ok())
+                // Weather Underground uses -9999 for "no reading"
+                .filter(|v| *v > -9999.0)
+        };
+
+        Self {
+            temperature_celsius: get("tempf").map(fahrenheit_to_celsius),
+            humidity_percent: get("humidity"),
+            wind_speed_mps: get("windspeedmph").map(mph_to_mps),
+            wind_direction_degrees: get("winddir"),
+            pressure_hpa: get(pressure_key).map(inhg_to_hpa),
+            precipitation_mm: get(rain_key).map(inches_to_mm),
+            uv_index: get("uv").or_else(|| get("UV")),
+        }
+    }
+}
+
+impl AppState {
+    async fn record_observation(
+        &self,
+        station_id: &str,
+        password: Option<&str>,
+        observation: Observation,
+    ) -> StatusCode {
+        let Some(station) = self.stations.get(station_id) else {
+            warn!("Rejected upload from unknown station {}", station_id);
+            return StatusCode::FORBIDDEN;
+        };
+        let rejected = match (&station.password, password) {
+            (Some(expected), Some(given)) => {
+                (!constant_time_eq(expected.as_bytes(), given.as_bytes()))
+                    .then_some("wrong password")
+            }
+            (None, None) => None,
+            (Some(_), None) => Some("the station has a password, which Ecowitt can't send"),
+            (None, Some(_)) => Some("Weather Underground stations need a password"),
+        };
+        if let Some(reason) = rejected {
+            warn!("Rejected upload from station {}: {}", station_id, reason);
+            return StatusCode::FORBIDDEN;
+        }
+        let location_name = &station.location;
+
+        // Reuse the forecast location so both sources share the same labels
+        let labels = {
+            let mut locations = self.locations.write().await;
+            let Some(data) = locations.get_mut(location_name) else {
+                warn!(
+                    "Station {} is mapped to unmonitored location {}",
+                    station_id, location_name
+                );
+                return StatusCode::FORBIDDEN;
+            };
+            if let Some(temp) = observation.temperature_celsius {
+                data.accuracy.observe(Utc::now(), temp);
+            }
+            // Series without coordinates would linger next to the ones with
+            // them once the location is resolved
+            if data.location.is_none() && value_labels_need_location() {
+                None
+            } else {
+                Some(value_labels(
+                    location_name,
+                    data.location.as_ref(),
+                    "station",
+                ))
+            }
+        };
+
+        debug!(
+            "Station {} reported for {}: {:?}",
+            station_id, location_name, observation
+        );
+        STATION_LAST_REPORT
+            .with_label_values(&location_labels(location_name, &[station_id]))
+            .set(Utc::now().timestamp());
+
+        let Some(labels) = labels else {
+            debug!(
+                "Not exporting the readings of station {} until {} is looked up",
+                station_id, location_name
+            );
+            return StatusCode::OK;
+        };
+
+        if let Some(temp) = observation.temperature_celsius {
+            TEMPERATURE.with_label_values(&labels).set(temp);
+        }
+        if let Some(humidity) = observation.humidity_percent {
+            HUMIDITY.with_label_values(&labels).set(humidity);
+        }
+        if let Some(wind_speed) = observation.wind_speed_mps {
+            WIND_SPEED.with_label_values(&labels).set(wind_speed);
+        }
+        if let Some(wind_dir) = observation.wind_direction_degrees {
+            WIND_DIRECTION.with_label_values(&labels).set(wind_dir);
+        }
+        if let Some(pressure) = observation.pressure_hpa {
+            PRESSURE.with_label_values(&labels).set(pressure);
+        }
+        if let Some(precip) = observation.precipitation_mm {
+            PRECIPITATION.with_label_values(&labels).set(precip);
+        }
+        if let Some(uv) = observation.uv_index {
+            UV_INDEX.with_label_values(&labels).set(uv);
+        }
+
+        StatusCode::OK
+    }
+}
+
+/// Ecowitt "customized" upload, a form-encoded POST identified by PASSKEY
+pub async fn ecowitt_handler(
+    State(state): State<AppState>,
+    Form(params): Form<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let Some(station_id) = params.get("PASSKEY") else {
+        return StatusCode::BAD_REQUEST;
+    };
+
+    let observation = Observation::from_params(&params, "baromrelin", "hourlyrainin");
+    state
+        .record_observation(station_id, None, observation)
+        .await
+}
+
+/// Weather Underground `updateweatherstation` upload, a GET identified by ID
+/// and PASSWORD
+pub async fn wunderground_handler(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let Some(station_id) = params.get("ID") else {
+        return (StatusCode::BAD_REQUEST, "missing ID");
+    };
+
+    let observation = Observation::from_params(&params, "baromin", "rainin");
+    let password = params
+        .get("PASSWORD")
+        .map(String::as_str)
+        .unwrap_or_default();
+    match state
+        .record_observation(station_id, Some(password), observation)
+        .await
+    {
+        // Stations look for this exact body
+        StatusCode::OK => (StatusCode::OK, "success"),
+        status => (status, "unauthorized"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{Location, Position};
+    use prometheus::core::Collector;
+    use std::sync::Arc;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("value is present");
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn place(lat: f64, lon: f64) -> Location {
+        Location {
+            id: None,
+            name: "Station test".to_string(),
+            position: Position { lat, lon },
+            elevation: None,
+            time_zone: None,
+            country: None,
+            region: None,
+        }
+    }
+
+    /// StationOslo and StationBergen are looked up, StationPending isn't yet
+    async fn state(stations: &[(&str, &str, Option<&str>)]) -> AppState {
+        let mut state =
+            AppState::for_tests("locations: [StationOslo, StationBergen, StationPending]");
+        {
+            let mut locations = state.locations.write().await;
+            for (location_name, lat, lon) in [
+                ("StationOslo", 59.91, 10.75),
+                ("StationBergen", 60.39, 5.32),
+            ] {
+                locations.get_mut(location_name).unwrap().location = Some(place(lat, lon));
+            }
+        }
+        state.stations = Arc::new(
+            stations
+                .iter()
+                .map(|(id, location, password)| {
+                    let station = Station {
+                        location: location.to_string(),
+                        password: password.map(str::to_string),
+                    };
+                    (id.to_string(), station)
+                })
+                .collect(),
+        );
+        state
+    }
+
+    async fn temperature(state: &AppState, location_name: &str) -> f64 {
+        let locations = state.locations.read().await;
+        let location = locations[location_name].location.as_ref();
+        TEMPERATURE
+            .with_label_values(&value_labels(location_name, location, "station"))
+            .get()
+    }
+
+    /// Station series of a location, whatever their coordinates
+    fn station_series(location_name: &str) -> Vec<Vec<(String, String)>> {
+        TEMPERATURE
+            .collect()
+            .into_iter()
+            .flat_map(|mut family| family.take_metric())
+            .map(|metric| {
+                metric
+                    .label
+                    .iter()
+                    .map(|pair| (pair.name().to_string(), pair.value().to_string()))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|labels| {
+                labels.contains(&("location".to_string(), location_name.to_string()))
+                    && labels.contains(&("source".to_string(), "station".to_string()))
+            })
+            .collect()
+    }
+
+    async fn wunderground(state: &AppState, pairs: &[(&str, &str)]) -> (StatusCode, String) {
+        let response = wunderground_handler(State(state.clone()), Query(params(pairs)))
+            .await
+            .into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    async fn ecowitt(state: &AppState, pairs: &[(&str, &str)]) -> StatusCode {
+        ecowitt_handler(State(state.clone()), Form(params(pairs)))
+            .await
+            .into_response()
+            .status()
+    }
+
+    #[test]
+    fn converts_imperial_units() {
+        let observation = Observation::from_params(
+            &params(&[
+                ("tempf", "50"),
+                ("humidity", "80"),
+                ("windspeedmph", "10"),
+                ("winddir", "270"),
+                ("baromin", "29.92"),
+                ("rainin", "0.1"),
+                ("UV", "3"),
+            ]),
+            "baromin",
+            "rainin",
+        );
+
+        assert_close(observation.temperature_celsius, 10.0);
+        assert_close(observation.humidity_percent, 80.0);
+        assert_close(observation.wind_speed_mps, 4.4704);
+        assert_close(observation.wind_direction_degrees, 270.0);
+        assert_close(observation.pressure_hpa, 1013.207888);
+        assert_close(observation.precipitation_mm, 2.54);
+        assert_close(observation.uv_index, 3.0);
+    }
+
+    #[test]
+    fn pressure_and_rain_fields_depend_on_the_protocol() {
+        let upload = params(&[
+            ("baromin", "30"),
+            ("baromrelin", "29"),
+            ("rainin", "1"),
+            ("hourlyrainin", "0.5"),
+            ("uv", "2"),
+        ]);
+
+        let wunderground = Observation::from_params(&upload, "baromin", "rainin");
+        assert_close(wunderground.pressure_hpa, 30.0 * 33.8639);
+        assert_close(wunderground.precipitation_mm, 25.4);
+        assert_close(wunderground.uv_index, 2.0);
+
+        let ecowitt = Observation::from_params(&upload, "baromrelin", "hourlyrainin");
+        assert_close(ecowitt.pressure_hpa, 29.0 * 33.8639);
+        assert_close(ecowitt.precipitation_mm, 12.7);
+    }
+
+    #[test]
+    fn missing_readings_are_skipped() {
+        let observation = Observation::from_params(
+            &params(&[
+                ("tempf", "-9999"),
+                ("humidity", "-9999.0"),
+                ("windspeedmph", "n/a"),
+                ("winddir", ""),
+                ("baromin", "-40"),
+            ]),
+            "baromin",
+            "rainin",
+        );
+
+        assert_eq!(observation.temperature_celsius, None);
+        assert_eq!(observation.humidity_percent, None);
+        assert_eq!(observation.wind_speed_mps, None);
+        assert_eq!(observation.wind_direction_degrees, None);
+        // Only the sentinel is dropped, not every negative value
+        assert_close(observation.pressure_hpa, -40.0 * 33.8639);
+        assert_eq!(observation.precipitation_mm, None);
+        assert_eq!(observation.uv_index, None);
+    }
+
+    #[tokio::test]
+    async fn wunderground_uploads_need_the_station_password() {
+        let state = state(&[
+            ("KOSLO1", "StationOslo", Some("hunter2")),
+            ("KOSLO2", "StationOslo", None),
+        ])
+        .await;
+
+        for (pairs, status) in [
+            (
+                vec![("ID", "KOSLO1"), ("tempf", "50")],
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                vec![("ID", "KOSLO1"), ("PASSWORD", "hunter"), ("tempf", "50")],
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                vec![("ID", "KOSLO1"), ("PASSWORD", ""), ("tempf", "50")],
+                StatusCode::FORBIDDEN,
+            ),
+            // A station without a password can't be used from Weather Underground
+            (
+                vec![("ID", "KOSLO2"), ("PASSWORD", "anything"), ("tempf", "50")],
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                vec![("ID", "KUNKNOWN"), ("PASSWORD", "hunter2"), ("tempf", "50")],
+                StatusCode::FORBIDDEN,
+            ),
+            (vec![("PASSWORD", "hunter2")], StatusCode::BAD_REQUEST),
+        ] {
+            assert_eq!(wunderground(&state, &pairs).await.0, status, "{:?}", pairs);
+        }
+        assert_eq!(temperature(&state, "StationOslo").await, 0.0);
+
+        let accepted = wunderground(
+            &state,
+            &[("ID", "KOSLO1"), ("PASSWORD", "hunter2"), ("tempf", "50")],
+        )
+        .await;
+        assert_eq!(accepted, (StatusCode::OK, "success".to_string()));
+        assert_close(Some(temperature(&state, "StationOslo").await), 10.0);
+    }
+
+    #[tokio::test]
+    async fn ecowitt_uploads_are_only_accepted_without_a_password() {
+        let state = state(&[
+            ("ECO1", "StationBergen", None),
+            ("ECO2", "StationBergen", Some("hunter2")),
+        ])
+        .await;
+
+        assert_eq!(
+            ecowitt(&state, &[("PASSKEY", "ECO2"), ("tempf", "41")]).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            ecowitt(&state, &[("PASSKEY", "ECO9"), ("tempf", "41")]).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            ecowitt(&state, &[("tempf", "41")]).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(temperature(&state, "StationBergen").await, 0.0);
+
+        assert_eq!(
+            ecowitt(&state, &[("PASSKEY", "ECO1"), ("tempf", "41")]).await,
+            StatusCode::OK
+        );
+        assert_close(Some(temperature(&state, "StationBergen").await), 5.0);
+    }
+
+    #[tokio::test]
+    async fn readings_wait_for_the_location_lookup() {
+        let state = state(&[("ECO3", "StationPending", None)]).await;
+
+        let upload = [("PASSKEY", "ECO3"), ("tempf", "41")];
+        assert_eq!(ecowitt(&state, &upload).await, StatusCode::OK);
+        assert!(station_series("StationPending").is_empty());
+
+        state
+            .locations
+            .write()
+            .await
+            .get_mut("StationPending")
+            .unwrap()
+            .location = Some(place(58.969_975_5, 5.733_107));
+        assert_eq!(ecowitt(&state, &upload).await, StatusCode::OK);
+        let series = station_series("StationPending");
+        assert_eq!(series.len(), 1);
+        assert!(series[0].contains(&("latitude".to_string(), "58.97".to_string())));
+        assert!(series[0].contains(&("longitude".to_string(), "5.7331".to_string())));
+    }
+}
@@ -0,0 +1,166 @@
+//! Per-location fetch status and the HTML page on `/` and `/status` showing
+//! it.
+
+use crate::AppState;
+use axum::{extract::State, response::Html};
+use chrono::{DateTime, Utc};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// What happened on the last updates of a location
+#[derive(Debug, Default, Clone)]
+pub struct FetchStatus {
+    pub last_attempt: Option<DateTime<Utc>>,
+    pub last_success: Option<DateTime<Utc>>,
+    /// Error of the last attempt, cleared when one succeeds
+    pub last_error: Option<String>,
+    /// Updates that had to go to the provider
+    pub fetches: u64,
+    pub failures: u64,
+    /// Updates served from an unexpired cache
+    pub cache_hits: u64,
+    /// Earliest time the background task fetches again
+    pub next_refresh: Option<DateTime<Utc>>,
+}
+
+impl FetchStatus {
+    pub fn record_success(&mut self, now: DateTime<Utc>, expires: Option<DateTime<Utc>>) {
+        self.last_attempt = Some(now);
+        self.last_success = Some(now);
+        self.last_error = None;
+        self.fetches += 1;
+        self.next_refresh = expires;
+    }
+
+    /// A failed update is retried on the next check of the background task,
+    /// `update_interval` from now
+    pub fn record_failure(
+        &mut self,
+        now: DateTime<Utc>,
+        error: &anyhow::Error,
+        update_interval: Duration,
+    ) {
+        self.last_attempt = Some(now);
+        self.last_error = Some(format!("{:#}", error));
+        self.fetches += 1;
+        self.failures += 1;
+        self.next_refresh = Some(now + update_interval);
+    }
+
+    pub fn record_cache_hit(&mut self) {
+        self.cache_hits += 1;
+    }
+}
+
+pub async fn handler(State(state): State<AppState>) -> Html<String> {
+    let now = Utc::now();
+    let location_names = state.location_names().await;
+    let locations = state.locations.read().await;
+
+    let mut rows = String::new();
+    for location_name in &location_names {
+        let Some(data) = locations.get(location_name) else {
+            continue;
+        };
+        let status = &data.status;
+
+        let place = match &data.location {
+            Some(location) => format!(
+                "{}<br><small>{:.4}, {:.4}</small>",
+                escape(&location.describe()),
+                location.position.lat,
+                location.position.lon
+            ),
+            None => "<em>not resolved</em>".to_string(),
+        };
+        let result = match (&status.last_error, status.last_attempt) {
+            (Some(error), _) => format!("<span class=\"error\">{}</span>", escape(error)),
+            (None, Some(_)) => "<span class=\"ok\">OK</span>".to_string(),
+            (None, None) => "<em>pending</em>".to_string(),
+        };
+
+        let _ = writeln!(
+            rows,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>\
+             <td>{}</td><td>{}</td><td>{} / {} / {}</td><td>{}</td></tr>",
+            escape(location_name),
+            place,
+            result,
+            time(status.last_attempt, now),
+            time(status.last_success, now),
+            time(data.cache.expires, now),
+            data.cache
+                .last_modified
+                .as_deref()
+                .map(escape)
+                .unwrap_or_else(|| "-".to_string()),
+            status.fetches,
+            status.failures,
+            status.cache_hits,
+            time(status.next_refresh, now),
+        );
+    }
+
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Weather Exporter</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; }}
+th, td {{ border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }}
+th {{ background: #eee; }}
+.ok {{ color: #080; }}
+.error {{ color: #b00; }}
+</style>
+</head>
+<body>
+<h1>Weather Exporter</h1>
+<p>Version {} &middot; {} UTC &middot; <a href="/metrics">Metrics</a> &middot; <a href="/api/v1/locations">API</a></p>
+<table>
+<tr><th>Location</th><th>Place</th><th>Last result</th><th>Last fetch</th><th>Last success</th><th>Cache expires</th><th>Last-Modified</th><th>Fetches / failures / cache hits</th><th>Next refresh</th></tr>
+{}</table>
+</body>
+</html>
+"#,
+        env!("CARGO_PKG_VERSION"),
+        now.format("%Y-%m-%d %H:%M:%S"),
+        rows
+    ))
+}
+
+/// `2025-01-15 12:00:00 (5m ago)`
+fn time(time: Option<DateTime<Utc>>, now: DateTime<Utc>) -> String {
+    let Some(time) = time else {
+        return "-".to_string();
+    };
+    let seconds = (time - now).num_seconds();
+    let relative = match seconds.abs() {
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m", s / 60),
+        s => format!("{}h {}m", s / 3600, s % 3600 / 60),
+    };
+    let relative = if seconds <= 0 {
+        format!("{} ago", relative)
+    } else {
+        format!("in {}", relative)
+    };
+    format!("{} ({})", time.format("%Y-%m-%d %H:%M:%S"), relative)
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
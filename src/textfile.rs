@@ -0,0 +1,41 @@
+use crate::metrics;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+const FILE_NAME: &str = "weather_exporter.prom";
+
+/// Writes the registry for node_exporter's textfile collector
+pub struct TextfileWriter {
+    path: PathBuf,
+}
+
+impl TextfileWriter {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            path: directory.into().join(FILE_NAME),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write to a temporary file and rename it into place, so node_exporter
+    /// never reads a half-written file. The temporary name does not end in
+    /// `.prom`, which keeps the collector from picking it up.
+    pub async fn write(&self) -> Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+
+        tokio::fs::write(&tmp, metrics::gather_text())
+            .await
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        tokio::fs::rename(&tmp, &self.path)
+            .await
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+
+        debug!("Wrote metrics to {}", self.path.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+//! Offline coordinate to timezone lookup, used when the geocoding result does
+//! not include a timezone.
+
+use chrono_tz::Tz;
+use std::str::FromStr;
+
+// Matches further away than this are not trusted
+const MAX_DISTANCE_KM: f64 = 1000.0;
+
+// (latitude, longitude, IANA timezone) of reference cities
+const REFERENCE_POINTS: &[(f64, f64, &str)] = &[
+    // Europe
+    (59.91, 10.75, "Europe/Oslo"),
+    (60.39, 5.32, "Europe/Oslo"),
+    (63.43, 10.40, "Europe/Oslo"),
+    (69.65, 18.96, "Europe/Oslo"),
+    (59.33, 18.07, "Europe/Stockholm"),
+    (57.71, 11.97, "Europe/Stockholm"),
+    (55.60, 13.00, "Europe/Stockholm"),
+    (65.58, 22.15, "Europe/Stockholm"),
+    (55.68, 12.57, "Europe/Copenhagen"),
+    (56.16, 10.20, "Europe/Copenhagen"),
+    (60.17, 24.94, "Europe/Helsinki"),
+    (65.01, 25.47, "Europe/Helsinki"),
+    (64.15, -21.94, "Atlantic/Reykjavik"),
+    (51.51, -0.13, "Europe/London"),
+    (53.48, -2.24, "Europe/London"),
+    (55.95, -3.19, "Europe/London"),
+    (53.35, -6.26, "Europe/Dublin"),
+    (48.86, 2.35, "Europe/Paris"),
+    (43.30, 5.37, "Europe/Paris"),
+    (52.37, 4.90, "Europe/Amsterdam"),
+    (50.85, 4.35, "Europe/Brussels"),
+    (49.61, 6.13, "Europe/Luxembourg"),
+    (52.52, 13.40, "Europe/Berlin"),
+    (48.14, 11.58, "Europe/Berlin"),
+    (53.55, 9.99, "Europe/Berlin"),
+    (47.37, 8.54, "Europe/Zurich"),
+    (48.21, 16.37, "Europe/Vienna"),
+    (50.08, 14.44, "Europe/Prague"),
+    (52.23, 21.01, "Europe/Warsaw"),
+    (47.50, 19.04, "Europe/Budapest"),
+    (41.90, 12.50, "Europe/Rome"),
+    (45.46, 9.19, "Europe/Rome"),
+    (40.42, -3.70, "Europe/Madrid"),
+    (37.39, -5.98, "Europe/Madrid"),
+    (36.72, -4.42, "Europe/Madrid"),
+    (38.88, -6.97, "Europe/Madrid"),
+    (42.24, -8.72, "Europe/Madrid"),
+    (41.39, 2.17, "Europe/Madrid"),
+    (38.72, -9.14, "Europe/Lisbon"),
+    (41.15, -8.61, "Europe/Lisbon"),
+    (37.02, -7.93, "Europe/Lisbon"),
+    (37.98, 23.73, "Europe/Athens"),
+    (44.43, 26.10, "Europe/Bucharest"),
+    (42.70, 23.32, "Europe/Sofia"),
+    (44.79, 20.45, "Europe/Belgrade"),
+    (45.81, 15.98, "Europe/Zagreb"),
+    (59.44, 24.75, "Europe/Tallinn"),
+    (56.95, 24.11, "Europe/Riga"),
+    (54.69, 25.28, "Europe/Vilnius"),
+    (50.45, 30.52, "Europe/Kyiv"),
+    (53.90, 27.56, "Europe/Minsk"),
+    (55.76, 37.62, "Europe/Moscow"),
+    (59.94, 30.31, "Europe/Moscow"),
+    (41.01, 28.98, "Europe/Istanbul"),
+    // Africa and the Middle East
+    (30.04, 31.24, "Africa/Cairo"),
+    (6.52, 3.38, "Africa/Lagos"),
+    (-1.29, 36.82, "Africa/Nairobi"),
+    (-26.20, 28.05, "Africa/Johannesburg"),
+    (-33.92, 18.42, "Africa/Johannesburg"),
+    (33.57, -7.59, "Africa/Casablanca"),
+    (35.76, -5.83, "Africa/Casablanca"),
+    (34.03, -5.00, "Africa/Casablanca"),
+    (36.75, 3.06, "Africa/Algiers"),
+    (14.69, -17.44, "Africa/Dakar"),
+    (9.03, 38.74, "Africa/Addis_Ababa"),
+    (-4.32, 15.31, "Africa/Kinshasa"),
+    (31.77, 35.21, "Asia/Jerusalem"),
+    (24.71, 46.68, "Asia/Riyadh"),
+    (25.20, 55.27, "Asia/Dubai"),
+    (35.69, 51.39, "Asia/Tehran"),
+    // Asia
+    (24.86, 67.00, "Asia/Karachi"),
+    (28.61, 77.21, "Asia/Kolkata"),
+    (19.08, 72.88, "Asia/Kolkata"),
+    (12.97, 77.59, "Asia/Kolkata"),
+    (23.81, 90.41, "Asia/Dhaka"),
+    (27.72, 85.32, "Asia/Kathmandu"),
+    (28.05, 81.62, "Asia/Kathmandu"),
+    (26.45, 87.27, "Asia/Kathmandu"),
+    (26.85, 80.95, "Asia/Kolkata"),
+    (25.59, 85.14, "Asia/Kolkata"),
+    (32.73, 74.86, "Asia/Kolkata"),
+    (43.24, 76.89, "Asia/Almaty"),
+    (13.76, 100.50, "Asia/Bangkok"),
+    (21.03, 105.85, "Asia/Bangkok"),
+    (1.35, 103.82, "Asia/Singapore"),
+    (3.139, 101.687, "Asia/Kuala_Lumpur"),
+    (-6.21, 106.85, "Asia/Jakarta"),
+    (14.60, 120.98, "Asia/Manila"),
+    (22.32, 114.17, "Asia/Hong_Kong"),
+    (25.03, 121.57, "Asia/Taipei"),
+    (39.90, 116.41, "Asia/Shanghai"),
+    (31.23, 121.47, "Asia/Shanghai"),
+    (30.57, 104.07, "Asia/Shanghai"),
+    (43.83, 87.62, "Asia/Urumqi"),
+    (39.47, 75.99, "Asia/Urumqi"),
+    (37.11, 79.93, "Asia/Urumqi"),
+    (29.65, 91.17, "Asia/Shanghai"),
+    (37.57, 126.98, "Asia/Seoul"),
+    (35.68, 139.69, "Asia/Tokyo"),
+    (34.69, 135.50, "Asia/Tokyo"),
+    (43.06, 141.35, "Asia/Tokyo"),
+    (55.03, 82.92, "Asia/Novosibirsk"),
+    (56.84, 60.61, "Asia/Yekaterinburg"),
+    (52.29, 104.28, "Asia/Irkutsk"),
+    (43.12, 131.89, "Asia/Vladivostok"),
+    // Oceania
+    (-33.87, 151.21, "Australia/Sydney"),
+    (-37.81, 144.96, "Australia/Melbourne"),
+    (-27.47, 153.03, "Australia/Brisbane"),
+    (-34.93, 138.60, "Australia/Adelaide"),
+    (-32.49, 137.77, "Australia/Adelaide"),
+    (-32.13, 133.68, "Australia/Adelaide"),
+    (-31.95, 115.86, "Australia/Perth"),
+    (-30.75, 121.47, "Australia/Perth"),
+    (-33.86, 121.89, "Australia/Perth"),
+    (-17.96, 122.24, "Australia/Perth"),
+    (-12.46, 130.84, "Australia/Darwin"),
+    (-23.70, 133.88, "Australia/Darwin"),
+    (-36.85, 174.76, "Pacific/Auckland"),
+    (-41.29, 174.78, "Pacific/Auckland"),
+    (21.31, -157.86, "Pacific/Honolulu"),
+    // Americas
+    (61.22, -149.90, "America/Anchorage"),
+    (49.28, -123.12, "America/Vancouver"),
+    (47.61, -122.33, "America/Los_Angeles"),
+    (37.77, -122.42, "America/Los_Angeles"),
+    (34.05, -118.24, "America/Los_Angeles"),
+    (33.45, -112.07, "America/Phoenix"),
+    (39.74, -104.99, "America/Denver"),
+    (51.05, -114.07, "America/Edmonton"),
+    (29.76, -95.37, "America/Chicago"),
+    (41.88, -87.63, "America/Chicago"),
+    (44.98, -93.27, "America/Chicago"),
+    (49.90, -97.14, "America/Winnipeg"),
+    (19.43, -99.13, "America/Mexico_City"),
+    (33.75, -84.39, "America/New_York"),
+    (40.71, -74.01, "America/New_York"),
+    (42.36, -71.06, "America/New_York"),
+    (25.76, -80.19, "America/New_York"),
+    (43.65, -79.38, "America/Toronto"),
+    (45.50, -73.57, "America/Toronto"),
+    (44.65, -63.58, "America/Halifax"),
+    (47.56, -52.71, "America/St_Johns"),
+    (23.11, -82.37, "America/Havana"),
+    (9.93, -84.08, "America/Costa_Rica"),
+    (4.71, -74.07, "America/Bogota"),
+    (10.48, -66.90, "America/Caracas"),
+    (-12.05, -77.04, "America/Lima"),
+    (-16.50, -68.15, "America/La_Paz"),
+    (-33.45, -70.67, "America/Santiago"),
+    (-34.60, -58.38, "America/Argentina/Buenos_Aires"),
+    (-23.55, -46.63, "America/Sao_Paulo"),
+    (-22.91, -43.17, "America/Sao_Paulo"),
+    (-3.12, -60.02, "America/Manaus"),
+    (64.18, -51.72, "America/Nuuk"),
+];
+
+/// Great-circle distance in kilometers
+fn distance_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (lon2 - lon1).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    6371.0 * 2.0 * a.sqrt().asin()
+}
+
+/// Timezone of the nearest reference city, or a fixed-offset zone derived
+/// from the longitude when no city is close enough. Places close to a border
+/// can end up with the neighbour's zone, the config file can override it.
+pub fn lookup(lat: f64, lon: f64) -> Tz {
+    let nearest = REFERENCE_POINTS
+        .iter()
+        .map(|(ref_lat, ref_lon, tz)| (distance_km(lat, lon, *ref_lat, *ref_lon), tz))
+        .min_by(|a, b| a.0.total_cmp(&b.0));
+
+    if let Some((distance, tz)) = nearest
+        && distance <= MAX_DISTANCE_KM
+        && let Ok(tz) = Tz::from_str(tz)
+    {
+        return tz;
+    }
+
+    // Etc/GMT zones have inverted signs: Etc/GMT-9 is UTC+9
+    let offset = (lon / 15.0).round() as i32;
+    let name = match offset {
+        0 => "Etc/GMT".to_string(),
+        o if o > 0 => format!("Etc/GMT-{}", o),
+        o => format!("Etc/GMT+{}", -o),
+    };
+    Tz::from_str(&name).unwrap_or(Tz::UTC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Offset, TimeZone, Utc};
+
+    fn utc_offset_hours(tz: Tz) -> i32 {
+        let now = Utc.with_ymd_and_hms(2026, 1, 15, 12, 0, 0).unwrap();
+        tz.offset_from_utc_datetime(&now.naive_utc())
+            .fix()
+            .local_minus_utc()
+            / 3600
+    }
+
+    #[test]
+    fn places_near_borders() {
+        for (place, lat, lon, expected) in [
+            ("Kalgoorlie", -30.75, 121.47, Tz::Australia__Perth),
+            ("Esperance", -33.86, 121.89, Tz::Australia__Perth),
+            ("Port Augusta", -32.49, 137.77, Tz::Australia__Adelaide),
+            ("Coober Pedy", -29.01, 134.75, Tz::Australia__Adelaide),
+            ("Hotan", 37.11, 79.93, Tz::Asia__Urumqi),
+            ("Shigatse", 29.27, 88.88, Tz::Asia__Shanghai),
+            ("Pokhara", 28.21, 83.99, Tz::Asia__Kathmandu),
+            ("Srinagar", 34.08, 74.80, Tz::Asia__Kolkata),
+            ("Tetouan", 35.57, -5.37, Tz::Africa__Casablanca),
+            ("Fez", 34.03, -5.00, Tz::Africa__Casablanca),
+            ("Cordoba", 37.89, -4.78, Tz::Europe__Madrid),
+            ("Huelva", 37.26, -6.95, Tz::Europe__Madrid),
+            ("Braga", 41.55, -8.42, Tz::Europe__Lisbon),
+            ("Faro", 37.02, -7.93, Tz::Europe__Lisbon),
+        ] {
+            assert_eq!(lookup(lat, lon), expected, "{}", place);
+        }
+    }
+
+    #[test]
+    fn remote_places_get_a_fixed_offset() {
+        // Etc/GMT names have the sign inverted
+        for (lat, lon, name, hours) in [
+            (-40.0, -10.0, "Etc/GMT+1", -1),
+            (0.0, -150.0, "Etc/GMT+10", -10),
+            (-60.0, -180.0, "Etc/GMT+12", -12),
+            (-40.0, 80.0, "Etc/GMT-5", 5),
+            (-60.0, 180.0, "Etc/GMT-12", 12),
+            (-60.0, 7.0, "Etc/GMT", 0),
+        ] {
+            let tz = lookup(lat, lon);
+            assert_eq!(tz.name(), name, "{}, {}", lat, lon);
+            assert_eq!(utc_offset_hours(tz), hours, "{}", name);
+        }
+    }
+}
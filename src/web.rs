@@ -0,0 +1,728 @@
+//! `--web.config.file` in the format of the Prometheus exporter-toolkit: TLS
+//! with optional client certificates, bcrypt basic auth and extra response
+//! headers.
+
+use crate::station;
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    serve::Listener,
+};
+use base64::Engine;
+use ring::hmac;
+use ring::rand::SystemRandom;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::version::{TLS12, TLS13};
+use tokio_rustls::rustls::{self, RootCertStore, ServerConfig, SupportedProtocolVersion};
+use tracing::debug;
+
+/// Time a client gets to complete the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebConfig {
+    pub tls_server_config: Option<TlsServerConfig>,
+    #[serde(default)]
+    pub http_server_config: HttpServerConfig,
+    /// Usernames and their bcrypt password hashes
+    #[serde(default)]
+    pub basic_auth_users: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsServerConfig {
+    /// Certificate chain, as a PEM file or inline
+    cert_file: Option<PathBuf>,
+    cert: Option<String>,
+    /// Private key, as a PEM file or inline
+    key_file: Option<PathBuf>,
+    key: Option<String>,
+    #[serde(default)]
+    client_auth_type: ClientAuthType,
+    /// CAs client certificates are verified against
+    client_ca_file: Option<PathBuf>,
+    client_ca: Option<String>,
+    min_version: Option<TlsVersion>,
+    max_version: Option<TlsVersion>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpServerConfig {
+    /// Added to every response
+    #[serde(default)]
+    headers: HashMap<String, String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+pub enum ClientAuthType {
+    #[default]
+    NoClientCert,
+    RequestClientCert,
+    RequireAnyClientCert,
+    VerifyClientCertIfGiven,
+    RequireAndVerifyClientCert,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Deserialize)]
+pub enum TlsVersion {
+    TLS10,
+    TLS11,
+    TLS12,
+    TLS13,
+}
+
+impl WebConfig {
+    /// Load and validate a web config file
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: Self = serde_yaml::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        // Relative paths are relative to the file, as in exporter-toolkit
+        let base = path.parent().unwrap_or(Path::new("."));
+        let config = Self {
+            tls_server_config: config.tls_server_config.map(|tls| TlsServerConfig {
+                cert_file: tls.cert_file.map(|p| base.join(p)),
+                key_file: tls.key_file.map(|p| base.join(p)),
+                client_ca_file: tls.client_ca_file.map(|p| base.join(p)),
+                ..tls
+            }),
+            ..config
+        };
+
+        for (user, hash) in &config.basic_auth_users {
+            bcrypt::HashParts::from_str(hash)
+                .map_err(|e| anyhow::anyhow!("Invalid bcrypt hash for user {}: {}", user, e))?;
+        }
+        config.http_server_config.headers()?;
+        // Certificates are checked once here rather than on the first
+        // connection
+        config.tls_acceptor()?;
+        Ok(config)
+    }
+
+    pub fn tls_enabled(&self) -> bool {
+        self.tls_server_config.is_some()
+    }
+
+    pub fn tls_acceptor(&self) -> Result<Option<TlsAcceptor>> {
+        self.tls_server_config
+            .as_ref()
+            .map(|tls| tls.acceptor().context("Invalid tls_server_config"))
+            .transpose()
+    }
+
+    /// Settings for [`middleware`], `None` if there is nothing to do
+    pub fn http_settings(&self) -> Result<Option<HttpSettings>> {
+        let headers = self.http_server_config.headers()?;
+        if self.basic_auth_users.is_empty() && headers.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(HttpSettings {
+            users: self.basic_auth_users.clone(),
+            headers,
+            cache_key: hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+                .map_err(|_| anyhow::anyhow!("Failed to generate a credentials cache key"))?,
+            verified: Mutex::new(HashSet::new()),
+        }))
+    }
+}
+
+impl TlsServerConfig {
+    fn acceptor(&self) -> Result<TlsAcceptor> {
+        let certs: Vec<CertificateDer<'static>> = pem_certs(&self.cert_file, &self.cert, "cert")?;
+        let key = match (&self.key_file, &self.key) {
+            (Some(path), None) => PrivateKeyDer::from_pem_file(path)
+                .with_context(|| format!("Failed to load key from {}", path.display()))?,
+            (None, Some(pem)) => {
+                PrivateKeyDer::from_pem_slice(pem.as_bytes()).context("Failed to parse key")?
+            }
+            (Some(_), Some(_)) => return Err(anyhow::anyhow!("Set key_file or key, not both")),
+            (None, None) => return Err(anyhow::anyhow!("key_file is required")),
+        };
+
+        let min = self.min_version.unwrap_or(TlsVersion::TLS12);
+        let max = self.max_version.unwrap_or(TlsVersion::TLS13);
+        // TLS 1.0 and 1.1 are not implemented, a minimum below 1.2 still
+        // allows 1.2 and 1.3
+        let versions: Vec<&'static SupportedProtocolVersion> =
+            [(TlsVersion::TLS12, &TLS12), (TlsVersion::TLS13, &TLS13)]
+                .into_iter()
+                .filter(|(version, _)| (min..=max).contains(version))
+                .map(|(_, supported)| supported)
+                .collect();
+        if versions.is_empty() {
+            return Err(anyhow::anyhow!(
+                "No TLS version between {:?} and {:?} is supported, only TLS12 and TLS13",
+                min,
+                max
+            ));
+        }
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&versions)?;
+        let builder = match self.client_auth_type {
+            ClientAuthType::NoClientCert => builder.with_no_client_auth(),
+            ClientAuthType::VerifyClientCertIfGiven
+            | ClientAuthType::RequireAndVerifyClientCert => {
+                let mut roots = RootCertStore::empty();
+                for cert in pem_certs(&self.client_ca_file, &self.client_ca, "client_ca")? {
+                    roots.add(cert)?;
+                }
+                let mut verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+                if self.client_auth_type == ClientAuthType::VerifyClientCertIfGiven {
+                    verifier = verifier.allow_unauthenticated();
+                }
+                builder.with_client_cert_verifier(verifier.build()?)
+            }
+            // Accepting certificates without verifying them defeats the
+            // point of asking for them
+            ClientAuthType::RequestClientCert | ClientAuthType::RequireAnyClientCert => {
+                return Err(anyhow::anyhow!(
+                    "client_auth_type {:?} is not supported, use VerifyClientCertIfGiven or \
+                     RequireAndVerifyClientCert",
+                    self.client_auth_type
+                ));
+            }
+        };
+        if self.client_auth_type == ClientAuthType::NoClientCert
+            && (self.client_ca_file.is_some() || self.client_ca.is_some())
+        {
+            return Err(anyhow::anyhow!(
+                "client_ca_file needs client_auth_type RequireAndVerifyClientCert or \
+                 VerifyClientCertIfGiven"
+            ));
+        }
+
+        let mut config = builder
+            .with_single_cert(certs, key)
+            .context("Certificate and key don't match")?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// Certificates from a PEM file or inline PEM
+fn pem_certs(
+    path: &Option<PathBuf>,
+    pem: &Option<String>,
+    field: &str,
+) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = match (path, pem) {
+        (Some(path), None) => CertificateDer::pem_file_iter(path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .with_context(|| format!("Failed to load {} from {}", field, path.display()))?,
+        (None, Some(pem)) => CertificateDer::pem_slice_iter(pem.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Failed to parse {}", field))?,
+        (Some(_), Some(_)) => {
+            return Err(anyhow::anyhow!("Set {}_file or {}, not both", field, field));
+        }
+        (None, None) => return Err(anyhow::anyhow!("{}_file is required", field)),
+    };
+    if certs.is_empty() {
+        return Err(anyhow::anyhow!("No certificates in {}", field));
+    }
+    Ok(certs)
+}
+
+impl HttpServerConfig {
+    fn headers(&self) -> Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            headers.insert(
+                HeaderName::from_str(name)
+                    .with_context(|| format!("Invalid header name {}", name))?,
+                HeaderValue::from_str(value)
+                    .with_context(|| format!("Invalid value for header {}", name))?,
+            );
+        }
+        Ok(headers)
+    }
+}
+
+pub struct HttpSettings {
+    users: HashMap<String, String>,
+    headers: HeaderMap,
+    /// Key for [`Self::tag`], random per run
+    cache_key: hmac::Key,
+    /// Tags of credentials that matched, so bcrypt only runs once per user
+    /// and password without keeping passwords in memory
+    verified: Mutex<HashSet<Vec<u8>>>,
+}
+
+/// Require basic auth if users are configured and add the configured headers
+/// to every response. Station uploads are exempt, as consoles can't send
+/// basic auth and the uploads are checked against the station passwords.
+pub async fn middleware(
+    State(settings): State<Arc<HttpSettings>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let credentials = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| {
+            base64::engine::general_purpose::STANDARD
+                .decode(encoded)
+                .ok()
+        })
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .and_then(|decoded| {
+            decoded
+                .split_once(':')
+                .map(|(user, password)| (user.to_string(), password.to_string()))
+        });
+
+    let path = request.uri().path();
+    let exempt = path == station::ECOWITT_PATH || path == station::WUNDERGROUND_PATH;
+    let mut response = if settings.users.is_empty() || exempt {
+        next.run(request).await
+    } else {
+        let authenticated = match credentials {
+            Some((user, password)) => settings.authenticate(user, password).await,
+            None => false,
+        };
+        if authenticated {
+            next.run(request).await
+        } else {
+            (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Basic")],
+                "Unauthorized\n",
+            )
+                .into_response()
+        }
+    };
+
+    for (name, value) in &settings.headers {
+        response.headers_mut().insert(name, value.clone());
+    }
+    response
+}
+
+impl HttpSettings {
+    async fn authenticate(self: &Arc<Self>, user: String, password: String) -> bool {
+        let tag = self.tag(&user, &password);
+        if let Ok(verified) = self.verified.lock()
+            && verified.contains(&tag)
+        {
+            return true;
+        }
+
+        // Unknown users are checked against some hash as well, so response
+        // times don't reveal which users exist
+        let Some(hash) = self
+            .users
+            .get(&user)
+            .or_else(|| self.users.values().next())
+            .cloned()
+        else {
+            return false;
+        };
+        let known = self.users.contains_key(&user);
+        let settings = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let matches = bcrypt::verify(&password, &hash).unwrap_or(false) && known;
+            if matches && let Ok(mut verified) = settings.verified.lock() {
+                verified.insert(tag);
+            }
+            matches
+        })
+        .await
+        .unwrap_or(false)
+    }
+
+    /// HMAC of the credentials, with the user's length so no other user and
+    /// password pair produces the same input
+    fn tag(&self, user: &str, password: &str) -> Vec<u8> {
+        let mut context = hmac::Context::with_key(&self.cache_key);
+        context.update(&(user.len() as u64).to_be_bytes());
+        context.update(user.as_bytes());
+        context.update(password.as_bytes());
+        context.sign().as_ref().to_vec()
+    }
+}
+
+/// Accepts connections from `L` and completes their TLS handshake in the
+/// background, so a slow client doesn't hold up the others
+pub struct TlsListener<L: Listener> {
+    connections: mpsc::Receiver<(tokio_rustls::server::TlsStream<L::Io>, L::Addr)>,
+    local_addr: std::io::Result<L::Addr>,
+}
+
+impl<L: Listener> TlsListener<L>
+where
+    L::Addr: Clone + Sync + std::fmt::Debug + 'static,
+{
+    pub fn new(mut listener: L, acceptor: TlsAcceptor) -> Self {
+        let local_addr = listener.local_addr();
+        let (sender, connections) = mpsc::channel(64);
+        tokio::spawn(async move {
+            loop {
+                // Stops once the server drops the listener
+                let (io, addr) = tokio::select! {
+                    connection = listener.accept() => connection,
+                    _ = sender.closed() => return,
+                };
+                let acceptor = acceptor.clone();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(io)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send((stream, addr)).await;
+                        }
+                        Ok(Err(e)) => debug!("TLS handshake with {:?} failed: {}", addr, e),
+                        Err(_) => debug!("TLS handshake with {:?} timed out", addr),
+                    }
+                });
+            }
+        });
+        Self {
+            connections,
+            local_addr,
+        }
+    }
+}
+
+impl<L: Listener> Listener for TlsListener<L>
+where
+    L::Addr: Clone + Sync,
+{
+    type Io = tokio_rustls::server::TlsStream<L::Io>;
+    type Addr = L::Addr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.connections.recv().await {
+            Some(connection) => connection,
+            // The accept loop only ends when this listener is dropped
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        match &self.local_addr {
+            Ok(addr) => Ok(addr.clone()),
+            Err(e) => Err(std::io::Error::new(e.kind(), e.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Router,
+        routing::{get, post},
+    };
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::TlsConnector;
+    use tokio_rustls::rustls::ClientConfig;
+    use tokio_rustls::rustls::pki_types::ServerName;
+
+    /// Generated by scripts/generate-test-certs.sh
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/web")
+            .join(name)
+    }
+
+    /// Write `contents` to a config file of its own, with `{dir}` replaced by
+    /// the fixture directory
+    fn config_file(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("weather-web-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{}.yml", name));
+        let contents = contents.replace("{dir}", &fixture("").display().to_string());
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    async fn serve(config: &WebConfig) -> SocketAddr {
+        let mut app = Router::new()
+            .route("/metrics", get(|| async { "metrics" }))
+            .route("/admin/locations", post(|| async { "admin" }))
+            .route(station::ECOWITT_PATH, post(|| async { "ecowitt" }))
+            .route(station::WUNDERGROUND_PATH, get(|| async { "success" }));
+        if let Some(settings) = config.http_settings().unwrap() {
+            app = app.layer(axum::middleware::from_fn_with_state(
+                Arc::new(settings),
+                middleware,
+            ));
+        }
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = config.tls_acceptor().unwrap().unwrap();
+        tokio::spawn(async move {
+            axum::serve(TlsListener::new(listener, acceptor), app)
+                .await
+                .unwrap()
+        });
+        addr
+    }
+
+    /// A client trusting the test CA, presenting `{client}.crt` if given
+    fn connector(client: Option<&str>) -> TlsConnector {
+        let mut roots = RootCertStore::empty();
+        for cert in pem_certs(&Some(fixture("ca.crt")), &None, "ca").unwrap() {
+            roots.add(cert).unwrap();
+        }
+        let builder =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots);
+        let config = match client {
+            Some(name) => builder
+                .with_client_auth_cert(
+                    pem_certs(&Some(fixture(&format!("{}.crt", name))), &None, "cert").unwrap(),
+                    PrivateKeyDer::from_pem_file(fixture(&format!("{}.key", name))).unwrap(),
+                )
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+        TlsConnector::from(Arc::new(config))
+    }
+
+    /// Send a request over HTTP/1.1 and return the response head and body
+    /// with lowercase header names
+    async fn request(
+        addr: SocketAddr,
+        client: Option<&str>,
+        method: &str,
+        path: &str,
+        credentials: Option<(&str, &str)>,
+    ) -> std::io::Result<String> {
+        let tcp = TcpStream::connect(addr).await?;
+        let mut tls = connector(client)
+            .connect(ServerName::try_from("localhost").unwrap(), tcp)
+            .await?;
+
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 0\r\n",
+            method, path
+        );
+        if let Some((user, password)) = credentials {
+            let encoded =
+                base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password));
+            request.push_str(&format!("Authorization: Basic {}\r\n", encoded));
+        }
+        request.push_str("\r\n");
+        tls.write_all(request.as_bytes()).await?;
+
+        let mut response = Vec::new();
+        match tls.read_to_end(&mut response).await {
+            Ok(_) => {}
+            // Some servers close without close_notify after the response
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof && !response.is_empty() => {}
+            Err(e) => return Err(e),
+        }
+        Ok(String::from_utf8_lossy(&response).to_ascii_lowercase())
+    }
+
+    fn assert_refused(result: std::io::Result<String>) {
+        if let Ok(response) = result {
+            assert!(!response.starts_with("http/"), "{}", response);
+        }
+    }
+
+    #[test]
+    fn loads_paths_relative_to_the_file() {
+        let config = WebConfig::load(&fixture("web.yml")).unwrap();
+        assert!(config.tls_enabled());
+        assert_eq!(
+            config.tls_server_config.unwrap().cert_file,
+            Some(fixture("server.crt"))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        let cases = [
+            (
+                "unknown",
+                "tls_server_config:\n  cert_file: x\n  ciphers: []\n",
+                "ciphers",
+            ),
+            (
+                "any-client-cert",
+                "tls_server_config:\n  cert_file: {dir}/server.crt\n  key_file: {dir}/server.key\n  client_auth_type: RequireAnyClientCert\n",
+                "not supported",
+            ),
+            (
+                "ca-without-auth",
+                "tls_server_config:\n  cert_file: {dir}/server.crt\n  key_file: {dir}/server.key\n  client_ca_file: {dir}/ca.crt\n",
+                "client_ca_file needs",
+            ),
+            (
+                "mismatched-key",
+                "tls_server_config:\n  cert_file: {dir}/server.crt\n  key_file: {dir}/client.key\n",
+                "don't match",
+            ),
+            (
+                "tls11",
+                "tls_server_config:\n  cert_file: {dir}/server.crt\n  key_file: {dir}/server.key\n  max_version: TLS11\n",
+                "No TLS version",
+            ),
+            (
+                "plaintext-password",
+                "basic_auth_users:\n  prometheus: secret\n",
+                "Invalid bcrypt hash for user prometheus",
+            ),
+            (
+                "bad-header",
+                "http_server_config:\n  headers:\n    X-Bad: \"a\\nb\"\n",
+                "Invalid value for header X-Bad",
+            ),
+        ];
+        for (name, contents, expected) in cases {
+            let error = WebConfig::load(&config_file(name, contents)).unwrap_err();
+            let message = format!("{:#}", error);
+            assert!(message.contains(expected), "{}: {}", name, message);
+        }
+    }
+
+    #[tokio::test]
+    async fn requires_a_trusted_client_certificate() {
+        let addr = serve(&WebConfig::load(&fixture("web.yml")).unwrap()).await;
+        let credentials = Some(("prometheus", "secret"));
+
+        assert_refused(request(addr, None, "GET", "/metrics", credentials).await);
+        assert_refused(request(addr, Some("untrusted"), "GET", "/metrics", credentials).await);
+        let response = request(addr, Some("client"), "GET", "/metrics", credentials)
+            .await
+            .unwrap();
+        assert!(response.starts_with("http/1.1 200"), "{}", response);
+        assert!(response.ends_with("metrics"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn refuses_plain_http() {
+        let addr = serve(&WebConfig::load(&fixture("web.yml")).unwrap()).await;
+        let mut tcp = TcpStream::connect(addr).await.unwrap();
+        tcp.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = Vec::new();
+        let _ = tcp.read_to_end(&mut response).await;
+        assert!(!response.starts_with(b"HTTP/"));
+    }
+
+    #[tokio::test]
+    async fn requires_basic_auth_except_for_station_uploads() {
+        let addr = serve(&WebConfig::load(&fixture("web.yml")).unwrap()).await;
+        let client = Some("client");
+
+        for (method, path) in [("GET", "/metrics"), ("POST", "/admin/locations")] {
+            for credentials in [
+                None,
+                Some(("prometheus", "wrong")),
+                Some(("nobody", "secret")),
+            ] {
+                let response = request(addr, client, method, path, credentials)
+                    .await
+                    .unwrap();
+                assert!(response.starts_with("http/1.1 401"), "{}", response);
+                assert!(response.contains("www-authenticate: basic"), "{}", response);
+                assert!(
+                    response.contains("strict-transport-security"),
+                    "{}",
+                    response
+                );
+            }
+        }
+
+        // Twice, the second time from the cache
+        for _ in 0..2 {
+            let response = request(
+                addr,
+                client,
+                "POST",
+                "/admin/locations",
+                Some(("prometheus", "secret")),
+            )
+            .await
+            .unwrap();
+            assert!(response.starts_with("http/1.1 200"), "{}", response);
+            assert!(
+                response.contains("strict-transport-security: max-age=31536000"),
+                "{}",
+                response
+            );
+            assert!(response.ends_with("admin"), "{}", response);
+        }
+
+        // Station uploads have their own passwords
+        for (method, path) in [
+            ("POST", station::ECOWITT_PATH),
+            ("GET", station::WUNDERGROUND_PATH),
+        ] {
+            let response = request(addr, client, method, path, None).await.unwrap();
+            assert!(response.starts_with("http/1.1 200"), "{}", response);
+            assert!(
+                response.contains("strict-transport-security"),
+                "{}",
+                response
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn client_certificate_can_be_optional() {
+        let path = config_file(
+            "optional-client-cert",
+            "tls_server_config:\n  cert_file: {dir}/server.crt\n  key_file: {dir}/server.key\n  client_auth_type: VerifyClientCertIfGiven\n  client_ca_file: {dir}/ca.crt\n  min_version: TLS13\n",
+        );
+        let addr = serve(&WebConfig::load(&path).unwrap()).await;
+
+        for client in [None, Some("client")] {
+            let response = request(addr, client, "GET", "/metrics", None)
+                .await
+                .unwrap();
+            assert!(response.starts_with("http/1.1 200"), "{}", response);
+        }
+        assert_refused(request(addr, Some("untrusted"), "GET", "/metrics", None).await);
+    }
+
+    #[tokio::test]
+    async fn cached_credentials_are_not_kept_in_plain_text() {
+        let config = WebConfig::load(&fixture("web.yml")).unwrap();
+        let settings = Arc::new(config.http_settings().unwrap().unwrap());
+        assert!(
+            settings
+                .authenticate("prometheus".to_string(), "secret".to_string())
+                .await
+        );
+        assert!(
+            !settings
+                .authenticate("prometheus".to_string(), "secrets".to_string())
+                .await
+        );
+        let verified = settings.verified.lock().unwrap();
+        assert_eq!(verified.len(), 1);
+        assert!(verified.contains(&settings.tag("prometheus", "secret")));
+    }
+}